@@ -1,6 +1,8 @@
-use std::collections::HashMap;
-use crate::backend_api::{DocBackend, FrontendUpdate, Intent, Stroke};
-use automerge::{AutoCommit, ReadDoc, transaction::Transactable, ObjType, Value, ScalarValue, ROOT, sync::{self, SyncDoc}};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use rand::{distr::Alphanumeric, Rng};
+use crate::backend_api::{BackendError, BackendStats, Comment, CompactionReport, Conflict, DocBackend, FrontendUpdate, HistoryEntry, IncrementalSave, Intent, PeerPresence, Point, Presence, Selection, Shape, Stroke, StrokeChanges, UserInfo};
+use automerge::{ActorId, ObjId, AutoCommit, Patch, PatchAction, ChangeHash, AutomergeError, transaction::CommitOptions, ReadDoc, transaction::Transactable, ObjType, Value, ScalarValue, ROOT, sync::{self, SyncDoc}};
 
 /// Backend implementation using Automerge CRDT.
 ///
@@ -12,20 +14,667 @@ pub struct AutomergeBackend {
     doc: AutoCommit,
     /// Map of sync states for each connected peer.
     sync_states: HashMap<String, sync::State>,
+    /// Presence of the local user.
+    local_presence: Presence,
+    /// Latest presence of each connected peer.
+    peer_presence: HashMap<String, Presence>,
+    /// Receivers of updates caused by remote changes (see `subscribe`).
+    subscribers: Vec<UnboundedSender<FrontendUpdate>>,
+    /// Whether local changes are being grouped (see `begin_transaction`).
+    in_transaction: bool,
+    /// Heads of the document as last persisted (see `mark_saved`) or loaded.
+    saved_heads: Vec<ChangeHash>,
+    /// Descriptions of the changes grouped by the open transaction.
+    pending_messages: Vec<String>,
+    /// Identity of the local collaborator, embedded in the actor id.
+    identity: Option<String>,
+    /// Groups of local edits that `Intent::Undo` reverts, most recent last.
+    undo_stack: Vec<Vec<Edit>>,
+    /// Groups of undone edits that `Intent::Redo` re-applies, most recent last.
+    redo_stack: Vec<Vec<Edit>>,
+    /// Edits made in the open transaction; they become one undo step on commit.
+    transaction_edits: Vec<Edit>,
+    /// Page shown and edited locally: an element of the "pages" list, or
+    /// `None` for the first page, whose content lives in the root map.
+    current_page: Option<ObjId>,
+    /// One entry per element of the current page's "strokes" list (`None` for
+    /// elements that are not strokes), updated from Automerge patches by `sync_stroke_cache`.
+    stroke_cache: Vec<(ObjId, Option<Stroke>)>,
+    /// The "strokes" list that `stroke_cache` mirrors.
+    stroke_cache_list: Option<ObjId>,
+    /// Whether the document changed since `stroke_cache` was last brought up to date.
+    stroke_cache_stale: bool,
+    /// Changes made to `stroke_cache` since the last `FrontendUpdate`, or
+    /// `None` if it was read again from scratch.
+    stroke_changes: Option<StrokeChanges>,
+    /// Whether local edits are rejected (see `DocBackend::set_read_only`).
+    read_only: bool,
+}
+
+/// Root fields checked for conflicts; the rest (e.g. "modified_at") are
+/// bookkeeping whose concurrent values nobody needs to choose between.
+const CONFLICT_KEYS: &[&str] = &["title"];
+
+/// Size of the pieces a background image is split into (see `set_background`).
+const BACKGROUND_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A reversible local edit, kept on the undo/redo stacks.
+///
+/// Strokes are tracked by their list element id, so undo only touches the
+/// strokes this replica added or removed, never concurrent remote ones. The
+/// first field is the "strokes" list of the page the edit was made on.
+#[derive(Clone)]
+enum Edit {
+    /// Strokes inserted into a "strokes" list, with their element ids.
+    InsertedStrokes(ObjId, Vec<(ObjId, Stroke)>),
+    /// Strokes removed from a "strokes" list, with their former index and
+    /// element id, by ascending index.
+    RemovedStrokes(ObjId, Vec<(usize, ObjId, Stroke)>),
+    /// A comment entry changed from `before` to `after` (`None` when absent).
+    Comment {
+        id: String,
+        before: Option<String>,
+        after: Option<String>,
+    },
 }
 
 impl AutomergeBackend {
     /// Creates a new, empty AutomergeBackend.
     ///
-    /// Initializes the document with a "strokes" list.
+    /// Initializes the document with a "strokes" list and a "comments" map.
     pub fn new() -> Self {
         Self { 
-            doc: AutoCommit::new(),
+            doc: Self::skeleton(),
             sync_states: HashMap::new(),
+            local_presence: Presence::default(),
+            peer_presence: HashMap::new(),
+            subscribers: Vec::new(),
+            in_transaction: false,
+            saved_heads: Vec::new(),
+            pending_messages: Vec::new(),
+            identity: None,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            transaction_edits: Vec::new(),
+            current_page: None,
+            stroke_cache: Vec::new(),
+            stroke_cache_list: None,
+            stroke_cache_stale: true,
+            stroke_changes: None,
+            read_only: false,
+        }
+    }
+
+    /// Builds a new document containing the shared top-level objects.
+    ///
+    /// The objects are created in one change by a fixed actor at a fixed time,
+    /// so every peer produces the byte-identical change. Automerge deduplicates
+    /// it on sync instead of seeing two concurrently created "strokes" lists, of
+    /// which only one would win (dropping the other peer's strokes). Later
+    /// changes use a random actor as usual.
+    ///
+    /// The change must stay exactly as it is: documents created by versions
+    /// with another skeleton would no longer share it.
+    fn skeleton() -> AutoCommit {
+        let mut doc = AutoCommit::new().with_actor(ActorId::from([0u8; 16].as_slice()));
+        doc.put_object(ROOT, "strokes", ObjType::List)
+            .expect("putting into a fresh document cannot fail");
+        doc.put_object(ROOT, "comments", ObjType::Map)
+            .expect("putting into a fresh document cannot fail");
+        doc.commit_with(CommitOptions::default().with_time(0));
+        doc.set_actor(ActorId::random());
+        doc
+    }
+
+    /// Records the current time as the document's "modified_at" timestamp.
+    /// Called after every local change so the value syncs along with it.
+    /// Also closes the change with `message`, unless a transaction is grouping changes.
+    fn touch(&mut self, message: &str) -> Result<(), BackendError> {
+        self.doc.put(ROOT, "modified_at", ScalarValue::Timestamp(now_millis()))?;
+        if self.in_transaction {
+            self.pending_messages.push(message.to_string());
+        } else {
+            self.commit_change(message.to_string());
+        }
+        Ok(())
+    }
+
+    /// String stored under `key` in the root map, if any.
+    fn root_string(&self, key: &str) -> Option<String> {
+        match self.doc.get(ROOT, key) {
+            Ok(Some((Value::Scalar(s), _))) => match s.as_ref() {
+                ScalarValue::Str(t) => Some(t.to_string()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Closes the pending change, recording a description and the current time.
+    fn commit_change(&mut self, message: String) {
+        self.doc.commit_with(CommitOptions::default().with_message(message).with_time(now_millis()));
+    }
+
+    /// Builds an update reflecting the current document state, with the
+    /// stroke changes since the previous one.
+    fn frontend_update(&mut self) -> FrontendUpdate {
+        let stroke_changes = if self.stroke_cache_stale {
+            None
+        } else {
+            self.stroke_changes.replace(StrokeChanges::default())
+        };
+        FrontendUpdate {
+            strokes: self.get_strokes(),
+            comments: self.get_comments(),
+            conflicts: self.get_conflicts(),
+            stroke_changes,
+        }
+    }
+
+    /// Returns the current page's "strokes" list, creating it if missing.
+    fn strokes_list(&mut self) -> Result<ObjId, BackendError> {
+        let page = self.page_object();
+        match self.doc.get(&page, "strokes") {
+            Ok(Some((Value::Object(ObjType::List), id))) => Ok(id),
+            _ => Ok(self.doc.put_object(&page, "strokes", ObjType::List)?),
+        }
+    }
+
+    /// Returns the current page's "strokes" list, if it exists.
+    fn page_strokes_list(&self) -> Option<ObjId> {
+        match self.doc.get(self.page_object(), "strokes") {
+            Ok(Some((Value::Object(ObjType::List), id))) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Returns every "pages" list. The list is created with the first added
+    /// page, so peers adding pages before they first sync create one each;
+    /// the pages of all of them are kept.
+    fn pages_lists(&self) -> Vec<ObjId> {
+        self.doc
+            .get_all(ROOT, "pages")
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(val, id)| matches!(val, Value::Object(ObjType::List)).then_some(id))
+            .collect()
+    }
+
+    /// Returns the object holding each page's content, in page order: the
+    /// root map for the first page, then the maps in the "pages" lists.
+    ///
+    /// Keeping the first page in the root map means documents written before
+    /// pages existed open as single-page documents.
+    fn page_objects(&self) -> Vec<ObjId> {
+        let mut pages = vec![ROOT];
+        for list_id in self.pages_lists() {
+            pages.extend(self.doc.values(&list_id).filter_map(|(val, id)| {
+                matches!(val, Value::Object(ObjType::Map)).then_some(id)
+            }));
+        }
+        pages
+    }
+
+    /// Returns the object holding the current page's content. Falls back to the
+    /// first page if the current one has been deleted (e.g. by a peer).
+    fn page_object(&self) -> ObjId {
+        match &self.current_page {
+            Some(page) if self.page_objects().contains(page) => page.clone(),
+            _ => ROOT,
+        }
+    }
+
+    /// Makes the page at `index` current, or returns `None` if there is no such page.
+    fn switch_page(&mut self, index: usize) -> Option<()> {
+        let page = self.page_objects().into_iter().nth(index)?;
+        self.current_page = (page != ROOT).then_some(page);
+        Some(())
+    }
+
+    /// Returns the raw JSON stored for a comment, if any.
+    fn comment_json(&self, id: &str) -> Option<String> {
+        let map_id = match self.doc.get(ROOT, "comments") {
+            Ok(Some((Value::Object(ObjType::Map), map_id))) => map_id,
+            _ => return None,
+        };
+        match self.doc.get(&map_id, id) {
+            Ok(Some((Value::Scalar(s), _))) => match s.as_ref() {
+                ScalarValue::Str(json) => Some(json.to_string()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Records a local edit as part of the current undo step.
+    /// Any new edit discards the redo history.
+    fn record(&mut self, edit: Edit) {
+        self.redo_stack.clear();
+        if self.in_transaction {
+            self.transaction_edits.push(edit);
+        } else {
+            self.undo_stack.push(vec![edit]);
+        }
+    }
+
+    /// Points history entries at a stroke's new element id after it was re-inserted.
+    fn remap_stroke(&mut self, old: &ObjId, new: &ObjId) {
+        let edits = self.undo_stack.iter_mut().chain(self.redo_stack.iter_mut()).flatten()
+            .chain(self.transaction_edits.iter_mut());
+        for edit in edits {
+            match edit {
+                Edit::InsertedStrokes(_, items) => {
+                    for (elem, _) in items.iter_mut().filter(|(elem, _)| elem == old) {
+                        *elem = new.clone();
+                    }
+                }
+                Edit::RemovedStrokes(_, items) => {
+                    for (_, elem, _) in items.iter_mut().filter(|(_, elem, _)| elem == old) {
+                        *elem = new.clone();
+                    }
+                }
+                Edit::Comment { .. } => {}
+            }
+        }
+    }
+
+    /// Reverts a group of edits (newest first) and returns the group that re-applies them.
+    ///
+    /// Reverting is all or nothing: if an edit fails, the edits already
+    /// reverted are rolled back. Must not be called during a transaction,
+    /// whose earlier changes the rollback would discard too.
+    fn revert_group(&mut self, group: Vec<Edit>) -> Result<Vec<Edit>, BackendError> {
+        let mut reverted = Vec::new();
+        let mut reinserted = Vec::new();
+        for edit in group.into_iter().rev() {
+            match self.revert(edit, &mut reinserted) {
+                Ok(Some(edit)) => reverted.push(edit),
+                Ok(None) => {}
+                Err(e) => {
+                    self.doc.rollback();
+                    return Err(e);
+                }
+            }
+        }
+        for (old, new) in reinserted {
+            self.remap_stroke(&old, &new);
+        }
+        Ok(reverted)
+    }
+
+    /// Reverts one edit and returns the edit that reverts it back, or `None`
+    /// if a peer has changed what it touched since. The element ids of
+    /// re-inserted strokes are added to `reinserted` as (old, new) pairs.
+    fn revert(&mut self, edit: Edit, reinserted: &mut Vec<(ObjId, ObjId)>) -> Result<Option<Edit>, BackendError> {
+        match edit {
+            Edit::InsertedStrokes(list_id, items) => {
+                // Strokes already removed (e.g. by a peer's clear) are skipped.
+                let mut removed = Vec::new();
+                for (elem, stroke) in items {
+                    if let Some(index) = self.doc.values(&list_id).position(|(_, id)| id == elem) {
+                        removed.push((index, elem, stroke));
+                    }
+                }
+                removed.sort_by_key(|(index, _, _)| *index);
+                for (index, _, _) in removed.iter().rev() {
+                    self.doc.delete(&list_id, *index)?;
+                }
+                Ok(Some(Edit::RemovedStrokes(list_id, removed)))
+            }
+            Edit::RemovedStrokes(list_id, items) => {
+                let mut inserted = Vec::new();
+                for (index, old_elem, stroke) in items {
+                    let index = index.min(self.doc.length(&list_id));
+                    let elem = self.insert_stroke(&list_id, index, &stroke)?;
+                    reinserted.push((old_elem, elem.clone()));
+                    inserted.push((elem, stroke));
+                }
+                Ok(Some(Edit::InsertedStrokes(list_id, inserted)))
+            }
+            Edit::Comment { id, before, after } => {
+                // A peer edited, resolved or deleted the comment since; keep their change.
+                if self.comment_json(&id) != after {
+                    return Ok(None);
+                }
+                let map_id = self.comments_map()?;
+                match &before {
+                    Some(json) => self.doc.put(&map_id, id.as_str(), ScalarValue::Str(json.clone().into()))?,
+                    None => self.doc.delete(&map_id, id.as_str())?,
+                }
+                Ok(Some(Edit::Comment { id, before: after, after: before }))
+            }
+        }
+    }
+
+    /// Appends `stroke` to the current page, giving it a random id unless it has one.
+    fn draw_stroke(&mut self, mut stroke: Stroke) -> Result<(), BackendError> {
+        if stroke.id.is_empty() {
+            stroke.id = random_id();
+        }
+        // Recreated if missing
+        let list_id = self.strokes_list()?;
+
+        let len = self.doc.length(&list_id);
+        let elem = self.insert_stroke(&list_id, len, &stroke)?;
+        self.record(Edit::InsertedStrokes(list_id, vec![(elem, stroke)]));
+        Ok(())
+    }
+
+    /// Writes `stroke` into the "strokes" list at `index` and returns its element id.
+    ///
+    /// Each stroke is a map of native values, with the points flattened into
+    /// one list of integers (x0, y0, x1, y1, ...), so a stroke can be extended
+    /// in place and syncs as compact columns rather than an opaque string.
+    /// Shapes add a "shape" kind ("rectangle", "ellipse", "line" or "text")
+    /// and, for text labels, the "text" itself.
+    fn insert_stroke(&mut self, list_id: &ObjId, index: usize, stroke: &Stroke) -> Result<ObjId, BackendError> {
+        let map = self.doc.insert_object(list_id, index, ObjType::Map)?;
+        self.doc.put(&map, "id", stroke.id.as_str())?;
+        let color = self.doc.put_object(&map, "color", ObjType::List)?;
+        self.doc.splice(&color, 0, 0, stroke.color.iter().map(|c| ScalarValue::Uint(*c as u64)))?;
+        self.doc.put(&map, "width", stroke.width as f64)?;
+        let points = self.doc.put_object(&map, "points", ObjType::List)?;
+        let coords = stroke.points.iter().flat_map(|p| [ScalarValue::Int(p.x as i64), ScalarValue::Int(p.y as i64)]);
+        self.doc.splice(&points, 0, 0, coords)?;
+        if let Some(shape) = &stroke.shape {
+            let kind = match shape {
+                Shape::Rectangle => "rectangle",
+                Shape::Ellipse => "ellipse",
+                Shape::Line => "line",
+                Shape::Text(text) => {
+                    self.doc.put(&map, "text", text.as_str())?;
+                    "text"
+                }
+            };
+            self.doc.put(&map, "shape", kind)?;
+        }
+        Ok(map)
+    }
+
+    /// Reads the stroke stored in element `elem` of the "strokes" list: a map
+    /// (see `insert_stroke`), or a JSON string in documents written by older versions.
+    fn read_stroke(&self, value: Value, elem: &ObjId) -> Option<Stroke> {
+        let stroke = match value {
+            Value::Object(ObjType::Map) => {
+                let list = |key: &str| match self.doc.get(elem, key) {
+                    Ok(Some((Value::Object(ObjType::List), id))) => self.doc.values(&id)
+                        .filter_map(|(val, _)| match val {
+                            Value::Scalar(s) => match s.as_ref() {
+                                ScalarValue::Int(n) => Some(*n),
+                                ScalarValue::Uint(n) => Some(*n as i64),
+                                _ => None,
+                            },
+                            _ => None,
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                let string = |key: &str| match self.doc.get(elem, key) {
+                    Ok(Some((Value::Scalar(s), _))) => match s.as_ref() {
+                        ScalarValue::Str(text) => Some(text.to_string()),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let color = list("color");
+                Stroke {
+                    points: list("points").chunks_exact(2).map(|xy| Point { x: xy[0] as i32, y: xy[1] as i32 }).collect(),
+                    color: [0, 1, 2, 3].map(|i| color.get(i).map_or(255, |c| *c as u8)),
+                    width: match self.doc.get(elem, "width") {
+                        Ok(Some((Value::Scalar(s), _))) => match s.as_ref() {
+                            ScalarValue::F64(w) => *w as f32,
+                            _ => 1.0,
+                        },
+                        _ => 1.0,
+                    },
+                    id: string("id").unwrap_or_default(),
+                    // Kinds added by newer versions are shown as a plain line.
+                    shape: match string("shape").as_deref() {
+                        Some("rectangle") => Some(Shape::Rectangle),
+                        Some("ellipse") => Some(Shape::Ellipse),
+                        Some("line") => Some(Shape::Line),
+                        Some("text") => Some(Shape::Text(string("text").unwrap_or_default())),
+                        _ => None,
+                    },
+                }
+            }
+            Value::Scalar(s) => match s.as_ref() {
+                ScalarValue::Str(json) => serde_json::from_str(json).ok()?,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(with_stroke_id(stroke, elem))
+    }
+
+    /// Reads every element of the current page's "strokes" list.
+    fn read_stroke_list(&self) -> Vec<(ObjId, Option<Stroke>)> {
+        match self.page_strokes_list() {
+            Some(list_id) => self.doc.values(&list_id)
+                .map(|(val, elem)| {
+                    let stroke = self.read_stroke(val, &elem);
+                    (elem, stroke)
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Brings `stroke_cache` up to date with all changes since the last call,
+    /// reading again only the strokes that Automerge's patches mention. Inside
+    /// a transaction the cache is just marked stale, as patches are only
+    /// produced for committed changes.
+    fn sync_stroke_cache(&mut self) {
+        if self.in_transaction {
+            self.stroke_cache_stale = true;
+            return;
+        }
+        let list_id = self.page_strokes_list();
+        if list_id != self.stroke_cache_list {
+            // Another page is shown, or its list was replaced: start over from it.
+            self.doc.update_diff_cursor();
+            self.stroke_cache = self.read_stroke_list();
+            self.stroke_cache_list = list_id;
+            self.stroke_cache_stale = false;
+            self.stroke_changes = None;
+            return;
+        }
+        if self.doc.diff_cursor().is_empty() {
+            // First diff of this document: the patches describe all of its content.
+            self.stroke_cache.clear();
+            self.stroke_changes = None;
+        }
+        let patches = self.doc.diff_incremental();
+        match self.apply_stroke_patches(patches) {
+            Some(changes) => {
+                if let Some(all) = &mut self.stroke_changes {
+                    all.added.extend(changes.added);
+                    all.removed.extend(changes.removed);
+                    all.changed.extend(changes.changed);
+                }
+            }
+            None => {
+                self.stroke_cache = self.read_stroke_list();
+                self.stroke_changes = None;
+            }
+        }
+        self.stroke_cache_stale = false;
+    }
+
+    /// Applies the patches touching the "strokes" list in `stroke_cache_list`
+    /// to `stroke_cache`, returning what they changed. Returns `None` if they
+    /// do not line up with the cache.
+    fn apply_stroke_patches(&mut self, patches: Vec<Patch>) -> Option<StrokeChanges> {
+        let Some(list_id) = self.stroke_cache_list.clone() else {
+            self.stroke_cache.clear();
+            return Some(StrokeChanges::default());
+        };
+        let mut changes = StrokeChanges::default();
+        let mut inserted = HashSet::new();
+        let mut touched = HashSet::new();
+        for patch in patches {
+            if patch.obj == list_id {
+                match patch.action {
+                    PatchAction::Insert { index, values } => {
+                        if index > self.stroke_cache.len() {
+                            return None;
+                        }
+                        let entries: Vec<_> = values.iter()
+                            .map(|(value, elem, _)| (elem.clone(), self.read_stroke(value.clone(), elem)))
+                            .collect();
+                        inserted.extend(entries.iter().map(|(elem, _)| elem.clone()));
+                        changes.added.extend(entries.iter().filter_map(|(_, stroke)| stroke.clone()));
+                        self.stroke_cache.splice(index..index, entries);
+                    }
+                    PatchAction::DeleteSeq { index, length } => {
+                        if index + length > self.stroke_cache.len() {
+                            return None;
+                        }
+                        let removed = self.stroke_cache.drain(index..index + length);
+                        changes.removed.extend(removed.filter_map(|(_, stroke)| stroke.map(|s| s.id)));
+                    }
+                    PatchAction::PutSeq { index, value: (value, elem), .. } => {
+                        let stroke = self.read_stroke(value, &elem);
+                        let entry = self.stroke_cache.get_mut(index)?;
+                        changes.removed.extend(entry.1.take().map(|s| s.id));
+                        changes.added.extend(stroke.clone());
+                        *entry = (elem, stroke);
+                    }
+                    _ => {}
+                }
+            } else if let Some(pos) = patch.path.iter().position(|(obj, _)| *obj == list_id) {
+                // A change inside a stroke, e.g. points appended to it.
+                let elem = patch.path.get(pos + 1).map_or(&patch.obj, |(obj, _)| obj);
+                if !inserted.contains(elem) {
+                    touched.insert(elem.clone());
+                }
+            }
+        }
+        for elem in touched {
+            let stroke = self.read_stroke(Value::Object(ObjType::Map), &elem);
+            if let Some(entry) = self.stroke_cache.iter_mut().find(|(id, _)| *id == elem) {
+                changes.changed.extend(stroke.clone());
+                entry.1 = stroke;
+            }
+        }
+        Some(changes)
+    }
+
+    /// Returns the "comments" map, creating it if missing.
+    fn comments_map(&mut self) -> Result<ObjId, BackendError> {
+        match self.doc.get(ROOT, "comments") {
+            Ok(Some((Value::Object(ObjType::Map), id))) => Ok(id),
+            _ => Ok(self.doc.put_object(ROOT, "comments", ObjType::Map)?),
+        }
+    }
+
+    /// Stores a comment as JSON under its id in the "comments" map.
+    /// Returns the stored JSON.
+    fn put_comment(&mut self, comment: &Comment) -> Result<String, BackendError> {
+        let json = serde_json::to_string(comment)
+            .map_err(|e| BackendError::Serialization(e.to_string()))?;
+        let map_id = self.comments_map()?;
+        self.doc.put(&map_id, comment.id.as_str(), ScalarValue::Str(json.clone().into()))?;
+        Ok(json)
+    }
+
+    /// Pushes an update to every live subscriber, dropping closed ones.
+    fn notify_subscribers(&mut self, update: &FrontendUpdate) {
+        self.subscribers.retain(|tx| tx.send(update.clone()).is_ok());
+    }
+}
+
+/// Builds an actor id carrying `identity`: its UTF-8 bytes, a zero byte and
+/// random bytes. The random part keeps the id unique when the same person
+/// edits from several sessions, which Automerge requires.
+fn actor_for_identity(identity: &str) -> ActorId {
+    let mut bytes = identity.as_bytes().to_vec();
+    bytes.push(0);
+    bytes.extend(rand::random::<[u8; 8]>());
+    ActorId::from(bytes)
+}
+
+/// Recovers the identity from an actor id built by `actor_for_identity`,
+/// falling back to the hex form for other actors.
+fn identity_of_actor(actor: &ActorId) -> String {
+    let bytes = actor.to_bytes();
+    match bytes.iter().position(|&b| b == 0) {
+        Some(end) if end > 0 && bytes.len() == end + 9 => match std::str::from_utf8(&bytes[..end]) {
+            Ok(identity) => identity.to_string(),
+            Err(_) => actor.to_hex_string(),
+        },
+        _ => actor.to_hex_string(),
+    }
+}
+
+/// Recreates the content of object `src` of `from` inside object `dst` of `to`,
+/// without any of its history. Objects already present in `dst` under the
+/// same key and of the same type are filled in rather than replaced.
+fn copy_object(from: &AutoCommit, src: &ObjId, to: &mut AutoCommit, dst: &ObjId) -> Result<(), AutomergeError> {
+    match from.object_type(src)? {
+        ObjType::Map | ObjType::Table => {
+            for key in from.keys(src) {
+                match from.get(src, &key)? {
+                    Some((Value::Scalar(value), _)) => to.put(dst, &key, value.into_owned())?,
+                    Some((Value::Object(obj_type), child)) => {
+                        let target = match to.get(dst, &key)? {
+                            Some((Value::Object(existing), id)) if existing == obj_type => id,
+                            _ => to.put_object(dst, &key, obj_type)?,
+                        };
+                        copy_object(from, &child, to, &target)?;
+                    }
+                    None => {}
+                }
+            }
+        }
+        ObjType::List => {
+            for (index, (value, child)) in from.values(src).enumerate() {
+                match value {
+                    Value::Scalar(value) => to.insert(dst, index, value.into_owned())?,
+                    Value::Object(obj_type) => {
+                        let target = to.insert_object(dst, index, obj_type)?;
+                        copy_object(from, &child, to, &target)?;
+                    }
+                }
+            }
         }
+        ObjType::Text => to.splice_text(dst, 0, 0, &from.text(src)?)?,
+    }
+    Ok(())
+}
+
+/// Generates an identifier for a new stroke or comment.
+fn random_id() -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(12)
+        .map(char::from)
+        .collect()
+}
+
+/// 64-bit FNV-1a hash, used to check that a chunked background is complete.
+/// Stable across platforms and versions, unlike `std`'s `DefaultHasher`.
+fn content_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+/// Gives a stroke stored without an id (by older versions) its list element id.
+fn with_stroke_id(stroke: Stroke, elem: &ObjId) -> Stroke {
+    if stroke.id.is_empty() {
+        Stroke { id: elem.to_string(), ..stroke }
+    } else {
+        stroke
     }
 }
 
+/// Current time in milliseconds since the Unix epoch.
+fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
 /// Provides a default way to create a new instance of `AutomergeBackend` by calling its `new` method.
 /// 
 /// This implementation allows `AutomergeBackend` to be used with constructs that require the `Default` trait,
@@ -36,6 +685,13 @@ impl Default for AutomergeBackend {
     }
 }
 
+/// Maps Automerge engine failures onto the backend-agnostic error type.
+impl From<AutomergeError> for BackendError {
+    fn from(e: AutomergeError) -> Self {
+        BackendError::Crdt(e.to_string())
+    }
+}
+
 /// Implementation of the `DocBackend` trait for `AutomergeBackend`.
 ///
 /// This backend uses [Automerge](https://automerge.org/) for real-time collaborative editing.
@@ -45,26 +701,70 @@ impl Default for AutomergeBackend {
 /// # Methods
 ///
 /// - `apply_intent`: Applies a user intent (drawing a stroke or clearing the canvas) to the document.
-///   - For `Intent::Draw`, serializes the stroke to JSON and inserts it into the "strokes" list.
+///   - For `Intent::Draw`, inserts the stroke into the "strokes" list as a map of native values,
+///     giving it a random id unless the caller chose one.
 ///   - For `Intent::Clear`, removes all strokes from the "strokes" list.
+///   - For `Intent::EraseStroke`, removes the first stroke with the given id.
+///   - For `Intent::DrawShape`, inserts a stroke holding the two corners and the kind of shape.
+///   - `Intent::Undo` / `Intent::Redo` revert / re-apply the latest local intent (or transaction)
+///     from an undo stack of reversible edits. Strokes are tracked by list element id, so strokes
+///     drawn concurrently by peers are left alone. Title and background changes are not undoable.
+///   - `Intent::AddComment` / `Intent::ReplyComment` / `Intent::ResolveComment` write
+///     JSON-encoded comments into the "comments" map, keyed by comment id. A reply is a
+///     comment of its own that names the first comment of its thread.
+///   - `Intent::AddPage` / `Intent::DeletePage` insert / delete a map (with its own "strokes"
+///     list and "background") in the "pages" list. The first page is the root map itself.
+///     `Intent::SwitchPage` only changes which page the other intents act on.
 ///   - Ensures the "strokes" list exists, creating it if necessary.
 ///   - Returns a `FrontendUpdate` containing the current strokes.
 ///
 /// - `get_strokes`: Retrieves all strokes from the document.
-///   - Iterates over the "strokes" list, deserializing each JSON string into a `Stroke`.
+///   - Iterates over the "strokes" list, reading each map (or, in older documents, JSON string)
+///     into a `Stroke`. Strokes stored without an id get their list element id (the same on every peer).
+///   - Served from a cache that is updated from Automerge's patches after every change, so a
+///     sync message only costs re-reading the strokes it touched. The strokes the patches
+///     added, removed or changed are passed on in `FrontendUpdate::stroke_changes`.
 ///   - Returns a vector of strokes.
 ///
+/// - `get_comments`: Retrieves all comments from the "comments" map, oldest first.
+///
+/// - `page_count` / `current_page`: Count the root map plus the maps in the "pages" list, and
+///   locate the current page among them by object id, so it stays current when peers add or
+///   delete other pages.
+///
+/// - `begin_transaction` / `commit`: Group local changes into one Automerge change.
+///   Outside a transaction every local change is committed on its own.
+///
 /// - `peer_connected` / `peer_disconnected`: Handles peer connection events.
 ///   - Maintains a sync state for each peer to track synchronization progress.
 ///
+/// - `set_local_presence` / `update_peer_presence` / `peers`: Tracks ephemeral presence.
+///   - Presence lives next to the document, not inside it, and is never persisted or synced by Automerge.
+///
 /// - `receive_sync_message`: Processes an incoming sync message from a peer.
 ///   - Decodes the message and applies it to the document using Automerge's sync protocol.
 ///   - Returns a `FrontendUpdate` with the latest strokes.
 ///
+/// - `set_local_identity`: Embeds the collaborator's identity in the Automerge actor id
+///   (followed by random bytes, as actor ids must be unique per session).
+///
+/// - `history` / `checkout`: List the Automerge change log and read the document at past heads
+///   (via `fork_at`). Local changes carry a message naming the intent and a commit time.
+///
+/// - `stats`: Derives op, tombstone and byte counts from the change history.
+///
+/// - `subscribe`: Registers a channel notified whenever a sync message changes the document.
+///
 /// - `generate_sync_message`: Generates a sync message for a peer.
 ///   - Uses Automerge's sync protocol to create a message containing document changes.
 ///
 /// - `save` / `load`: Serializes and deserializes the Automerge document for persistence.
+///   `save_incremental` emits only the changes after the heads recorded by `mark_saved`
+///   (or `load`), which Automerge can load when appended to a full save.
+///
+/// - `title` / `set_title` / `modified_at` / `document_id`: Document metadata stored in
+///   the root map ("title" and "id" strings, "modified_at" timestamp refreshed on every
+///   local change).
 ///
 /// - `set_background` / `get_background`: Stores and retrieves background image data as bytes.
 ///
@@ -77,7 +777,8 @@ impl Default for AutomergeBackend {
 ///
 /// # Error Handling
 ///
-/// - Most operations use `unwrap` or `expect` for simplicity; production code should handle errors gracefully.
+/// - Automerge failures are reported as `BackendError::Crdt`, undecodable sync messages
+///   as `BackendError::Sync` and invalid saved documents as `BackendError::Load`.
 /// - If the "strokes" list is missing, it is recreated automatically.
 ///
 /// # Usage
@@ -85,115 +786,665 @@ impl Default for AutomergeBackend {
 /// This backend is suitable for collaborative drawing applications where multiple users
 /// can draw and erase strokes in real time, with changes seamlessly synchronized across peers.
 impl DocBackend for AutomergeBackend {
-    fn apply_intent(&mut self, intent: Intent) -> FrontendUpdate {
+    fn apply_intent(&mut self, intent: Intent) -> Result<FrontendUpdate, BackendError> {
+        if self.read_only && !matches!(intent, Intent::SwitchPage(_)) {
+            return Err(BackendError::ReadOnly);
+        }
+        let message = match &intent {
+            Intent::Draw(_) => "Draw stroke",
+            Intent::Clear => "Clear canvas",
+            Intent::EraseStroke(_) => "Erase stroke",
+            Intent::DrawShape { .. } => "Draw shape",
+            Intent::Undo => "Undo",
+            Intent::Redo => "Redo",
+            Intent::AddComment { .. } => "Add comment",
+            Intent::ReplyComment { .. } => "Reply to comment",
+            Intent::ResolveComment { .. } => "Resolve comment",
+            Intent::AddPage => "Add page",
+            Intent::SwitchPage(_) => "Switch page",
+            Intent::DeletePage(_) => "Delete page",
+        };
         match intent {
-            Intent::Draw(stroke) => {
-                let json = serde_json::to_string(&stroke).unwrap();
-                let list_id = match self.doc.get(ROOT, "strokes") {
-                    Ok(Some((Value::Object(ObjType::List), id))) => id,
-                     _ => {
-                        // Recreate if missing
-                        self.doc.put_object(ROOT, "strokes", ObjType::List).unwrap()
-                    }
-                };
-                
-                let len = self.doc.length(&list_id);
-                // insert expects item: impl Into<ScalarValue>
-                // String implements Into<ScalarValue>
-                self.doc.insert(&list_id, len, ScalarValue::Str(json.into())).expect("Failed to insert stroke");
+            Intent::Draw(stroke) => self.draw_stroke(stroke)?,
+            Intent::DrawShape { shape, start, end, color, width } => {
+                if matches!(&shape, Shape::Text(text) if text.trim().is_empty()) {
+                    return Err(BackendError::InvalidIntent("text labels cannot be empty".into()));
+                }
+                // A shape is stored like any other stroke.
+                self.draw_stroke(Stroke { points: vec![start, end], color, width, id: String::new(), shape: Some(shape) })?;
             }
             Intent::Clear => {
-                 let list_id = match self.doc.get(ROOT, "strokes") {
-                    Ok(Some((Value::Object(ObjType::List), id))) => id,
-                    _ => return FrontendUpdate::empty(),
+                 let Some(list_id) = self.page_strokes_list() else {
+                    return Ok(self.frontend_update());
                 };
                 let len = self.doc.length(&list_id);
                 if len > 0 {
-                    self.doc.splice(&list_id, 0, len as isize, std::iter::empty::<ScalarValue>()).expect("Failed to clear");
+                    let removed: Vec<(usize, ObjId, Stroke)> = self.doc.values(&list_id)
+                        .enumerate()
+                        .filter_map(|(index, (val, elem))| Some((index, elem.clone(), self.read_stroke(val, &elem)?)))
+                        .collect();
+                    self.doc.splice(&list_id, 0, len as isize, std::iter::empty::<ScalarValue>())?;
+                    self.record(Edit::RemovedStrokes(list_id, removed));
+                }
+            }
+            Intent::EraseStroke(id) => {
+                let list_id = self.strokes_list()?;
+                let (index, elem, stroke) = self.doc.values(&list_id)
+                    .enumerate()
+                    .filter_map(|(index, (val, elem))| Some((index, elem.clone(), self.read_stroke(val, &elem)?)))
+                    .find(|(_, _, stroke)| stroke.id == id)
+                    .ok_or_else(|| BackendError::InvalidIntent(format!("no stroke with id {}", id)))?;
+                self.doc.delete(&list_id, index)?;
+                self.record(Edit::RemovedStrokes(list_id, vec![(index, elem, stroke)]));
+            }
+            Intent::Undo | Intent::Redo if self.in_transaction => {
+                return Err(BackendError::InvalidIntent("cannot undo or redo during a transaction".into()));
+            }
+            Intent::Undo => {
+                let Some(group) = self.undo_stack.pop() else {
+                    return Ok(self.frontend_update());
+                };
+                match self.revert_group(group.clone()) {
+                    Ok(redo) => self.redo_stack.push(redo),
+                    Err(e) => {
+                        self.undo_stack.push(group);
+                        return Err(e);
+                    }
+                }
+            }
+            Intent::Redo => {
+                let Some(group) = self.redo_stack.pop() else {
+                    return Ok(self.frontend_update());
+                };
+                match self.revert_group(group.clone()) {
+                    Ok(undo) => self.undo_stack.push(undo),
+                    Err(e) => {
+                        self.redo_stack.push(group);
+                        return Err(e);
+                    }
+                }
+            }
+            Intent::AddComment { start, end, text } => {
+                let id = random_id();
+                let comment = Comment {
+                    id,
+                    start,
+                    end,
+                    text,
+                    author: self.local_presence.user.name.clone(),
+                    created_at: now_millis(),
+                    resolved: false,
+                    reply_to: None,
+                };
+                let json = self.put_comment(&comment)?;
+                self.record(Edit::Comment { id: comment.id, before: None, after: Some(json) });
+            }
+            Intent::ReplyComment { id, text } => {
+                let parent = self
+                    .get_comments()
+                    .into_iter()
+                    .find(|c| c.id == id)
+                    .ok_or_else(|| BackendError::InvalidIntent(format!("no comment with id {}", id)))?;
+                // Replies to a reply join the thread of the comment it replies to.
+                let thread = parent.reply_to.clone().unwrap_or(parent.id);
+                // Every reply is a comment of its own, so concurrent replies are all kept.
+                let reply = Comment {
+                    id: random_id(),
+                    start: parent.start,
+                    end: parent.end,
+                    text,
+                    author: self.local_presence.user.name.clone(),
+                    created_at: now_millis(),
+                    resolved: false,
+                    reply_to: Some(thread),
+                };
+                let json = self.put_comment(&reply)?;
+                self.record(Edit::Comment { id: reply.id, before: None, after: Some(json) });
+            }
+            Intent::ResolveComment { id } => {
+                let mut comment = self
+                    .get_comments()
+                    .into_iter()
+                    .find(|c| c.id == id)
+                    .ok_or_else(|| BackendError::InvalidIntent(format!("no comment with id {}", id)))?;
+                if comment.resolved {
+                    return Ok(self.frontend_update());
+                }
+                comment.resolved = true;
+                let before = self.comment_json(&id);
+                let json = self.put_comment(&comment)?;
+                self.record(Edit::Comment { id, before, after: Some(json) });
+            }
+            Intent::AddPage => {
+                // The new page goes after the current one.
+                let current = self.page_object();
+                let lists = self.pages_lists();
+                let after_current = lists.iter().find_map(|list| {
+                    let position = self.doc.values(list).position(|(_, id)| id == current)?;
+                    Some((list.clone(), position + 1))
+                });
+                let (pages_id, index) = match (after_current, lists.into_iter().next()) {
+                    (Some(found), _) => found,
+                    (None, Some(first)) => (first, 0),
+                    (None, None) => (self.doc.put_object(ROOT, "pages", ObjType::List)?, 0),
+                };
+                let page = self.doc.insert_object(&pages_id, index, ObjType::Map)?;
+                self.doc.put_object(&page, "strokes", ObjType::List)?;
+                self.current_page = Some(page);
+            }
+            Intent::SwitchPage(index) => {
+                self.switch_page(index)
+                    .ok_or_else(|| BackendError::InvalidIntent(format!("no page {}", index)))?;
+                self.sync_stroke_cache();
+                return Ok(self.frontend_update());
+            }
+            Intent::DeletePage(index) => {
+                if index == 0 {
+                    return Err(BackendError::InvalidIntent("the first page cannot be deleted".into()));
+                }
+                let page = self.page_objects().into_iter().nth(index)
+                    .ok_or_else(|| BackendError::InvalidIntent(format!("no page {}", index)))?;
+                let (pages_id, position) = self.pages_lists().into_iter()
+                    .find_map(|list| {
+                        let position = self.doc.values(&list).position(|(_, id)| id == page)?;
+                        Some((list, position))
+                    })
+                    .expect("pages after the first live in a pages list");
+                if let Ok(Some((_, strokes_id))) = self.doc.get(&page, "strokes") {
+                    // The page's strokes can no longer be brought back by undo.
+                    let on_page = |edit: &Edit| matches!(edit,
+                        Edit::InsertedStrokes(list, _) | Edit::RemovedStrokes(list, _) if *list == strokes_id);
+                    for group in self.undo_stack.iter_mut().chain(self.redo_stack.iter_mut()) {
+                        group.retain(|edit| !on_page(edit));
+                    }
+                    self.undo_stack.retain(|group| !group.is_empty());
+                    self.redo_stack.retain(|group| !group.is_empty());
+                    self.transaction_edits.retain(|edit| !on_page(edit));
+                }
+                let was_current = self.page_object() == page;
+                self.doc.delete(&pages_id, position)?;
+                if was_current {
+                    self.switch_page(index - 1);
                 }
             }
         }
+        self.touch(message)?;
+        self.sync_stroke_cache();
 
-        FrontendUpdate {
-            strokes: self.get_strokes(),
-        }
+        Ok(self.frontend_update())
     }
 
     fn get_strokes(&self) -> Vec<Stroke> {
-         let list_id = match self.doc.get(ROOT, "strokes") {
-             Ok(Some((Value::Object(ObjType::List), id))) => id,
-             _ => return Vec::new(),
-         };
-         
-         let mut strokes = Vec::new();
-         for (val, _) in self.doc.values(&list_id) {
-             if let Value::Scalar(scalar) = val {
-                 if let ScalarValue::Str(s) = scalar.as_ref() {
-                     if let Ok(stroke) = serde_json::from_str(&s) {
-                         strokes.push(stroke);
-                     }
-                 }
-             }
-         }
-         strokes
+        if self.stroke_cache_stale {
+            return self.read_stroke_list().into_iter().filter_map(|(_, stroke)| stroke).collect();
+        }
+        self.stroke_cache.iter().filter_map(|(_, stroke)| stroke.clone()).collect()
+    }
+
+    /// A stroke's author is the actor of the operation that created its map.
+    fn stroke_authors(&self) -> Vec<(String, String)> {
+        let authors = |entries: &[(ObjId, Option<Stroke>)]| -> Vec<(String, String)> {
+            entries
+                .iter()
+                .filter_map(|(elem, stroke)| match (elem, stroke) {
+                    (ObjId::Id(_, actor, _), Some(stroke)) => Some((stroke.id.clone(), identity_of_actor(actor))),
+                    _ => None,
+                })
+                .collect()
+        };
+        if self.stroke_cache_stale {
+            authors(&self.read_stroke_list())
+        } else {
+            authors(&self.stroke_cache)
+        }
+    }
+
+    fn page_count(&self) -> usize {
+        self.page_objects().len()
+    }
+
+    fn current_page(&self) -> usize {
+        let page = self.page_object();
+        self.page_objects().iter().position(|id| *id == page).unwrap_or(0)
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), BackendError> {
+        if self.in_transaction {
+            return Err(BackendError::InvalidIntent("a transaction is already open".into()));
+        }
+        // Close anything pending so the transaction starts a fresh change.
+        self.doc.commit();
+        self.in_transaction = true;
+        self.transaction_edits.clear();
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<FrontendUpdate, BackendError> {
+        if !self.in_transaction {
+            return Err(BackendError::InvalidIntent("no transaction is open".into()));
+        }
+        self.in_transaction = false;
+        let edits = std::mem::take(&mut self.transaction_edits);
+        if !edits.is_empty() {
+            self.undo_stack.push(edits);
+        }
+        let mut messages = std::mem::take(&mut self.pending_messages);
+        messages.dedup();
+        self.commit_change(messages.join(", "));
+        self.sync_stroke_cache();
+        Ok(self.frontend_update())
+    }
+
+    fn get_comments(&self) -> Vec<Comment> {
+        let map_id = match self.doc.get(ROOT, "comments") {
+            Ok(Some((Value::Object(ObjType::Map), id))) => id,
+            _ => return Vec::new(),
+        };
+
+        let mut comments: Vec<Comment> = self
+            .doc
+            .values(&map_id)
+            .filter_map(|(val, _)| match val {
+                Value::Scalar(scalar) => match scalar.as_ref() {
+                    ScalarValue::Str(s) => serde_json::from_str(s).ok(),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        comments.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        comments
+    }
+
+    fn get_conflicts(&self) -> Vec<Conflict> {
+        CONFLICT_KEYS
+            .iter()
+            .filter_map(|key| {
+                let mut values: Vec<String> = self.doc.get_all(ROOT, *key).ok()?
+                    .into_iter()
+                    .filter_map(|(val, _)| match val {
+                        Value::Scalar(s) => match s.as_ref() {
+                            ScalarValue::Str(text) => Some(text.to_string()),
+                            _ => None,
+                        },
+                        _ => None,
+                    })
+                    .collect();
+                // `get_all` lists the winning value last; show it first.
+                values.reverse();
+                let mut distinct = Vec::new();
+                for value in values {
+                    if !distinct.contains(&value) {
+                        distinct.push(value);
+                    }
+                }
+                (distinct.len() > 1).then(|| Conflict { key: key.to_string(), values: distinct })
+            })
+            .collect()
+    }
+
+    fn can_undo(&self) -> bool {
+        !self.read_only && !self.undo_stack.is_empty()
+    }
+
+    fn can_redo(&self) -> bool {
+        !self.read_only && !self.redo_stack.is_empty()
     }
 
     fn peer_connected(&mut self, peer_id: &str) {
         println!("Peer connected: {}", peer_id);
         self.sync_states.insert(peer_id.to_string(), sync::State::new());
+        self.peer_presence.insert(peer_id.to_string(), Presence::default());
     }
 
     fn peer_disconnected(&mut self, peer_id: &str) {
         println!("Peer disconnected: {}", peer_id);
         self.sync_states.remove(peer_id);
+        self.peer_presence.remove(peer_id);
+    }
+
+    fn set_local_presence(&mut self, cursor: Option<Point>, selection: Option<Selection>, user: UserInfo) {
+        self.local_presence = Presence { cursor, selection, user };
+    }
+
+    fn local_presence(&self) -> Presence {
+        self.local_presence.clone()
+    }
+
+    fn update_peer_presence(&mut self, peer_id: &str, presence: Presence) {
+        if let Some(entry) = self.peer_presence.get_mut(peer_id) {
+            *entry = presence;
+        }
+    }
+
+    fn peers(&self) -> Vec<PeerPresence> {
+        let mut peers: Vec<PeerPresence> = self.peer_presence.iter()
+            .map(|(peer_id, presence)| PeerPresence { peer_id: peer_id.clone(), presence: presence.clone() })
+            .collect();
+        peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        peers
     }
     /// Odbiera i przetwarza wiadomość synchronizacyjną od innego użytkownika.
-    fn receive_sync_message(&mut self, peer_id: &str, message: Vec<u8>) -> FrontendUpdate {
+    ///
+    /// Application is idempotent: Automerge identifies every change by its hash,
+    /// so changes that are already part of the document (retransmits, a replayed
+    /// message, or a delta overlapping a previously loaded snapshot) are skipped.
+    fn receive_sync_message(&mut self, peer_id: &str, message: Vec<u8>) -> Result<FrontendUpdate, BackendError> {
         // 1. Pobieramy "stan wiedzy" o tym koledze (sync_state).
         let sync_state = self.sync_states.entry(peer_id.to_string()).or_insert_with(sync::State::new);
         
         // 2. Dekodujemy wiadomość (rozpakowujemy walizkę).
-        if let Ok(msg) = sync::Message::decode(&message) {
-             // 3. "Wchłaniamy" zmiany do naszego dokumentu. To tutaj dzieje się łączenie (merge).
-             // Jednocześnie aktualizuje się sync_state, żebyśmy wiedzieli, że my też jesteśmy już "na bieżąco".
-             self.doc.sync().receive_sync_message(sync_state, msg).ok();
-        }
+        let msg = sync::Message::decode(&message)
+            .map_err(|e| BackendError::Sync(format!("invalid message from {}: {}", peer_id, e)))?;
+        // 3. "Wchłaniamy" zmiany do naszego dokumentu. To tutaj dzieje się łączenie (merge).
+        // Jednocześnie aktualizuje się sync_state, żebyśmy wiedzieli, że my też jesteśmy już "na bieżąco".
+        let heads_before = self.doc.get_heads();
+        self.doc.sync().receive_sync_message(sync_state, msg)
+            .map_err(|e| BackendError::Sync(e.to_string()))?;
+        self.sync_stroke_cache();
 
         // Zwracamy nową listę kresek do odrysowania na ekranie.
-        FrontendUpdate { strokes: self.get_strokes() }
+        let update = self.frontend_update();
+        if self.doc.get_heads() != heads_before {
+            self.notify_subscribers(&update);
+        }
+        Ok(update)
     }
 
-    fn generate_sync_message(&mut self, peer_id: &str) -> Option<Vec<u8>> {
-        if let Some(sync_state) = self.sync_states.get_mut(peer_id) {
-            // Pytamy bibliotekę Automerge: "Hej, co nowego wydarzyło się od ostatniej synchronizacji?".
-            // Jeśli są nowe zmiany, Automerge pakuje je w binarną wiadomość.
-            if let Some(msg) = self.doc.sync().generate_sync_message(sync_state) {
-                // Zwracamy pudełko z nowymi danymi.
-                return Some(msg.encode());
-            }
+    fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn set_local_identity(&mut self, identity: &str) {
+        if self.identity.as_deref() == Some(identity) {
+            return;
         }
-        // Kolega jest już na bieżąco.
-        None
+        self.identity = Some(identity.to_string());
+        self.doc.set_actor(actor_for_identity(identity));
     }
 
-    fn save(&mut self) -> Vec<u8> {
-        self.doc.save()
+    fn history(&mut self) -> Vec<HistoryEntry> {
+        self.doc
+            .get_changes(&[])
+            .iter()
+            .map(|change| HistoryEntry {
+                id: change.hash().to_string(),
+                actor: identity_of_actor(change.actor_id()),
+                timestamp: change.timestamp(),
+                message: change.message().cloned(),
+            })
+            .collect()
+    }
+
+    fn checkout(&mut self, version: &[String]) -> Result<FrontendUpdate, BackendError> {
+        let heads = version
+            .iter()
+            .map(|id| {
+                id.parse::<ChangeHash>()
+                    .map_err(|_| BackendError::InvalidIntent(format!("invalid version id {}", id)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let past = self
+            .doc
+            .fork_at(&heads)
+            .map_err(|e| BackendError::InvalidIntent(format!("unknown version: {}", e)))?;
+        let mut view = AutomergeBackend { doc: past, current_page: self.current_page.clone(), ..AutomergeBackend::new() };
+        Ok(view.frontend_update())
     }
 
-    fn load(&mut self, data: Vec<u8>) {
-        if let Ok(doc) = AutoCommit::load(&data) {
-            self.doc = doc;
-            self.sync_states.clear();
+    /// Counts are taken from the change history: every superseded op (an
+    /// overwritten value or a deleted list element) is a tombstone, and the
+    /// memory estimate is the encoded size of all changes.
+    fn stats(&mut self) -> BackendStats {
+        let changes = self.doc.get_changes(&[]);
+        let op_count = changes.iter().map(|c| c.len()).sum();
+        let tombstone_count = changes
+            .iter()
+            .flat_map(|c| c.decode().operations)
+            .map(|op| op.pred.len())
+            .sum();
+        let memory_bytes = changes.iter().map(|c| c.raw_bytes().len()).sum();
+
+        // Peers whose heads we have not heard yet are assumed to need everything.
+        let peer_heads: Vec<Option<Vec<automerge::ChangeHash>>> =
+            self.sync_states.values().map(|state| state.their_heads.clone()).collect();
+        let pending_sync_bytes = peer_heads
+            .iter()
+            .map(|heads| match heads {
+                Some(heads) => self.doc.get_changes(heads).iter().map(|c| c.raw_bytes().len()).sum(),
+                None => memory_bytes,
+            })
+            .sum();
+
+        BackendStats {
+            op_count,
+            tombstone_count,
+            peer_count: self.sync_states.len(),
+            pending_sync_bytes,
+            memory_bytes,
         }
     }
 
-    fn set_background(&mut self, data: Vec<u8>) {
-        // Store as bytes
-        self.doc.put(ROOT, "background", ScalarValue::Bytes(data)).ok();
+    fn fork(&mut self) -> Result<Box<dyn DocBackend>, BackendError> {
+        if self.in_transaction {
+            return Err(BackendError::InvalidIntent("cannot fork during a transaction".into()));
+        }
+        let mut doc = self.doc.fork();
+        if let Some(identity) = &self.identity {
+            doc.set_actor(actor_for_identity(identity));
+        }
+        Ok(Box::new(AutomergeBackend {
+            doc,
+            identity: self.identity.clone(),
+            local_presence: self.local_presence.clone(),
+            current_page: self.current_page.clone(),
+            ..AutomergeBackend::new()
+        }))
     }
 
+    /// `other` is read through its saved form, since backends are only known
+    /// by their trait here.
+    fn merge(&mut self, other: &mut dyn DocBackend) -> Result<FrontendUpdate, BackendError> {
+        if self.read_only {
+            return Err(BackendError::ReadOnly);
+        }
+        let mut theirs = AutoCommit::load(&other.save()).map_err(|e| BackendError::Load(e.to_string()))?;
+        self.doc.merge(&mut theirs)?;
+        self.sync_stroke_cache();
+        Ok(self.frontend_update())
+    }
+
+    /// Rebuilds the document from the shared skeleton plus one change holding
+    /// the current content, so the result still merges with fresh documents.
+    fn compact(&mut self, threshold: usize) -> Result<CompactionReport, BackendError> {
+        if !self.sync_states.is_empty() {
+            return Err(BackendError::InvalidIntent("cannot compact while peers are connected".into()));
+        }
+        if self.in_transaction {
+            return Err(BackendError::InvalidIntent("cannot compact during a transaction".into()));
+        }
+        let changes_before = self.doc.get_changes(&[]).len();
+        let bytes_before = self.doc.save().len();
+        if changes_before <= threshold {
+            return Ok(CompactionReport {
+                changes_before,
+                changes_after: changes_before,
+                bytes_before,
+                bytes_after: bytes_before,
+            });
+        }
+
+        let page = self.current_page();
+        let mut doc = Self::skeleton();
+        if let Some(identity) = &self.identity {
+            doc.set_actor(actor_for_identity(identity));
+        }
+        copy_object(&self.doc, &ROOT, &mut doc, &ROOT)?;
+        doc.commit_with(CommitOptions::default().with_message("Compact history").with_time(now_millis()));
+        self.doc = doc;
+        // The copied pages are new objects; stay on the same page.
+        self.switch_page(page);
+        // Nothing saved so far shares history with the new document.
+        self.saved_heads = Vec::new();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.sync_stroke_cache();
+
+        Ok(CompactionReport {
+            changes_before,
+            changes_after: self.doc.get_changes(&[]).len(),
+            bytes_before,
+            bytes_after: self.doc.save().len(),
+        })
+    }
+
+    fn subscribe(&mut self) -> UnboundedReceiver<FrontendUpdate> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    fn generate_sync_message(&mut self, peer_id: &str) -> Option<Vec<u8>> {
+        if self.in_transaction {
+            // Changes are offered to peers once the transaction is committed.
+            return None;
+        }
+        if let Some(sync_state) = self.sync_states.get_mut(peer_id) {
+            // Pytamy bibliotekę Automerge: "Hej, co nowego wydarzyło się od ostatniej synchronizacji?".
+            // Jeśli są nowe zmiany, Automerge pakuje je w binarną wiadomość.
+            if let Some(msg) = self.doc.sync().generate_sync_message(sync_state) {
+                // Zwracamy pudełko z nowymi danymi.
+                return Some(msg.encode());
+            }
+        }
+        // Kolega jest już na bieżąco.
+        None
+    }
+
+    fn save(&mut self) -> Vec<u8> {
+        self.doc.save()
+    }
+
+    fn save_incremental(&mut self) -> Result<IncrementalSave, BackendError> {
+        if self.in_transaction {
+            return Err(BackendError::InvalidIntent("cannot save during a transaction".into()));
+        }
+        let data = self.doc.save_after(&self.saved_heads);
+        Ok(IncrementalSave { data, version: self.version() })
+    }
+
+    fn version(&mut self) -> Vec<String> {
+        self.doc.get_heads().iter().map(|hash| hash.to_string()).collect()
+    }
+
+    fn mark_saved(&mut self, version: &[String]) {
+        self.saved_heads = version.iter().filter_map(|id| id.parse().ok()).collect();
+    }
+
+    fn load(&mut self, data: Vec<u8>) -> Result<(), BackendError> {
+        let mut doc = AutoCommit::load(&data).map_err(|e| BackendError::Load(e.to_string()))?;
+        if let Some(identity) = &self.identity {
+            doc.set_actor(actor_for_identity(identity));
+        }
+        self.saved_heads = doc.get_heads();
+        self.doc = doc;
+        self.sync_states.clear();
+        self.in_transaction = false;
+        self.pending_messages.clear();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.transaction_edits.clear();
+        self.current_page = None;
+        self.sync_stroke_cache();
+        Ok(())
+    }
+
+    fn title(&self) -> String {
+        self.root_string("title").unwrap_or_default()
+    }
+
+    fn set_title(&mut self, title: &str) -> Result<(), BackendError> {
+        if self.read_only {
+            return Err(BackendError::ReadOnly);
+        }
+        self.doc.put(ROOT, "title", title)?;
+        self.touch("Set title")
+    }
+
+    fn modified_at(&self) -> Option<i64> {
+        match self.doc.get(ROOT, "modified_at") {
+            Ok(Some((Value::Scalar(s), _))) => match s.as_ref() {
+                ScalarValue::Timestamp(t) => Some(*t),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn document_id(&self) -> Option<String> {
+        self.root_string("id").filter(|id| !id.is_empty())
+    }
+
+    fn set_document_id(&mut self, id: &str) -> Result<(), BackendError> {
+        if self.read_only {
+            return Err(BackendError::ReadOnly);
+        }
+        self.doc.put(ROOT, "id", id)?;
+        self.touch("Set document id")
+    }
+
+    /// The image is stored as a map holding its size, a content hash and a
+    /// list of chunks of at most `BACKGROUND_CHUNK_SIZE` bytes. Outside a
+    /// transaction every chunk is committed as a change of its own, so a peer
+    /// that received only part of the image is later sent just the rest.
+    fn set_background(&mut self, data: Vec<u8>) -> Result<(), BackendError> {
+        if self.read_only {
+            return Err(BackendError::ReadOnly);
+        }
+        let map = self.doc.put_object(self.page_object(), "background", ObjType::Map)?;
+        self.doc.put(&map, "size", data.len() as u64)?;
+        self.doc.put(&map, "hash", content_hash(&data))?;
+        let chunks = self.doc.put_object(&map, "chunks", ObjType::List)?;
+        let total = data.len().div_ceil(BACKGROUND_CHUNK_SIZE);
+        for (index, chunk) in data.chunks(BACKGROUND_CHUNK_SIZE).enumerate() {
+            self.doc.insert(&chunks, index, ScalarValue::Bytes(chunk.to_vec()))?;
+            if index + 1 < total && !self.in_transaction {
+                self.commit_change(format!("Set background (part {} of {})", index + 1, total));
+            }
+        }
+        self.touch("Set background")
+    }
+
+    /// Returns `None` until all chunks have arrived and match the stored hash.
     fn get_background(&self) -> Option<Vec<u8>> {
-        match self.doc.get(ROOT, "background") {
+        match self.doc.get(self.page_object(), "background") {
+            Ok(Some((Value::Object(ObjType::Map), map))) => {
+                let uint = |key: &str| match self.doc.get(&map, key) {
+                    Ok(Some((Value::Scalar(s), _))) => match s.as_ref() {
+                        ScalarValue::Uint(n) => Some(*n),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let chunks = match self.doc.get(&map, "chunks") {
+                    Ok(Some((Value::Object(ObjType::List), id))) => id,
+                    _ => return None,
+                };
+                let mut data = Vec::new();
+                for (val, _) in self.doc.values(&chunks) {
+                    if let Value::Scalar(s) = val {
+                        if let ScalarValue::Bytes(chunk) = s.as_ref() {
+                            data.extend_from_slice(chunk);
+                        }
+                    }
+                }
+                (uint("size") == Some(data.len() as u64) && uint("hash") == Some(content_hash(&data))).then_some(data)
+            }
+            // Documents written by older versions store the image as one value.
             Ok(Some((Value::Scalar(s), _))) => {
                 if let ScalarValue::Bytes(b) = s.as_ref() {
                     Some(b.clone())
@@ -216,6 +1467,8 @@ mod tests {
             points: vec![Point { x: 10, y: 10 }, Point { x: 20, y: 20 }],
             color: [255, 0, 0, 255],
             width: 5.0,
+            id: String::new(),
+            shape: None,
         }
     }
 
@@ -230,7 +1483,7 @@ mod tests {
         let mut backend = AutomergeBackend::new();
         let stroke = create_test_stroke();
         
-        backend.apply_intent(Intent::Draw(stroke.clone()));
+        backend.apply_intent(Intent::Draw(stroke.clone())).unwrap();
         
         let strokes = backend.get_strokes();
         assert_eq!(strokes.len(), 1);
@@ -243,10 +1496,10 @@ mod tests {
         let mut backend = AutomergeBackend::new();
         let stroke = create_test_stroke();
         
-        backend.apply_intent(Intent::Draw(stroke));
+        backend.apply_intent(Intent::Draw(stroke)).unwrap();
         assert!(!backend.get_strokes().is_empty());
         
-        backend.apply_intent(Intent::Clear);
+        backend.apply_intent(Intent::Clear).unwrap();
         assert!(backend.get_strokes().is_empty());
     }
 
@@ -254,12 +1507,12 @@ mod tests {
     fn test_save_and_load() {
         let mut backend1 = AutomergeBackend::new();
         let stroke = create_test_stroke();
-        backend1.apply_intent(Intent::Draw(stroke.clone()));
+        backend1.apply_intent(Intent::Draw(stroke.clone())).unwrap();
         
         let data = backend1.save();
         
         let mut backend2 = AutomergeBackend::new();
-        backend2.load(data);
+        backend2.load(data).unwrap();
         
         let strokes = backend2.get_strokes();
         assert_eq!(strokes.len(), 1);
@@ -278,7 +1531,7 @@ mod tests {
 
         // Client A draws something
         let stroke = create_test_stroke();
-        client_a.apply_intent(Intent::Draw(stroke));
+        client_a.apply_intent(Intent::Draw(stroke)).unwrap();
 
         // Generate sync message from A -> B
         // In Automerge, we might need multiple rounds, but for a single change, one might suffice or loop until None.
@@ -294,11 +1547,11 @@ mod tests {
             }
 
             if let Some(msg) = msg_a_to_b {
-                client_b.receive_sync_message("client_a", msg);
+                client_b.receive_sync_message("client_a", msg).unwrap();
             }
 
             if let Some(msg) = msg_b_to_a {
-                client_a.receive_sync_message("client_b", msg);
+                client_a.receive_sync_message("client_b", msg).unwrap();
             }
             
             max_rounds -= 1;
@@ -315,8 +1568,8 @@ mod tests {
             let msg_a = a.generate_sync_message(b_label);
             let msg_b = b.generate_sync_message(a_label);
             if msg_a.is_none() && msg_b.is_none() { break; }
-            if let Some(m) = msg_a { b.receive_sync_message(a_label, m); }
-            if let Some(m) = msg_b { a.receive_sync_message(b_label, m); }
+            if let Some(m) = msg_a { b.receive_sync_message(a_label, m).unwrap(); }
+            if let Some(m) = msg_b { a.receive_sync_message(b_label, m).unwrap(); }
         }
     }
 
@@ -336,8 +1589,10 @@ mod tests {
             points: vec![Point { x: 0, y: 0 }],
             color: [128, 128, 128, 255],
             width: 1.0,
+            id: String::new(),
+            shape: None,
         };
-        client_a.apply_intent(Intent::Draw(seed));
+        client_a.apply_intent(Intent::Draw(seed)).unwrap();
         sync_loop(&mut client_a, "a", &mut client_b, "b");
         assert_eq!(client_b.get_strokes().len(), 1, "B should have the seed stroke");
 
@@ -346,14 +1601,18 @@ mod tests {
             points: vec![Point { x: 0, y: 0 }, Point { x: 100, y: 100 }],
             color: [255, 0, 0, 255],
             width: 3.0,
+            id: String::new(),
+            shape: None,
         };
         let stroke_b = Stroke {
             points: vec![Point { x: 50, y: 50 }, Point { x: 150, y: 150 }],
             color: [0, 0, 255, 255],
             width: 4.0,
+            id: String::new(),
+            shape: None,
         };
-        client_a.apply_intent(Intent::Draw(stroke_a));
-        client_b.apply_intent(Intent::Draw(stroke_b));
+        client_a.apply_intent(Intent::Draw(stroke_a)).unwrap();
+        client_b.apply_intent(Intent::Draw(stroke_b)).unwrap();
 
         // Sync
         sync_loop(&mut client_a, "a", &mut client_b, "b");
@@ -376,18 +1635,20 @@ mod tests {
 
         // Seed a shared stroke so there is something to clear
         let initial = create_test_stroke();
-        client_a.apply_intent(Intent::Draw(initial));
+        client_a.apply_intent(Intent::Draw(initial)).unwrap();
         sync_loop(&mut client_a, "a", &mut client_b, "b");
         assert_eq!(client_b.get_strokes().len(), 1);
 
         // Concurrently: A clears, B draws a NEW stroke
-        client_a.apply_intent(Intent::Clear);
+        client_a.apply_intent(Intent::Clear).unwrap();
         let new_stroke = Stroke {
             points: vec![Point { x: 99, y: 99 }],
             color: [0, 255, 0, 255],
             width: 2.0,
+            id: String::new(),
+            shape: None,
         };
-        client_b.apply_intent(Intent::Draw(new_stroke.clone()));
+        client_b.apply_intent(Intent::Draw(new_stroke.clone())).unwrap();
 
         // Sync
         sync_loop(&mut client_a, "a", &mut client_b, "b");
@@ -400,27 +1661,150 @@ mod tests {
         assert_eq!(sa[0].color, [0, 255, 0, 255], "The surviving stroke should be B's green one");
     }
 
+    // ---- Undo / Redo contract -------------------------------------------------
+    #[test]
+    fn test_undo_redo_without_history_is_noop() {
+        let mut backend = AutomergeBackend::new();
+        assert!(!backend.can_undo());
+        assert!(!backend.can_redo());
+
+        let update = backend.apply_intent(Intent::Undo).unwrap();
+        assert!(update.strokes.is_empty());
+        let update = backend.apply_intent(Intent::Redo).unwrap();
+        assert!(update.strokes.is_empty());
+    }
+
+    #[test]
+    fn test_undo_redo_draw_and_clear() {
+        let mut backend = AutomergeBackend::new();
+        let first = Stroke { id: "first".into(), ..create_test_stroke() };
+        let second = Stroke { points: vec![Point { x: 5, y: 5 }], color: [0, 0, 0, 255], width: 1.0, id: "second".into(), shape: None };
+        backend.apply_intent(Intent::Draw(first.clone())).unwrap();
+        backend.apply_intent(Intent::Draw(second.clone())).unwrap();
+        backend.apply_intent(Intent::Clear).unwrap();
+
+        let update = backend.apply_intent(Intent::Undo).unwrap();
+        assert_eq!(update.strokes, vec![first.clone(), second.clone()], "Undoing a clear restores the order");
+        let update = backend.apply_intent(Intent::Undo).unwrap();
+        assert_eq!(update.strokes, vec![first.clone()]);
+        assert!(backend.can_redo());
+
+        let update = backend.apply_intent(Intent::Redo).unwrap();
+        assert_eq!(update.strokes, vec![first, second]);
+
+        // A new edit discards the redo history.
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        assert!(!backend.can_redo());
+    }
+
+    #[test]
+    fn test_undo_leaves_remote_strokes_alone() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.peer_connected("b");
+        b.peer_connected("a");
+
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        let remote = Stroke { points: vec![Point { x: 9, y: 9 }], color: [0, 255, 0, 255], width: 3.0, id: "remote".into(), shape: None };
+        b.apply_intent(Intent::Draw(remote.clone())).unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!(a.get_strokes().len(), 2);
+
+        a.apply_intent(Intent::Undo).unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!(a.get_strokes(), vec![remote.clone()]);
+        assert_eq!(b.get_strokes(), vec![remote]);
+        assert!(!b.can_undo() || b.apply_intent(Intent::Undo).unwrap().strokes.is_empty());
+    }
+
+    #[test]
+    fn test_undo_keeps_remote_comment_changes() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.peer_connected("b");
+        b.peer_connected("a");
+
+        a.apply_intent(Intent::AddComment {
+            start: Point { x: 0, y: 0 },
+            end: Point { x: 1, y: 1 },
+            text: "mine".into(),
+        }).unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+        let id = b.get_comments()[0].id.clone();
+        b.apply_intent(Intent::ResolveComment { id }).unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+
+        let update = a.apply_intent(Intent::Undo).unwrap();
+        assert_eq!(update.comments.len(), 1, "The comment a peer resolved is not removed");
+        assert!(update.comments[0].resolved);
+    }
+
+    #[test]
+    fn test_no_undo_during_transaction() {
+        let mut backend = AutomergeBackend::new();
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        backend.begin_transaction().unwrap();
+        assert!(matches!(backend.apply_intent(Intent::Undo), Err(BackendError::InvalidIntent(_))));
+        backend.commit().unwrap();
+        assert!(backend.can_undo(), "The refused step stays available");
+        assert!(backend.apply_intent(Intent::Undo).unwrap().strokes.is_empty());
+    }
+
+    #[test]
+    fn test_transaction_is_one_undo_step() {
+        let mut backend = AutomergeBackend::new();
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+
+        backend.begin_transaction().unwrap();
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        backend.apply_intent(Intent::AddComment {
+            start: Point { x: 0, y: 0 },
+            end: Point { x: 1, y: 1 },
+            text: "grouped".into(),
+        }).unwrap();
+        backend.commit().unwrap();
+
+        let update = backend.apply_intent(Intent::Undo).unwrap();
+        assert_eq!(update.strokes.len(), 1);
+        assert!(update.comments.is_empty());
+        let update = backend.apply_intent(Intent::Redo).unwrap();
+        assert_eq!(update.strokes.len(), 2);
+        assert_eq!(update.comments.len(), 1);
+    }
+
     // ---- Graceful handling of corrupted / invalid data -------------------------
     #[test]
     fn test_load_invalid_bytes_does_not_panic() {
         let mut backend = AutomergeBackend::new();
-        backend.apply_intent(Intent::Draw(create_test_stroke()));
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
 
         // Feed garbage bytes — should not panic, document should remain intact
-        backend.load(vec![0, 1, 2, 3, 255, 254]);
+        let result = backend.load(vec![0, 1, 2, 3, 255, 254]);
+        assert!(matches!(result, Err(BackendError::Load(_))), "Invalid bytes should be reported as a load error");
 
-        // The previous stroke should still be present (load failed silently)
+        // The previous stroke should still be present (load was rejected)
         assert_eq!(backend.get_strokes().len(), 1, "Invalid load should leave document unchanged");
     }
 
+    #[test]
+    fn test_malformed_sync_message_is_reported() {
+        let mut backend = AutomergeBackend::new();
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        backend.peer_connected("peer");
+
+        let result = backend.receive_sync_message("peer", vec![0xde, 0xad, 0xbe, 0xef]);
+        assert!(matches!(result, Err(BackendError::Sync(_))));
+        assert_eq!(backend.get_strokes().len(), 1, "A bad message must not alter the document");
+    }
+
     #[test]
     fn test_load_empty_bytes_does_not_panic() {
         let mut backend = AutomergeBackend::new();
-        backend.apply_intent(Intent::Draw(create_test_stroke()));
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
 
         // Empty bytes may be treated as a valid empty doc by Automerge.
         // The key requirement is that this call does not panic.
-        backend.load(vec![]);
+        let _ = backend.load(vec![]);
 
         // After loading empty data, behavior is implementation-defined:
         // Automerge may replace the doc (strokes gone) or reject the load.
@@ -438,8 +1822,10 @@ mod tests {
                 points: vec![Point { x: i, y: i }],
                 color: [i as u8, 0, 0, 255],
                 width: 1.0 + i as f32,
+                id: String::new(),
+                shape: None,
             };
-            backend.apply_intent(Intent::Draw(stroke));
+            backend.apply_intent(Intent::Draw(stroke)).unwrap();
         }
 
         let strokes = backend.get_strokes();
@@ -469,7 +1855,9 @@ mod tests {
             points: vec![Point { x: 0, y: 0 }], 
             color: [128, 128, 128, 255],
             width: 1.0,
-        }));
+            id: String::new(),
+            shape: None,
+        })).unwrap();
         sync_loop(&mut a, "a", &mut b, "b");
         sync_loop(&mut b, "b", &mut c, "c");
         assert_eq!(c.get_strokes().len(), 1, "C should have the seed stroke");
@@ -479,12 +1867,16 @@ mod tests {
             points: vec![Point { x: 1, y: 1 }],
             color: [255, 0, 0, 255],
             width: 1.0,
-        }));
+            id: String::new(),
+            shape: None,
+        })).unwrap();
         c.apply_intent(Intent::Draw(Stroke {
             points: vec![Point { x: 2, y: 2 }],
             color: [0, 0, 255, 255],
             width: 2.0,
-        }));
+            id: String::new(),
+            shape: None,
+        })).unwrap();
 
         // Sync A↔B, then B↔C, then A↔B again (propagate C's stroke to A)
         sync_loop(&mut a, "a", &mut b, "b");
@@ -510,12 +1902,38 @@ mod tests {
         assert!(backend.get_background().is_none());
 
         let img_data = vec![0xFF, 0xD8, 0xFF, 0xE0]; // fake JPEG header bytes
-        backend.set_background(img_data.clone());
+        backend.set_background(img_data.clone()).unwrap();
 
         let bg = backend.get_background().expect("Background should be set");
         assert_eq!(bg, img_data, "Background data should round-trip unchanged");
     }
 
+    #[test]
+    fn test_large_background_is_chunked_and_verified() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        let image: Vec<u8> = (0..BACKGROUND_CHUNK_SIZE * 2 + 100).map(|i| (i % 251) as u8).collect();
+        let changes_before = a.history().len();
+        a.set_background(image.clone()).unwrap();
+        assert_eq!(a.history().len(), changes_before + 3, "One change per chunk");
+        assert_eq!(a.get_background(), Some(image.clone()));
+
+        a.peer_connected("b");
+        b.peer_connected("a");
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!(b.get_background(), Some(image.clone()));
+
+        // A missing chunk means the image is not complete yet.
+        let (_, map) = b.doc.get(ROOT, "background").unwrap().unwrap();
+        let (_, chunks) = b.doc.get(&map, "chunks").unwrap().unwrap();
+        b.doc.delete(&chunks, 1).unwrap();
+        assert!(b.get_background().is_none());
+
+        // Documents from older versions store the image as a single value.
+        b.doc.put(ROOT, "background", ScalarValue::Bytes(vec![1, 2, 3])).unwrap();
+        assert_eq!(b.get_background(), Some(vec![1, 2, 3]));
+    }
+
     // ---- Peer disconnect cleans up sync state ----------------------------------
     #[test]
     fn test_peer_disconnect_removes_sync_state() {
@@ -528,4 +1946,667 @@ mod tests {
         assert!(backend.generate_sync_message("peer_x").is_none(),
             "No sync message should be produced for a disconnected peer");
     }
+
+    /// Helper: like `sync_loop`, but records every message sent from `a` to `b`.
+    fn sync_loop_recording(a: &mut AutomergeBackend, a_label: &str, b: &mut AutomergeBackend, b_label: &str) -> Vec<Vec<u8>> {
+        let mut sent = Vec::new();
+        for _ in 0..20 {
+            let msg_a = a.generate_sync_message(b_label);
+            let msg_b = b.generate_sync_message(a_label);
+            if msg_a.is_none() && msg_b.is_none() { break; }
+            if let Some(m) = msg_a {
+                sent.push(m.clone());
+                b.receive_sync_message(a_label, m).unwrap();
+            }
+            if let Some(m) = msg_b { a.receive_sync_message(b_label, m).unwrap(); }
+        }
+        sent
+    }
+
+    // ---- Metadata ---------------------------------------------------------------
+    #[test]
+    fn test_title_and_modified_at() {
+        let mut backend = AutomergeBackend::new();
+        assert_eq!(backend.title(), "");
+        assert!(backend.modified_at().is_none());
+
+        backend.set_title("Sketch").unwrap();
+        assert_eq!(backend.title(), "Sketch");
+        let first = backend.modified_at().expect("set_title should record a timestamp");
+
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        assert!(backend.modified_at().unwrap() >= first);
+    }
+
+    #[test]
+    fn test_title_syncs_between_peers() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.peer_connected("b");
+        b.peer_connected("a");
+
+        a.set_title("Shared board").unwrap();
+        a.set_document_id("board-1").unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+
+        assert_eq!(b.title(), "Shared board");
+        assert_eq!(b.document_id().as_deref(), Some("board-1"));
+        assert_eq!(b.modified_at(), a.modified_at());
+    }
+
+    // ---- Presence ---------------------------------------------------------------
+    #[test]
+    fn test_presence_tracks_connected_peers_only() {
+        let mut backend = AutomergeBackend::new();
+        backend.peer_connected("bob");
+        backend.peer_connected("alice");
+
+        let presence = Presence {
+            cursor: Some(Point { x: 5, y: 7 }),
+            selection: None,
+            user: UserInfo { name: "Bob".into(), color: None },
+        };
+        backend.update_peer_presence("bob", presence.clone());
+        backend.update_peer_presence("mallory", presence.clone());
+
+        let peers = backend.peers();
+        let ids: Vec<&str> = peers.iter().map(|p| p.peer_id.as_str()).collect();
+        assert_eq!(ids, vec!["alice", "bob"], "Unknown peers must be ignored, output sorted");
+        assert_eq!(peers[1].presence, presence);
+        assert_eq!(peers[0].presence, Presence::default());
+
+        backend.peer_disconnected("bob");
+        assert_eq!(backend.peers().len(), 1, "Disconnect must drop the peer's presence");
+    }
+
+    #[test]
+    fn test_local_presence_round_trip() {
+        let mut backend = AutomergeBackend::new();
+        let selection = Selection { start: Point { x: 0, y: 0 }, end: Point { x: 10, y: 10 } };
+        backend.set_local_presence(Some(Point { x: 1, y: 2 }), Some(selection.clone()), UserInfo { name: "me".into(), color: None });
+
+        let presence = backend.local_presence();
+        assert_eq!(presence.cursor, Some(Point { x: 1, y: 2 }));
+        assert_eq!(presence.selection, Some(selection));
+        assert_eq!(presence.user.name, "me");
+    }
+
+    // ---- Duplicate / replayed sync messages ------------------------------------
+    #[test]
+    fn test_replayed_sync_messages_are_idempotent() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.peer_connected("b");
+        b.peer_connected("a");
+
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        let sent = sync_loop_recording(&mut a, "a", &mut b, "b");
+        assert_eq!(b.get_strokes().len(), 2);
+
+        // Retransmit everything A ever sent, in order.
+        for m in sent.iter().cloned() {
+            b.receive_sync_message("a", m).unwrap();
+        }
+        assert_eq!(b.get_strokes().len(), 2, "Retransmitted ops must not be applied twice");
+
+        // Replay again after B lost its sync state (reconnect).
+        b.peer_disconnected("a");
+        b.peer_connected("a");
+        for m in sent.into_iter().rev() {
+            b.receive_sync_message("a", m).unwrap();
+        }
+        assert_eq!(b.get_strokes().len(), 2, "Replayed ops must not be applied twice");
+        assert_eq!(a.get_strokes(), b.get_strokes());
+    }
+
+    #[test]
+    fn test_snapshot_overlapping_delta_sync_has_no_duplicates() {
+        let mut a = AutomergeBackend::new();
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+
+        // B bootstraps from a snapshot, then A draws more before the delta sync.
+        let mut b = AutomergeBackend::new();
+        b.load(a.save()).unwrap();
+        a.apply_intent(Intent::Draw(Stroke {
+            points: vec![Point { x: 1, y: 2 }],
+            color: [0, 0, 0, 255],
+            width: 1.0,
+            id: String::new(),
+            shape: None,
+        })).unwrap();
+
+        a.peer_connected("b");
+        b.peer_connected("a");
+        sync_loop(&mut a, "a", &mut b, "b");
+
+        assert_eq!(b.get_strokes().len(), 2, "Ops already in the snapshot must be skipped");
+        assert_eq!(a.get_strokes(), b.get_strokes());
+    }
+
+    #[test]
+    fn test_subscribers_receive_remote_changes_only() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        let mut updates = b.subscribe();
+        drop(b.subscribe()); // closed receivers must not break notification
+
+        b.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        assert!(updates.try_recv().is_err(), "Local intents are not pushed");
+
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        a.peer_connected("b");
+        b.peer_connected("a");
+        sync_loop(&mut a, "a", &mut b, "b");
+
+        let mut last = None;
+        while let Ok(update) = updates.try_recv() {
+            last = Some(update);
+        }
+        assert_eq!(last.expect("Remote change must be pushed").strokes, b.get_strokes());
+        assert_eq!(b.subscribers.len(), 1);
+    }
+
+    #[test]
+    fn test_comments_add_resolve_and_sync() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.set_local_presence(None, None, UserInfo { name: "alice".into(), color: None });
+
+        let update = a.apply_intent(Intent::AddComment {
+            start: Point { x: 0, y: 0 },
+            end: Point { x: 50, y: 20 },
+            text: "Move this".into(),
+        }).unwrap();
+        assert_eq!(update.comments.len(), 1);
+        let comment = update.comments[0].clone();
+        assert_eq!(comment.author, "alice");
+        assert!(!comment.resolved);
+
+        // Comments survive a concurrent clear of the strokes.
+        a.peer_connected("b");
+        b.peer_connected("a");
+        sync_loop(&mut a, "a", &mut b, "b");
+        b.apply_intent(Intent::ResolveComment { id: comment.id.clone() }).unwrap();
+        a.apply_intent(Intent::Clear).unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+
+        assert_eq!(a.get_comments(), b.get_comments());
+        assert!(a.get_comments()[0].resolved);
+
+        let result = a.apply_intent(Intent::ResolveComment { id: "missing".into() });
+        assert!(matches!(result, Err(BackendError::InvalidIntent(_))));
+    }
+
+    #[test]
+    fn test_concurrent_replies_join_one_thread() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        let update = a.apply_intent(Intent::AddComment {
+            start: Point { x: 0, y: 0 },
+            end: Point { x: 50, y: 20 },
+            text: "Move this".into(),
+        }).unwrap();
+        let comment = update.comments[0].clone();
+        a.peer_connected("b");
+        b.peer_connected("a");
+        sync_loop(&mut a, "a", &mut b, "b");
+
+        a.apply_intent(Intent::ReplyComment { id: comment.id.clone(), text: "Where to?".into() }).unwrap();
+        let reply = b.apply_intent(Intent::ReplyComment { id: comment.id.clone(), text: "Done".into() }).unwrap()
+            .comments.into_iter().find(|c| c.reply_to.is_some()).unwrap();
+        b.apply_intent(Intent::ReplyComment { id: reply.id, text: "Thanks".into() }).unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+
+        let comments = a.get_comments();
+        assert_eq!(comments, b.get_comments());
+        assert_eq!(comments.len(), 4, "No reply is lost");
+        assert!(comments.iter().filter(|c| c.id != comment.id).all(|c| c.reply_to.as_ref() == Some(&comment.id)));
+        assert!(comments.iter().all(|c| c.start == comment.start && c.end == comment.end));
+    }
+
+    #[test]
+    fn test_stats_track_history_and_pending_sync() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        let empty = a.stats();
+        assert_eq!(empty.tombstone_count, 0);
+        assert_eq!(empty.peer_count, 0);
+
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        a.apply_intent(Intent::Clear).unwrap();
+        let stats = a.stats();
+        assert!(stats.op_count > empty.op_count);
+        assert!(stats.tombstone_count >= 1, "The cleared stroke is a tombstone");
+        assert!(stats.memory_bytes > 0);
+
+        a.peer_connected("b");
+        b.peer_connected("a");
+        assert_eq!(a.stats().peer_count, 1);
+        assert!(a.stats().pending_sync_bytes > 0);
+
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!(a.stats().pending_sync_bytes, 0);
+        assert_eq!(a.stats().op_count, b.stats().op_count);
+    }
+
+    #[test]
+    fn test_fresh_documents_drawing_before_first_sync_keep_all_strokes() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        b.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+
+        a.peer_connected("b");
+        b.peer_connected("a");
+        sync_loop(&mut a, "a", &mut b, "b");
+
+        assert_eq!(a.get_strokes().len(), 2, "Neither peer's strokes may be lost");
+        assert_eq!(a.get_strokes(), b.get_strokes());
+    }
+
+    #[test]
+    fn test_transaction_groups_changes_into_one() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.peer_connected("b");
+        b.peer_connected("a");
+        sync_loop(&mut a, "a", &mut b, "b");
+        let changes_before = a.doc.get_changes(&[]).len();
+
+        a.begin_transaction().unwrap();
+        assert!(matches!(a.begin_transaction(), Err(BackendError::InvalidIntent(_))));
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        a.set_title("Batch").unwrap();
+        assert!(a.generate_sync_message("b").is_none(), "Nothing is sent mid-transaction");
+
+        let update = a.commit().unwrap();
+        assert_eq!(update.strokes.len(), 2);
+        assert_eq!(a.doc.get_changes(&[]).len(), changes_before + 1);
+        assert!(matches!(a.commit(), Err(BackendError::InvalidIntent(_))));
+
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!(b.get_strokes().len(), 2);
+        assert_eq!(b.title(), "Batch");
+    }
+
+    #[test]
+    fn test_incremental_saves_append_to_snapshot() {
+        let mut backend = AutomergeBackend::new();
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        let mut file = backend.save();
+        let version = backend.version();
+        assert!(!backend.save_incremental().unwrap().data.is_empty(), "A full export does not count as saved");
+        backend.mark_saved(&version);
+        assert!(backend.save_incremental().unwrap().data.is_empty(), "Nothing changed since the save");
+
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        backend.set_title("Log").unwrap();
+        let delta = backend.save_incremental().unwrap();
+        assert!(!delta.data.is_empty());
+        assert_eq!(backend.save_incremental().unwrap(), delta, "Unmarked changes are returned again");
+        file.extend(delta.data);
+        backend.mark_saved(&delta.version);
+        assert!(backend.save_incremental().unwrap().data.is_empty());
+
+        let mut loaded = AutomergeBackend::new();
+        loaded.load(file).unwrap();
+        assert_eq!(loaded.get_strokes().len(), 2);
+        assert_eq!(loaded.title(), "Log");
+        assert!(loaded.save_incremental().unwrap().data.is_empty(), "A loaded document counts as saved");
+    }
+
+    #[test]
+    fn test_no_incremental_save_during_transaction() {
+        let mut backend = AutomergeBackend::new();
+        let changes_before = backend.history().len();
+        backend.begin_transaction().unwrap();
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        assert!(matches!(backend.save_incremental(), Err(BackendError::InvalidIntent(_))));
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        backend.commit().unwrap();
+        assert_eq!(backend.history().len(), changes_before + 1, "The grouped change was not split");
+    }
+
+    #[test]
+    fn test_history_and_checkout() {
+        let mut backend = AutomergeBackend::new();
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        backend.apply_intent(Intent::Clear).unwrap();
+
+        let history = backend.history();
+        let messages: Vec<_> = history.iter().map(|e| e.message.as_deref()).collect();
+        assert_eq!(messages, vec![None, Some("Draw stroke"), Some("Clear canvas")], "The skeleton has no message");
+        assert!(history[1].timestamp > 0);
+
+        let past = backend.checkout(&[history[1].id.clone()]).unwrap();
+        assert_eq!(past.strokes.len(), 1, "The stroke existed before the clear");
+        assert!(backend.get_strokes().is_empty(), "Checkout must not modify the document");
+
+        assert!(matches!(backend.checkout(&["nope".into()]), Err(BackendError::InvalidIntent(_))));
+        let unknown = "00".repeat(32);
+        assert!(matches!(backend.checkout(&[unknown]), Err(BackendError::InvalidIntent(_))));
+    }
+
+    #[test]
+    fn test_history_attributes_changes_to_identities() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.set_local_identity("alice");
+        b.set_local_identity("alice");
+        assert_ne!(a.doc.get_actor(), b.doc.get_actor(), "Sessions of one user need distinct actors");
+
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        b.set_local_identity("bob");
+        b.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        a.peer_connected("b");
+        b.peer_connected("a");
+        sync_loop(&mut a, "a", &mut b, "b");
+
+        let mut authors: Vec<String> = a.history().into_iter()
+            .filter(|e| e.message.as_deref() == Some("Draw stroke"))
+            .map(|e| e.actor)
+            .collect();
+        authors.sort();
+        assert_eq!(authors, vec!["alice", "bob"]);
+
+        let stroke_authors: Vec<String> = a.stroke_authors().into_iter().map(|(_, author)| author).collect();
+        assert_eq!(stroke_authors.len(), 2);
+        assert!(stroke_authors.contains(&"alice".to_string()) && stroke_authors.contains(&"bob".to_string()));
+        let ids: Vec<String> = a.get_strokes().into_iter().map(|s| s.id).collect();
+        assert_eq!(a.stroke_authors().into_iter().map(|(id, _)| id).collect::<Vec<_>>(), ids);
+    }
+
+    #[test]
+    fn test_compact_discards_history_but_keeps_content() {
+        let mut a = AutomergeBackend::new();
+        for _ in 0..20 {
+            a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        }
+        a.apply_intent(Intent::Clear).unwrap();
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        a.set_title("Kept").unwrap();
+        let strokes = a.get_strokes();
+
+        let unchanged = a.compact(1000).unwrap();
+        assert_eq!(unchanged.changes_before, unchanged.changes_after);
+        assert_eq!(unchanged.bytes_before, unchanged.bytes_after);
+
+        let report = a.compact(0).unwrap();
+        assert_eq!(report.changes_after, 2, "Skeleton plus the rewritten content");
+        assert!(report.bytes_after < report.bytes_before);
+        assert_eq!(a.get_strokes(), strokes);
+        assert_eq!(a.title(), "Kept");
+        assert!(!a.can_undo());
+
+        // Still merges with a fresh document without losing strokes.
+        let mut b = AutomergeBackend::new();
+        a.peer_connected("b");
+        b.peer_connected("a");
+        assert!(matches!(a.compact(0), Err(BackendError::InvalidIntent(_))));
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!(b.get_strokes(), strokes);
+    }
+
+    #[test]
+    fn test_fork_and_merge_branch() {
+        let mut main = AutomergeBackend::new();
+        main.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        let mut branch = main.fork().unwrap();
+        assert_eq!(branch.get_strokes(), main.get_strokes());
+
+        branch.apply_intent(Intent::Clear).unwrap();
+        branch.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        main.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        assert_eq!(main.get_strokes().len(), 2, "The branch is independent");
+
+        let update = main.merge(branch.as_mut()).unwrap();
+        assert_eq!(update.strokes.len(), 2, "Branch's clear removed the shared stroke only");
+        assert_eq!(update.strokes, main.get_strokes());
+        assert_eq!(branch.get_strokes().len(), 1);
+
+        // Merging again is a no-op.
+        assert_eq!(main.merge(branch.as_mut()).unwrap().strokes.len(), 2);
+    }
+
+    #[test]
+    fn test_concurrent_titles_are_reported_as_conflict() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.peer_connected("b");
+        b.peer_connected("a");
+        a.set_title("Plan A").unwrap();
+        b.set_title("Plan B").unwrap();
+        assert!(a.get_conflicts().is_empty());
+
+        sync_loop(&mut a, "a", &mut b, "b");
+        let conflicts = a.get_conflicts();
+        assert_eq!(conflicts, b.get_conflicts());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].key, "title");
+        assert_eq!(conflicts[0].values[0], a.title(), "The shown value comes first");
+        assert_eq!(conflicts[0].values.len(), 2);
+
+        a.set_title("Plan B").unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert!(b.get_conflicts().is_empty());
+        assert_eq!(b.title(), "Plan B");
+    }
+
+    #[test]
+    fn test_erase_single_stroke_syncs_and_undoes() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.peer_connected("b");
+        b.peer_connected("a");
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        b.apply_intent(Intent::Draw(Stroke { width: 9.0, ..create_test_stroke() })).unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+
+        let strokes = a.get_strokes();
+        assert_eq!(strokes, b.get_strokes(), "Ids agree across peers");
+        assert!(strokes.iter().all(|s| !s.id.is_empty()));
+        let (erased, kept): (Vec<Stroke>, Vec<Stroke>) = strokes.iter().cloned().partition(|s| s.width == 9.0);
+
+        let update = a.apply_intent(Intent::EraseStroke(erased[0].id.clone())).unwrap();
+        assert_eq!(update.strokes, kept);
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!(b.get_strokes(), kept);
+
+        assert!(matches!(
+            a.apply_intent(Intent::EraseStroke(erased[0].id.clone())),
+            Err(BackendError::InvalidIntent(_))
+        ));
+
+        a.apply_intent(Intent::Undo).unwrap();
+        assert_eq!(a.get_strokes(), strokes, "Undo restores the stroke with its id");
+    }
+
+    #[test]
+    fn test_shapes_sync_and_persist() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.peer_connected("b");
+        b.peer_connected("a");
+        let label = Shape::Text("Kitchen".into());
+        a.apply_intent(Intent::DrawShape {
+            shape: label.clone(),
+            start: Point { x: 5, y: 5 },
+            end: Point { x: 5, y: 5 },
+            color: [0, 0, 0, 255],
+            width: 14.0,
+        })
+        .unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!(b.get_strokes()[0].shape, Some(label.clone()));
+
+        let mut loaded = AutomergeBackend::new();
+        loaded.load(b.save()).unwrap();
+        assert_eq!(loaded.get_strokes(), a.get_strokes());
+
+        let id = loaded.get_strokes()[0].id.clone();
+        assert_eq!(loaded.apply_intent(Intent::EraseStroke(id)).unwrap().strokes, vec![], "Shapes erase like strokes");
+    }
+
+    #[test]
+    fn test_pages_sync_with_their_own_strokes_and_background() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.peer_connected("b");
+        b.peer_connected("a");
+        a.apply_intent(Intent::Draw(Stroke { id: "cover".into(), ..create_test_stroke() })).unwrap();
+        a.apply_intent(Intent::AddPage).unwrap();
+        a.apply_intent(Intent::Draw(Stroke { id: "sketch".into(), ..create_test_stroke() })).unwrap();
+        a.set_background(vec![1, 2, 3]).unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+
+        assert_eq!(b.page_count(), 2);
+        assert_eq!(b.current_page(), 0, "Switching pages is local");
+        assert_eq!(b.get_strokes()[0].id, "cover");
+        assert_eq!(b.get_background(), None);
+        b.apply_intent(Intent::SwitchPage(1)).unwrap();
+        assert_eq!(b.get_strokes()[0].id, "sketch");
+        assert_eq!(b.get_background(), Some(vec![1, 2, 3]));
+
+        // A page added by a peer in front of ours does not move us off our page.
+        a.apply_intent(Intent::SwitchPage(0)).unwrap();
+        a.apply_intent(Intent::AddPage).unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!((b.page_count(), b.current_page()), (3, 2));
+        assert_eq!(b.get_strokes()[0].id, "sketch");
+
+        // Deleting it elsewhere falls back to the first page.
+        a.apply_intent(Intent::DeletePage(2)).unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!((b.page_count(), b.current_page()), (2, 0));
+        assert_eq!(b.get_strokes()[0].id, "cover");
+    }
+
+    #[test]
+    fn test_pages_added_before_first_sync_are_all_kept() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.apply_intent(Intent::AddPage).unwrap();
+        a.apply_intent(Intent::Draw(Stroke { id: "a".into(), ..create_test_stroke() })).unwrap();
+        b.apply_intent(Intent::AddPage).unwrap();
+        b.apply_intent(Intent::Draw(Stroke { id: "b".into(), ..create_test_stroke() })).unwrap();
+
+        a.peer_connected("b");
+        b.peer_connected("a");
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!((a.page_count(), b.page_count()), (3, 3));
+        let mut ids: Vec<String> = (1..3)
+            .map(|page| {
+                a.apply_intent(Intent::SwitchPage(page)).unwrap();
+                a.get_strokes()[0].id.clone()
+            })
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["a".to_string(), "b".to_string()]);
+
+        // Either page can be deleted, whichever list holds it.
+        a.apply_intent(Intent::DeletePage(2)).unwrap();
+        a.apply_intent(Intent::DeletePage(1)).unwrap();
+        assert_eq!(a.page_count(), 1);
+    }
+
+    #[test]
+    fn test_undo_applies_to_the_page_it_was_drawn_on() {
+        let mut backend = AutomergeBackend::new();
+        backend.apply_intent(Intent::Draw(Stroke { id: "first".into(), ..create_test_stroke() })).unwrap();
+        backend.apply_intent(Intent::AddPage).unwrap();
+        backend.apply_intent(Intent::Draw(Stroke { id: "second".into(), ..create_test_stroke() })).unwrap();
+
+        backend.apply_intent(Intent::DeletePage(1)).unwrap();
+        assert!(backend.can_undo());
+        backend.apply_intent(Intent::Undo).unwrap();
+        assert!(backend.get_strokes().is_empty(), "Undo skips the strokes of the deleted page");
+        assert!(!backend.can_undo());
+    }
+
+    #[test]
+    fn test_strokes_are_stored_as_native_values() {
+        let mut backend = AutomergeBackend::new();
+        backend.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        let list_id = backend.strokes_list().unwrap();
+        let (value, elem) = backend.doc.get(&list_id, 0).unwrap().unwrap();
+        assert_eq!(value, Value::Object(ObjType::Map));
+        let (_, points) = backend.doc.get(&elem, "points").unwrap().unwrap();
+        assert_eq!(backend.doc.length(&points), 4, "Two points, flattened");
+
+        // Strokes written as JSON by older versions are still read.
+        let legacy = Stroke { width: 7.0, ..create_test_stroke() };
+        let json = serde_json::to_string(&legacy).unwrap();
+        backend.doc.insert(&list_id, 1, ScalarValue::Str(json.into())).unwrap();
+        backend.sync_stroke_cache();
+        let strokes = backend.get_strokes();
+        assert_eq!(strokes.len(), 2);
+        assert_eq!(strokes[0], Stroke { id: strokes[0].id.clone(), ..create_test_stroke() });
+        assert_eq!(strokes[1].width, 7.0);
+        assert!(!strokes[1].id.is_empty(), "Legacy strokes get their element id");
+
+        backend.apply_intent(Intent::EraseStroke(strokes[1].id.clone())).unwrap();
+        assert_eq!(backend.get_strokes().len(), 1);
+    }
+
+    #[test]
+    fn test_stroke_cache_follows_patches() {
+        let mut a = AutomergeBackend::new();
+        let mut b = AutomergeBackend::new();
+        a.peer_connected("b");
+        b.peer_connected("a");
+        let full_read = |backend: &AutomergeBackend| -> Vec<Stroke> {
+            backend.read_stroke_list().into_iter().filter_map(|(_, stroke)| stroke).collect()
+        };
+
+        for i in 0..3 {
+            a.apply_intent(Intent::Draw(Stroke { width: i as f32, ..create_test_stroke() })).unwrap();
+        }
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert!(!b.stroke_cache_stale);
+        assert_eq!(b.get_strokes(), full_read(&b));
+
+        let id = b.get_strokes()[1].id.clone();
+        b.apply_intent(Intent::EraseStroke(id)).unwrap();
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!(a.get_strokes(), full_read(&a));
+        assert_eq!(b.get_strokes(), a.get_strokes());
+
+        // Points appended to an existing stroke only re-read that stroke.
+        let list_id = b.strokes_list().unwrap();
+        let (_, elem) = b.doc.get(&list_id, 0).unwrap().unwrap();
+        let (_, points) = b.doc.get(&elem, "points").unwrap().unwrap();
+        b.doc.splice(&points, 4, 0, [ScalarValue::Int(30), ScalarValue::Int(30)]).unwrap();
+        b.doc.commit();
+        sync_loop(&mut a, "a", &mut b, "b");
+        assert_eq!(a.get_strokes()[0].points.len(), 3);
+        assert_eq!(a.get_strokes(), full_read(&a));
+
+        a.apply_intent(Intent::Clear).unwrap();
+        a.apply_intent(Intent::Undo).unwrap();
+        assert_eq!(a.get_strokes(), full_read(&a));
+        let data = a.save();
+        b.load(data).unwrap();
+        assert_eq!(b.get_strokes(), full_read(&a));
+    }
+
+    #[test]
+    fn test_updates_carry_stroke_changes() {
+        let mut a = AutomergeBackend::new();
+        a.apply_intent(Intent::Draw(create_test_stroke())).unwrap();
+        let update = a.apply_intent(Intent::Draw(Stroke { width: 2.0, ..create_test_stroke() })).unwrap();
+        let changes = update.stroke_changes.expect("a second stroke is an incremental change");
+        assert_eq!(changes.added, vec![update.strokes[1].clone()]);
+        assert!(changes.removed.is_empty() && changes.changed.is_empty());
+
+        let id = update.strokes[0].id.clone();
+        let update = a.apply_intent(Intent::EraseStroke(id.clone())).unwrap();
+        assert_eq!(update.stroke_changes.unwrap().removed, vec![id]);
+
+        a.apply_intent(Intent::AddPage).unwrap();
+        let update = a.apply_intent(Intent::SwitchPage(0)).unwrap();
+        assert_eq!(update.stroke_changes, None, "Another page is drawn from scratch");
+    }
 }
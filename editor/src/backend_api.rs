@@ -2,7 +2,9 @@
 //! 
 //! Defines the core data structures (`Point`, `Stroke`, `Intent`, `FrontendUpdate`)
 //! and the `DocBackend` trait which abstracts the document synchronization logic.
+//! All data types crossing the boundary implement `Serialize`/`Deserialize`.
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 /// Represents a 2D point with integer coordinates.
 /// Used to define the path of a stroke.
@@ -23,35 +25,399 @@ pub struct Stroke {
     pub color: [u8; 4],
     /// Width (thickness) of the stroke.
     pub width: f32,
+    /// Identifier used by `Intent::EraseStroke`. Left empty when drawing, the
+    /// backend then assigns one.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    /// Shape drawn instead of a freehand line. Shapes hold two points: the
+    /// corners of the area they span (see `Intent::DrawShape`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shape: Option<Shape>,
+}
+
+/// A shape that can be drawn on the canvas besides freehand strokes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Shape {
+    /// Rectangle outline spanning the two corners.
+    Rectangle,
+    /// Ellipse outline inscribed in the rectangle spanning the two corners.
+    Ellipse,
+    /// Straight line between the two points.
+    Line,
+    /// Text label whose top-left corner is the first point. The stroke width
+    /// is used as the font size.
+    Text(String),
+}
+
+/// Number of vertices approximating an ellipse.
+const ELLIPSE_VERTICES: usize = 64;
+
+impl Stroke {
+    /// Returns the vertices of the line to draw: the recorded points of a
+    /// freehand stroke, or the outline of a shape (closed for rectangles and
+    /// ellipses). Text labels have no line and return no vertices.
+    pub fn path(&self) -> Vec<Point> {
+        let (a, b) = match self.points.as_slice() {
+            [a, .., b] => (a.clone(), b.clone()),
+            [a] => (a.clone(), a.clone()),
+            [] => return Vec::new(),
+        };
+        match &self.shape {
+            None => self.points.clone(),
+            Some(Shape::Line) => vec![a, b],
+            Some(Shape::Rectangle) => vec![
+                a.clone(),
+                Point { x: b.x, y: a.y },
+                b.clone(),
+                Point { x: a.x, y: b.y },
+                a,
+            ],
+            Some(Shape::Ellipse) => {
+                let (cx, cy) = ((a.x + b.x) as f32 / 2.0, (a.y + b.y) as f32 / 2.0);
+                let (rx, ry) = ((b.x - a.x).abs() as f32 / 2.0, (b.y - a.y).abs() as f32 / 2.0);
+                (0..=ELLIPSE_VERTICES)
+                    .map(|i| {
+                        let angle = i as f32 / ELLIPSE_VERTICES as f32 * std::f32::consts::TAU;
+                        Point { x: (cx + rx * angle.cos()).round() as i32, y: (cy + ry * angle.sin()).round() as i32 }
+                    })
+                    .collect()
+            }
+            Some(Shape::Text(_)) => Vec::new(),
+        }
+    }
+
+    /// Whether `point` lies within `radius` of the stroke's drawn line, or
+    /// (for text labels) of the area roughly covered by the text.
+    pub fn hit(&self, point: &Point, radius: f32) -> bool {
+        if let (Some(Shape::Text(text)), Some(origin)) = (&self.shape, self.points.first()) {
+            // Assumes glyphs about 0.6 of the font size wide.
+            let longest = text.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+            let (w, h) = (longest as f32 * self.width * 0.6, text.lines().count().max(1) as f32 * self.width);
+            let (dx, dy) = ((point.x - origin.x) as f32, (point.y - origin.y) as f32);
+            return dx >= -radius && dx <= w + radius && dy >= -radius && dy <= h + radius;
+        }
+        let reach = radius + self.width / 2.0;
+        let (px, py) = (point.x as f32, point.y as f32);
+        let distance_to = |a: &Point, b: &Point| {
+            let (ax, ay, bx, by) = (a.x as f32, a.y as f32, b.x as f32, b.y as f32);
+            let (dx, dy) = (bx - ax, by - ay);
+            let len_sq = dx * dx + dy * dy;
+            let t = if len_sq == 0.0 { 0.0 } else { (((px - ax) * dx + (py - ay) * dy) / len_sq).clamp(0.0, 1.0) };
+            ((ax + t * dx - px).powi(2) + (ay + t * dy - py).powi(2)).sqrt()
+        };
+        match self.path().as_slice() {
+            [] => false,
+            [only] => distance_to(only, only) <= reach,
+            points => points.windows(2).any(|pair| distance_to(&pair[0], &pair[1]) <= reach),
+        }
+    }
+}
+
+/// Returns the topmost (most recently drawn) stroke within `radius` of `point`.
+pub fn stroke_at<'a>(strokes: &'a [Stroke], point: &Point, radius: f32) -> Option<&'a Stroke> {
+    strokes.iter().rev().find(|stroke| stroke.hit(point, radius))
+}
+
+/// Intents that turn a page showing `current` into one showing `target`, such
+/// as a past version: strokes missing from `target` are erased and strokes
+/// missing from `current` are drawn again. Strokes are matched by id.
+pub fn restore_intents(current: &[Stroke], target: &[Stroke]) -> Vec<Intent> {
+    let erase = current
+        .iter()
+        .filter(|stroke| !target.iter().any(|t| t.id == stroke.id))
+        .map(|stroke| Intent::EraseStroke(stroke.id.clone()));
+    let draw = target
+        .iter()
+        .filter(|stroke| !current.iter().any(|c| c.id == stroke.id))
+        .map(|stroke| Intent::Draw(stroke.clone()));
+    erase.chain(draw).collect()
+}
+
+/// Identifying information about a collaborator, shown next to their presence.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct UserInfo {
+    /// Display name of the user.
+    pub name: String,
+    /// Color the user is shown in, as [R, G, B, A]. Chosen by the user's own
+    /// client (derived from their identity) so every peer shows the same color.
+    #[serde(default)]
+    pub color: Option<[u8; 4]>,
+}
+
+/// Rectangular canvas area selected by a user, given by two opposite corners.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Selection {
+    /// Corner where the selection started.
+    pub start: Point,
+    /// Opposite corner of the selection.
+    pub end: Point,
+}
+
+impl Selection {
+    /// Whether `point` lies inside the selected area (borders included).
+    pub fn contains(&self, point: &Point) -> bool {
+        let (left, right) = (self.start.x.min(self.end.x), self.start.x.max(self.end.x));
+        let (top, bottom) = (self.start.y.min(self.end.y), self.start.y.max(self.end.y));
+        (left..=right).contains(&point.x) && (top..=bottom).contains(&point.y)
+    }
+}
+
+/// Ephemeral awareness state of a participant.
+/// Not part of the persisted document; exchanged alongside it by the network layer.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Presence {
+    /// Pointer position on the canvas, if the pointer is over it.
+    pub cursor: Option<Point>,
+    /// Currently selected area, if any.
+    pub selection: Option<Selection>,
+    /// Who this presence belongs to.
+    pub user: UserInfo,
+}
+
+/// Presence of a connected remote peer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerPresence {
+    /// Unique identifier of the peer.
+    pub peer_id: String,
+    /// Latest presence reported by the peer.
+    pub presence: Presence,
+}
+
+impl PeerPresence {
+    /// Name to show next to the peer's cursor: the user's name, or the peer id if unset.
+    pub fn display_name(&self) -> &str {
+        if self.presence.user.name.is_empty() {
+            &self.peer_id
+        } else {
+            &self.presence.user.name
+        }
+    }
+}
+
+/// A comment anchored to a rectangular area of the canvas.
+/// Stored in the document, so it syncs and persists like strokes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Comment {
+    /// Unique identifier, assigned by the backend.
+    pub id: String,
+    /// Corner where the anchored area starts.
+    pub start: Point,
+    /// Opposite corner of the anchored area.
+    pub end: Point,
+    /// Comment body.
+    pub text: String,
+    /// Display name of the author (the local user when the comment was added).
+    pub author: String,
+    /// Creation time in milliseconds since the Unix epoch.
+    pub created_at: i64,
+    /// Whether the comment has been resolved.
+    pub resolved: bool,
+    /// Comment this one replies to, the first of its thread. Replies share
+    /// the area of that comment and are resolved along with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
 }
 
 /// Represents a user's intent to modify the document.
-/// Passed from the UI to the backend.
-#[derive(Debug, Clone, PartialEq)]
+/// Passed from the UI to the backend. Serializable, so intents can be
+/// recorded, replayed for debugging or sent to an out-of-process backend.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Intent {
     /// Intent to add a new stroke.
     Draw(Stroke),
     /// Intent to clear the document.
     Clear,
+    /// Intent to remove a single stroke, given by its `Stroke::id`.
+    EraseStroke(String),
+    /// Intent to add a shape, stored as a stroke whose points are `start` and `end`.
+    DrawShape {
+        /// Kind of shape.
+        shape: Shape,
+        /// First corner (for text labels, the top-left corner).
+        start: Point,
+        /// Opposite corner.
+        end: Point,
+        /// Color in [R, G, B, A] format.
+        color: [u8; 4],
+        /// Line width, or font size for text labels.
+        width: f32,
+    },
+    /// Intent to revert the most recent local change.
+    Undo,
+    /// Intent to re-apply the most recently undone local change.
+    Redo,
+    /// Intent to add a comment anchored to the area between two corners.
+    AddComment {
+        /// Corner where the anchored area starts.
+        start: Point,
+        /// Opposite corner of the anchored area.
+        end: Point,
+        /// Comment body.
+        text: String,
+    },
+    /// Intent to reply to a comment, continuing its thread.
+    ReplyComment {
+        /// Identifier of the comment replied to.
+        id: String,
+        /// Reply body.
+        text: String,
+    },
+    /// Intent to mark a comment as resolved.
+    ResolveComment {
+        /// Identifier of the comment.
+        id: String,
+    },
+    /// Intent to insert an empty page after the current one and switch to it.
+    AddPage,
+    /// Intent to show and edit the page at the given index. Only changes the
+    /// local view; the document itself is not modified.
+    SwitchPage(usize),
+    /// Intent to delete the page at the given index, with its strokes and
+    /// background. The first page cannot be deleted.
+    DeletePage(usize),
 }
 
 /// Represents an update to be applied to the frontend/UI.
 /// Returned by the backend after processing an intent or receiving a sync message.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FrontendUpdate {
     /// Current list of strokes to render.
     pub strokes: Vec<Stroke>,
+    /// Current comments, oldest first.
+    pub comments: Vec<Comment>,
+    /// Document fields that peers changed concurrently.
+    #[serde(default)]
+    pub conflicts: Vec<Conflict>,
+    /// How `strokes` differs from the strokes of the previous update, so a
+    /// frontend can redraw only what changed; `None` if not known, e.g. after
+    /// switching pages, when `strokes` has to be drawn from scratch.
+    #[serde(default)]
+    pub stroke_changes: Option<StrokeChanges>,
+}
+
+/// Strokes added, removed or changed between two `FrontendUpdate`s.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct StrokeChanges {
+    /// New strokes, in document order.
+    pub added: Vec<Stroke>,
+    /// Ids of the strokes that were removed.
+    pub removed: Vec<String>,
+    /// Strokes whose content changed, e.g. points appended to them.
+    pub changed: Vec<Stroke>,
+}
+
+/// Concurrent values of a document field, e.g. a title set by two peers at
+/// the same time. Every peer picks the same value to show, but the others are
+/// kept until a later change replaces them all.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Conflict {
+    /// Name of the field, e.g. "title".
+    pub key: String,
+    /// The competing values; the first is the one currently shown.
+    pub values: Vec<String>,
 }
 
 impl FrontendUpdate {
-    /// Creates an empty update with no strokes.
+    /// Creates an empty update with no strokes or comments.
     pub fn empty() -> Self {
         Self {
             strokes: Vec::new(),
+            comments: Vec::new(),
+            conflicts: Vec::new(),
+            stroke_changes: None,
+        }
+    }
+}
+
+/// One change in the document's history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Identifier of the change; pass to `DocBackend::checkout` to view the document as of it.
+    pub id: String,
+    /// Identity of the collaborator who made the change (see
+    /// `DocBackend::set_local_identity`), or the replica id if unknown.
+    pub actor: String,
+    /// Time the change was made, in milliseconds since the Unix epoch (0 if unknown).
+    pub timestamp: i64,
+    /// Description of the change, if any.
+    pub message: Option<String>,
+}
+
+/// Health figures of the collaboration engine, shown in the status bar and debug panel.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct BackendStats {
+    /// Total number of operations in the document history.
+    pub op_count: usize,
+    /// Operations overwritten or deleted by later ones but still kept in the history.
+    pub tombstone_count: usize,
+    /// Number of connected peers.
+    pub peer_count: usize,
+    /// Bytes of changes that connected peers are not yet known to have.
+    pub pending_sync_bytes: usize,
+    /// Approximate memory used by the document, in bytes.
+    pub memory_bytes: usize,
+}
+
+/// Outcome of `DocBackend::compact`, for reporting to the user.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct CompactionReport {
+    /// Number of changes in the history before compaction.
+    pub changes_before: usize,
+    /// Number of changes in the history after compaction.
+    pub changes_after: usize,
+    /// Size of the saved document before compaction, in bytes.
+    pub bytes_before: usize,
+    /// Size of the saved document after compaction, in bytes.
+    pub bytes_after: usize,
+}
+
+/// Changes not yet persisted, from `DocBackend::save_incremental`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct IncrementalSave {
+    /// The serialized changes; empty if nothing changed.
+    pub data: Vec<u8>,
+    /// Version of the document the changes lead to; pass it to
+    /// `DocBackend::mark_saved` once `data` is written.
+    pub version: Vec<String>,
+}
+
+/// Errors reported by a `DocBackend` when an operation cannot be completed.
+///
+/// Every variant carries a human-readable description suitable for the status bar.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BackendError {
+    /// The intent is not valid for the current document state.
+    InvalidIntent(String),
+    /// Document content could not be serialized or deserialized.
+    Serialization(String),
+    /// The underlying CRDT engine rejected an operation.
+    Crdt(String),
+    /// A sync message from a peer could not be decoded or applied.
+    Sync(String),
+    /// Persisted document data could not be loaded.
+    Load(String),
+    /// The backend could not service the request (worker task failed, lock poisoned, remote unreachable).
+    Unavailable(String),
+    /// The document is read-only locally (see `DocBackend::set_read_only`).
+    ReadOnly,
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::InvalidIntent(msg) => write!(f, "Invalid intent: {}", msg),
+            BackendError::Serialization(msg) => write!(f, "Serialization error: {}", msg),
+            BackendError::Crdt(msg) => write!(f, "CRDT error: {}", msg),
+            BackendError::Sync(msg) => write!(f, "Sync error: {}", msg),
+            BackendError::Load(msg) => write!(f, "Load error: {}", msg),
+            BackendError::Unavailable(msg) => write!(f, "Backend unavailable: {}", msg),
+            BackendError::ReadOnly => write!(f, "The document is read-only"),
         }
     }
 }
 
+impl std::error::Error for BackendError {}
+
 /// Trait for document backend management and synchronization.
 ///
 /// Handles CRDT logic, persistence, and network synchronization messages.
@@ -61,10 +427,76 @@ pub trait DocBackend: Send {
     ///
     /// # Arguments
     /// * `intent` - The user's intent (e.g., Draw or Clear).
-    fn apply_intent(&mut self, intent: Intent) -> FrontendUpdate;
+    ///
+    /// # Errors
+    /// Returns a `BackendError` if the intent could not be applied; the document is left unchanged.
+    fn apply_intent(&mut self, intent: Intent) -> Result<FrontendUpdate, BackendError>;
 
     /// Retrieves the current state of strokes from the backend.
     fn get_strokes(&self) -> Vec<Stroke>;
+
+    /// Returns who drew each stroke of the current page, as `(stroke id,
+    /// identity)` pairs in drawing order. Identities are those of
+    /// `HistoryEntry::actor`; after `compact`, every stroke counts as drawn by
+    /// the collaborator who compacted the document.
+    fn stroke_authors(&self) -> Vec<(String, String)>;
+
+    // Pages
+    //
+    // A document holds one or more pages, each with its own strokes and
+    // background. Strokes, `Intent::Clear` and the background always refer to
+    // the current page, which is local state chosen with `Intent::SwitchPage`.
+    // If a peer deletes the current page, the first page becomes current.
+
+    /// Returns the number of pages, at least 1.
+    fn page_count(&self) -> usize;
+
+    /// Returns the index of the current page.
+    fn current_page(&self) -> usize;
+
+    // Transactions
+    //
+    // Changes made between `begin_transaction` and `commit` (intents, title,
+    // background) form a single change: one undo step and one batch offered to
+    // peers. While a transaction is open `generate_sync_message` returns `None`.
+    // Operations that read the change history (`save`, `stats`, incoming sync
+    // messages) may close the pending change early, so transactions should
+    // cover one synchronous UI action.
+
+    /// Starts grouping subsequent local changes into one change.
+    ///
+    /// # Errors
+    /// Returns `BackendError::InvalidIntent` if a transaction is already open.
+    fn begin_transaction(&mut self) -> Result<(), BackendError>;
+
+    /// Closes the open transaction and returns the resulting document state.
+    ///
+    /// # Errors
+    /// Returns `BackendError::InvalidIntent` if no transaction is open.
+    fn commit(&mut self) -> Result<FrontendUpdate, BackendError>;
+
+    /// Retrieves all comments, resolved ones included, oldest first.
+    fn get_comments(&self) -> Vec<Comment>;
+
+    /// Returns the fields currently holding concurrent values. Setting such a
+    /// field again (e.g. with `set_title`) resolves its conflict.
+    fn get_conflicts(&self) -> Vec<Conflict>;
+
+    // Undo / Redo
+    //
+    // `Intent::Undo` reverts the most recent *local* change and `Intent::Redo`
+    // re-applies the most recently undone one. Remote changes are never undone.
+    // Any new local edit clears the redo history. When there is nothing to
+    // undo/redo (or the backend keeps no history) the intent is a no-op and the
+    // returned update reflects the unchanged document. Undo and redo are
+    // refused during a transaction, and a step that fails changes nothing and
+    // stays available.
+
+    /// Returns `true` if an `Intent::Undo` would change the document.
+    fn can_undo(&self) -> bool;
+
+    /// Returns `true` if an `Intent::Redo` would change the document.
+    fn can_redo(&self) -> bool;
     
     // Sync methods
 
@@ -75,11 +507,36 @@ pub trait DocBackend: Send {
     fn peer_connected(&mut self, peer_id: &str);
 
     /// Notification that a peer has disconnected.
+    /// Also drops the peer's presence.
     ///
     /// # Arguments
     /// * `peer_id` - Unique identifier of the disconnected peer.
     fn peer_disconnected(&mut self, peer_id: &str);
 
+    // Presence
+
+    /// Updates the local user's presence, to be broadcast by the network layer.
+    ///
+    /// # Arguments
+    /// * `cursor` - Pointer position on the canvas, if any.
+    /// * `selection` - Selected area, if any.
+    /// * `user` - Identifying information of the local user.
+    fn set_local_presence(&mut self, cursor: Option<Point>, selection: Option<Selection>, user: UserInfo);
+
+    /// Returns the local user's presence as last set by `set_local_presence`.
+    fn local_presence(&self) -> Presence;
+
+    /// Records presence received from a peer.
+    /// Presence from peers that are not connected (see `peer_connected`) is ignored.
+    ///
+    /// # Arguments
+    /// * `peer_id` - Identifier of the sender.
+    /// * `presence` - The peer's latest presence.
+    fn update_peer_presence(&mut self, peer_id: &str, presence: Presence);
+
+    /// Returns all connected peers with their latest presence, ordered by peer id.
+    fn peers(&self) -> Vec<PeerPresence>;
+
     /// Processes an incoming synchronization message from a peer.
     ///
     /// # Arguments
@@ -87,8 +544,80 @@ pub trait DocBackend: Send {
     /// * `message` - The raw byte data of the message.
     ///
     /// # Returns
-    /// An update to reflect any changes in the document state.
-    fn receive_sync_message(&mut self, peer_id: &str, message: Vec<u8>) -> FrontendUpdate;
+    /// An update to reflect any changes in the document state, or a
+    /// `BackendError::Sync` if the message is malformed.
+    fn receive_sync_message(&mut self, peer_id: &str, message: Vec<u8>) -> Result<FrontendUpdate, BackendError>;
+
+    // Access
+
+    /// Makes the document read-only locally, or editable again. While read-only,
+    /// intents other than `Intent::SwitchPage` fail with `BackendError::ReadOnly`,
+    /// as do `set_title`, `set_background` and `merge`; changes from peers still apply.
+    fn set_read_only(&mut self, read_only: bool);
+
+    /// Returns `true` while the document is read-only locally.
+    fn is_read_only(&self) -> bool;
+
+    // History
+
+    /// Identifies the local collaborator (e.g. by their LiveKit identity), so that
+    /// subsequent local changes are attributed to them in `history`.
+    fn set_local_identity(&mut self, identity: &str);
+
+    /// Returns the document's change history, oldest first. Every change comes
+    /// after the changes it depends on.
+    fn history(&mut self) -> Vec<HistoryEntry>;
+
+    /// Materializes the document as it was at a past version, given by the ids
+    /// of one or more `HistoryEntry` values (the latest changes to include).
+    /// The current document is not modified.
+    ///
+    /// # Errors
+    /// Returns `BackendError::InvalidIntent` if an id is not part of the history.
+    fn checkout(&mut self, version: &[String]) -> Result<FrontendUpdate, BackendError>;
+
+    /// Computes statistics about the document and its synchronization.
+    /// May be expensive on large documents; callers should not poll it every frame.
+    fn stats(&mut self) -> BackendStats;
+
+    /// Creates an independent copy of the document that shares its history, so
+    /// edits made on either side can later be combined with `merge`.
+    ///
+    /// # Errors
+    /// Returns `BackendError::InvalidIntent` while a transaction is open.
+    fn fork(&mut self) -> Result<Box<dyn DocBackend>, BackendError>;
+
+    /// Merges the changes of `other` (typically a `fork` of this document)
+    /// into this document and returns the combined state. The content of
+    /// `other` is not modified.
+    ///
+    /// # Errors
+    /// Returns `BackendError::Load` if `other` uses an incompatible engine.
+    fn merge(&mut self, other: &mut dyn DocBackend) -> Result<FrontendUpdate, BackendError>;
+
+    /// Discards the whole change history once it holds more than `threshold`
+    /// changes, keeping only the current content, rewritten as a fresh change.
+    /// Smaller histories are left alone, and the report then shows no difference.
+    ///
+    /// Compaction is local: afterwards `history` starts over, undo is reset and
+    /// earlier versions can no longer be checked out. The result is in effect a
+    /// fork: a peer or file still holding the old history would merge the
+    /// rewritten content as new and duplicate it, so compaction suits documents
+    /// that are not shared, or that continue under a new `document_id`.
+    ///
+    /// # Errors
+    /// Returns `BackendError::InvalidIntent` while peers are connected or a
+    /// transaction is open.
+    fn compact(&mut self, threshold: usize) -> Result<CompactionReport, BackendError>;
+
+    /// Subscribes to document changes merged from peers.
+    ///
+    /// Every `receive_sync_message` that alters the document pushes the new
+    /// state to all live receivers, so the UI can redraw without having to be
+    /// the caller that applied the change. Local intents are not pushed; their
+    /// update is the return value of `apply_intent`. Dropped receivers are
+    /// forgotten on the next notification.
+    fn subscribe(&mut self) -> tokio::sync::mpsc::UnboundedReceiver<FrontendUpdate>;
 
     /// Generates a synchronization message to be sent to a specific peer.
     ///
@@ -101,14 +630,67 @@ pub trait DocBackend: Send {
 
     // Persistence
 
-    /// Serializes the entire document state to bytes for saving.
+    /// Serializes the entire document state to bytes.
+    ///
+    /// Exporting the document (to a peer, a file being written...) does not
+    /// count as saving it: what `save_incremental` returns is unaffected.
     fn save(&mut self) -> Vec<u8>;
 
+    /// Serializes only the changes made since the version last passed to
+    /// `mark_saved`, or since `load`. The data is empty if nothing changed.
+    ///
+    /// The output can be appended to previously saved data: the concatenation
+    /// is accepted by `load`. The same changes are returned again until their
+    /// `version` is passed to `mark_saved`, so a failed write loses nothing.
+    ///
+    /// # Errors
+    /// Returns `BackendError::InvalidIntent` while a transaction is open, as
+    /// saving would close its change early.
+    fn save_incremental(&mut self) -> Result<IncrementalSave, BackendError>;
+
+    /// Returns the current version of the document: the ids of its latest
+    /// changes, as accepted by `checkout`. Closes the pending change, like `save`.
+    fn version(&mut self) -> Vec<String>;
+
+    /// Records that the document up to `version` (from `save_incremental` or
+    /// `version`) has been persisted, so `save_incremental` starts after it.
+    fn mark_saved(&mut self, version: &[String]);
+
     /// Loads the document state from serialized bytes.
     ///
     /// # Arguments
-    /// * `data` - The byte data to load.
-    fn load(&mut self, data: Vec<u8>);
+    /// * `data` - The byte data to load: a `save` output, optionally followed
+    ///   by `save_incremental` outputs.
+    ///
+    /// # Errors
+    /// Returns `BackendError::Load` if the data is not a valid document; the current document is kept.
+    fn load(&mut self, data: Vec<u8>) -> Result<(), BackendError>;
+
+    // Metadata
+
+    /// Returns the document title, or an empty string if none was set.
+    fn title(&self) -> String;
+
+    /// Sets the document title. The title is part of the document and syncs to peers.
+    ///
+    /// # Arguments
+    /// * `title` - The new title.
+    fn set_title(&mut self, title: &str) -> Result<(), BackendError>;
+
+    /// Time of the last change to the document (local or received from a peer),
+    /// in milliseconds since the Unix epoch. `None` if the document was never modified.
+    fn modified_at(&self) -> Option<i64>;
+
+    /// Identifier of the document, shared with peers like the title, or `None`
+    /// until one is assigned. Collaboration on the document happens in the
+    /// room named after it (see `protocol::room_for_document`).
+    fn document_id(&self) -> Option<String>;
+
+    /// Assigns the document's identifier. Like the title, it syncs to peers.
+    ///
+    /// # Arguments
+    /// * `id` - The new identifier.
+    fn set_document_id(&mut self, id: &str) -> Result<(), BackendError>;
 
     // Background
 
@@ -116,8 +698,162 @@ pub trait DocBackend: Send {
     ///
     /// # Arguments
     /// * `data` - Raw bytes of the background image (e.g., PNG/JPEG data).
-    fn set_background(&mut self, data: Vec<u8>);
+    fn set_background(&mut self, data: Vec<u8>) -> Result<(), BackendError>;
 
-    /// Retrieves the current background image data.
+    /// Retrieves the current background image data, or `None` if there is no
+    /// complete image (e.g. it is still arriving from a peer).
     fn get_background(&self) -> Option<Vec<u8>>;
-}
\ No newline at end of file
+}
+
+/// Identifier of a document managed by a `DocStore`.
+pub type DocId = String;
+
+/// Manager owning several open documents, each backed by its own `DocBackend`.
+///
+/// Gives the sidebar, tabs and network multiplexing a single place to look
+/// documents up by id.
+pub trait DocStore: Send {
+    /// Creates a new empty document and returns its id.
+    fn create(&mut self) -> DocId;
+
+    /// Opens a document from serialized data (as produced by `DocBackend::save`).
+    ///
+    /// # Errors
+    /// Returns `BackendError::Load` if the data is not a valid document.
+    fn open(&mut self, data: Vec<u8>) -> Result<DocId, BackendError>;
+
+    /// Closes a document and hands back its backend (e.g. to save it).
+    /// Returns `None` if no document with this id is open.
+    fn close(&mut self, id: &str) -> Option<Box<dyn DocBackend>>;
+
+    /// Lists ids of open documents in the order they were opened.
+    fn list(&self) -> Vec<DocId>;
+
+    /// Returns the backend of an open document.
+    fn get(&self, id: &str) -> Option<&dyn DocBackend>;
+
+    /// Returns the backend of an open document for modification.
+    fn get_mut(&mut self, id: &str) -> Option<&mut Box<dyn DocBackend>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intents_roundtrip_through_json() {
+        let intents = vec![
+            Intent::Draw(Stroke {
+                points: vec![Point { x: 1, y: 2 }, Point { x: 3, y: 4 }],
+                color: [1, 2, 3, 255],
+                width: 2.5,
+                id: String::new(),
+                shape: None,
+            }),
+            Intent::Clear,
+            Intent::Undo,
+            Intent::Redo,
+            Intent::AddComment {
+                start: Point { x: 0, y: 0 },
+                end: Point { x: 5, y: 5 },
+                text: "here".into(),
+            },
+            Intent::ResolveComment { id: "abc".into() },
+        ];
+
+        // One intent per line, as in a recorded session.
+        let log: Vec<String> = intents.iter().map(|i| serde_json::to_string(i).unwrap()).collect();
+        let replayed: Vec<Intent> = log.iter().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(replayed, intents);
+    }
+
+    #[test]
+    fn test_update_and_error_roundtrip_through_json() {
+        let update = FrontendUpdate {
+            strokes: vec![Stroke { points: vec![Point { x: 7, y: 8 }], color: [0, 0, 0, 255], width: 1.0, id: String::new(), shape: None }],
+            comments: Vec::new(),
+            conflicts: vec![Conflict { key: "title".into(), values: vec!["A".into(), "B".into()] }],
+            stroke_changes: Some(StrokeChanges { removed: vec!["s1".into()], ..StrokeChanges::default() }),
+        };
+        let json = serde_json::to_string(&update).unwrap();
+        assert_eq!(serde_json::from_str::<FrontendUpdate>(&json).unwrap(), update);
+
+        let error = BackendError::Sync("bad message".into());
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(serde_json::from_str::<BackendError>(&json).unwrap(), error);
+    }
+
+    #[test]
+    fn test_stroke_hit_testing() {
+        let line = Stroke {
+            points: vec![Point { x: 0, y: 0 }, Point { x: 100, y: 0 }],
+            color: [0, 0, 0, 255],
+            width: 4.0,
+            id: "line".into(),
+            shape: None,
+        };
+        let dot = Stroke { points: vec![Point { x: 50, y: 1 }], color: [0, 0, 0, 255], width: 2.0, id: "dot".into(), shape: None };
+
+        assert!(line.hit(&Point { x: 50, y: 5 }, 3.0), "Within radius plus half the width");
+        assert!(!line.hit(&Point { x: 50, y: 6 }, 3.0));
+        assert!(!line.hit(&Point { x: 110, y: 0 }, 3.0), "Beyond the end point");
+
+        let strokes = vec![line, dot];
+        assert_eq!(stroke_at(&strokes, &Point { x: 50, y: 0 }, 1.0).unwrap().id, "dot", "Topmost stroke wins");
+        assert_eq!(stroke_at(&strokes, &Point { x: 10, y: 0 }, 1.0).unwrap().id, "line");
+        assert!(stroke_at(&strokes, &Point { x: 10, y: 50 }, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_shape_paths_and_hit_testing() {
+        let shape = |shape: Shape| Stroke {
+            points: vec![Point { x: 0, y: 0 }, Point { x: 100, y: 50 }],
+            color: [0, 0, 0, 255],
+            width: 2.0,
+            id: String::new(),
+            shape: Some(shape),
+        };
+        let rectangle = shape(Shape::Rectangle);
+        assert_eq!(rectangle.path().len(), 5);
+        assert_eq!(rectangle.path().first(), rectangle.path().last(), "Outline is closed");
+        assert!(rectangle.hit(&Point { x: 100, y: 25 }, 1.0), "On the right edge");
+        assert!(!rectangle.hit(&Point { x: 50, y: 25 }, 1.0), "Only the outline is drawn");
+
+        let ellipse = shape(Shape::Ellipse);
+        assert!(ellipse.hit(&Point { x: 50, y: 0 }, 1.0), "Touches the top edge");
+        assert!(!ellipse.hit(&Point { x: 0, y: 0 }, 1.0), "Misses the corner");
+
+        assert_eq!(shape(Shape::Line).path(), vec![Point { x: 0, y: 0 }, Point { x: 100, y: 50 }]);
+
+        let label = Stroke { width: 10.0, ..shape(Shape::Text("Hello".into())) };
+        assert!(label.path().is_empty());
+        assert!(label.hit(&Point { x: 20, y: 5 }, 0.0));
+        assert!(!label.hit(&Point { x: 50, y: 5 }, 0.0), "Past the end of the text");
+
+        let note = Stroke { width: 10.0, ..shape(Shape::Text("Hello\nworld".into())) };
+        assert!(note.hit(&Point { x: 20, y: 15 }, 0.0), "On the second line");
+        assert!(!note.hit(&Point { x: 20, y: 25 }, 0.0));
+
+        let area = Selection { start: Point { x: 100, y: 60 }, end: Point { x: 0, y: 0 } };
+        assert!(rectangle.points.iter().all(|p| area.contains(p)), "Corners may be given in any order");
+        assert!(!area.contains(&Point { x: 101, y: 0 }));
+    }
+
+    #[test]
+    fn test_restore_intents_erase_and_redraw_by_id() {
+        let stroke = |id: &str| Stroke {
+            points: vec![Point { x: 0, y: 0 }],
+            color: [0, 0, 0, 255],
+            width: 1.0,
+            id: id.into(),
+            shape: None,
+        };
+        let current = vec![stroke("kept"), stroke("added later")];
+        let past = vec![stroke("erased later"), stroke("kept")];
+        assert_eq!(
+            restore_intents(&current, &past),
+            vec![Intent::EraseStroke("added later".into()), Intent::Draw(stroke("erased later"))]
+        );
+        assert!(restore_intents(&past, &past).is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+//! Registry of the available `DocBackend` implementations, selectable by name.
+use std::collections::{HashMap, HashSet};
+
+use crate::automerge_backend::AutomergeBackend;
+use crate::backend_api::{BackendError, Comment, DocBackend, Intent, UserInfo};
+
+/// A backend that can be selected at startup (`--backend=<name>`) or at runtime.
+pub struct BackendEntry {
+    /// Name used on the command line and in the UI.
+    pub name: &'static str,
+    /// Short human-readable description.
+    pub description: &'static str,
+    /// Constructs a new, empty instance of the backend.
+    constructor: fn() -> Box<dyn DocBackend>,
+}
+
+/// Name of the backend used when none is requested.
+pub const DEFAULT_BACKEND: &str = "automerge";
+
+/// All registered backends.
+pub const BACKENDS: &[BackendEntry] = &[BackendEntry {
+    name: "automerge",
+    description: "Automerge CRDT document",
+    constructor: || Box::new(AutomergeBackend::new()),
+}];
+
+/// Returns the names of all registered backends.
+pub fn names() -> Vec<&'static str> {
+    BACKENDS.iter().map(|b| b.name).collect()
+}
+
+/// Constructs a new, empty backend by name.
+///
+/// # Errors
+/// Returns `BackendError::Unavailable` if no backend with this name is registered.
+pub fn create(name: &str) -> Result<Box<dyn DocBackend>, BackendError> {
+    BACKENDS
+        .iter()
+        .find(|b| b.name == name)
+        .map(|b| (b.constructor)())
+        .ok_or_else(|| {
+            BackendError::Unavailable(format!(
+                "unknown backend '{}' (available: {})",
+                name,
+                names().join(", ")
+            ))
+        })
+}
+
+/// Copies the document content (every page's strokes and background, the
+/// comments, the title and the document id) of `from` into `to`.
+///
+/// `to` is expected to be empty. The content is replayed as local edits, so it
+/// works between backends with incompatible storage formats; edit history and
+/// sync state are not carried over, and comments get new ids and creation
+/// times. `from` is left on the page it was on.
+pub fn migrate(from: &mut dyn DocBackend, to: &mut dyn DocBackend) -> Result<(), BackendError> {
+    let current = from.current_page();
+    for page in 0..from.page_count() {
+        from.apply_intent(Intent::SwitchPage(page))?;
+        if page > 0 {
+            to.apply_intent(Intent::AddPage)?;
+        }
+        for stroke in from.get_strokes() {
+            to.apply_intent(Intent::Draw(stroke))?;
+        }
+        if let Some(background) = from.get_background() {
+            to.set_background(background)?;
+        }
+    }
+    from.apply_intent(Intent::SwitchPage(current))?;
+    to.apply_intent(Intent::SwitchPage(current))?;
+    migrate_comments(from, to)?;
+    let title = from.title();
+    if !title.is_empty() {
+        to.set_title(&title)?;
+    }
+    if let Some(id) = from.document_id() {
+        to.set_document_id(&id)?;
+    }
+    Ok(())
+}
+
+/// Replays the comments of `from` into `to`, keeping their authors, threads
+/// and resolved state.
+fn migrate_comments(from: &dyn DocBackend, to: &mut dyn DocBackend) -> Result<(), BackendError> {
+    let presence = to.local_presence();
+    // Threads first, so every reply finds the comment it replies to.
+    let (threads, replies): (Vec<Comment>, Vec<Comment>) =
+        from.get_comments().into_iter().partition(|c| c.reply_to.is_none());
+    // Ids in `from` -> ids the comments got in `to`.
+    let mut ids: HashMap<String, String> = HashMap::new();
+    for comment in threads.iter().chain(&replies) {
+        let intent = match comment.reply_to.as_ref().and_then(|thread| ids.get(thread)) {
+            Some(thread) => Intent::ReplyComment { id: thread.clone(), text: comment.text.clone() },
+            // A reply whose thread is gone is kept as a comment of its own.
+            None => Intent::AddComment {
+                start: comment.start.clone(),
+                end: comment.end.clone(),
+                text: comment.text.clone(),
+            },
+        };
+        // Backends take the author of a new comment from the local presence.
+        let user = UserInfo { name: comment.author.clone(), color: presence.user.color };
+        to.set_local_presence(None, None, user);
+        let known: HashSet<String> = to.get_comments().into_iter().map(|c| c.id).collect();
+        to.apply_intent(intent)?;
+        let Some(added) = to.get_comments().into_iter().find(|c| !known.contains(&c.id)) else {
+            continue;
+        };
+        if comment.resolved {
+            to.apply_intent(Intent::ResolveComment { id: added.id.clone() })?;
+        }
+        ids.insert(comment.id.clone(), added.id);
+    }
+    to.set_local_presence(presence.cursor, presence.selection, presence.user);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend_api::{Point, Stroke};
+
+    #[test]
+    fn test_create_by_name() {
+        assert!(create(DEFAULT_BACKEND).is_ok());
+        assert!(matches!(create("nope"), Err(BackendError::Unavailable(_))));
+    }
+
+    #[test]
+    fn test_migrate_copies_content() {
+        let stroke = |x| {
+            Intent::Draw(Stroke {
+                points: vec![Point { x, y: 2 }, Point { x: 3, y: 4 }],
+                color: [255, 0, 0, 255],
+                width: 2.0,
+                id: String::new(),
+                shape: None,
+            })
+        };
+        let mut from = create(DEFAULT_BACKEND).unwrap();
+        from.apply_intent(stroke(1)).unwrap();
+        from.set_background(vec![1, 2, 3]).unwrap();
+        from.apply_intent(Intent::AddPage).unwrap();
+        from.apply_intent(stroke(5)).unwrap();
+        from.set_background(vec![4, 5]).unwrap();
+        from.apply_intent(Intent::AddComment {
+            start: Point { x: 0, y: 0 },
+            end: Point { x: 5, y: 5 },
+            text: "here".into(),
+        })
+        .unwrap();
+        let thread = from.get_comments()[0].id.clone();
+        let author = from.get_comments()[0].author.clone();
+        from.apply_intent(Intent::ReplyComment { id: thread.clone(), text: "agreed".into() }).unwrap();
+        from.apply_intent(Intent::ResolveComment { id: thread }).unwrap();
+        from.set_title("Sketch").unwrap();
+        from.set_document_id("doc-1").unwrap();
+        from.apply_intent(Intent::SwitchPage(0)).unwrap();
+
+        let mut to = create(DEFAULT_BACKEND).unwrap();
+        migrate(from.as_mut(), to.as_mut()).unwrap();
+
+        assert_eq!(from.current_page(), 0);
+        assert_eq!(to.page_count(), 2);
+        assert_eq!(to.current_page(), 0);
+        for page in 0..2 {
+            from.apply_intent(Intent::SwitchPage(page)).unwrap();
+            to.apply_intent(Intent::SwitchPage(page)).unwrap();
+            assert_eq!(to.get_strokes(), from.get_strokes());
+            assert_eq!(to.get_background(), from.get_background());
+        }
+        let comments = to.get_comments();
+        assert_eq!(comments.len(), 2);
+        // Comments added in the same millisecond are ordered by their random ids.
+        let thread = comments.iter().find(|c| c.reply_to.is_none()).unwrap();
+        let reply = comments.iter().find(|c| c.reply_to.is_some()).unwrap();
+        assert_eq!((thread.text.as_str(), thread.resolved, thread.reply_to.as_ref()), ("here", true, None));
+        assert_eq!((reply.text.as_str(), reply.reply_to.as_ref()), ("agreed", Some(&thread.id)));
+        assert_eq!(thread.author, author);
+        assert_eq!(to.title(), "Sketch");
+        assert_eq!(to.document_id().as_deref(), Some("doc-1"));
+    }
+}
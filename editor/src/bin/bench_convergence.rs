@@ -23,6 +23,8 @@ fn generate_stroke(peer: usize, i: usize) -> Stroke {
         ],
         color: [(peer * 60 % 256) as u8, (i % 256) as u8, 128, 255],
         width: 2.0 + (peer % 5) as f32,
+        id: String::new(),
+        shape: None,
     }
 }
 
@@ -34,10 +36,10 @@ fn sync_loop(a: &mut AutomergeBackend, a_label: &str, b: &mut AutomergeBackend,
             break;
         }
         if let Some(m) = msg_ab {
-            b.receive_sync_message(a_label, m);
+            b.receive_sync_message(a_label, m).expect("Failed to apply sync message");
         }
         if let Some(m) = msg_ba {
-            a.receive_sync_message(b_label, m);
+            a.receive_sync_message(b_label, m).expect("Failed to apply sync message");
         }
     }
 }
@@ -53,12 +55,12 @@ fn run_star_sync(peers: &mut [AutomergeBackend], labels: &[String]) {
 
             // hub → spoke
             if let Some(m) = hub.generate_sync_message(&labels[i]) {
-                spoke.receive_sync_message(&labels[0], m);
+                spoke.receive_sync_message(&labels[0], m).expect("Failed to apply sync message");
                 any_msg = true;
             }
             // spoke → hub
             if let Some(m) = spoke.generate_sync_message(&labels[0]) {
-                hub.receive_sync_message(&labels[i], m);
+                hub.receive_sync_message(&labels[i], m).expect("Failed to apply sync message");
                 any_msg = true;
             }
         }
@@ -87,14 +89,14 @@ fn main() {
         }
 
         // Seed shared strokes list via hub
-        peers[0].apply_intent(Intent::Draw(generate_stroke(0, 0)));
+        peers[0].apply_intent(Intent::Draw(generate_stroke(0, 0))).expect("Failed to apply intent");
         run_star_sync(&mut peers, &labels);
 
         // Each peer draws STROKES_PER_PEER strokes concurrently (no sync between draws)
         let draw_start = Instant::now();
         for p in 0..num_peers {
             for i in 1..=STROKES_PER_PEER {
-                peers[p].apply_intent(Intent::Draw(generate_stroke(p, i)));
+                peers[p].apply_intent(Intent::Draw(generate_stroke(p, i))).expect("Failed to apply intent");
             }
         }
         let draw_time = draw_start.elapsed();
@@ -146,15 +148,15 @@ fn main() {
 
     // Seed 100 strokes
     for i in 0..100 {
-        hub.apply_intent(Intent::Draw(generate_stroke(0, i)));
+        hub.apply_intent(Intent::Draw(generate_stroke(0, i))).expect("Failed to apply intent");
     }
     sync_loop(&mut hub, "hub", &mut spoke, "spoke");
     println!("Initial strokes on both: {}", hub.get_strokes().len());
 
     // Hub clears, spoke draws 20 new strokes concurrently
-    hub.apply_intent(Intent::Clear);
+    hub.apply_intent(Intent::Clear).expect("Failed to apply intent");
     for i in 0..20 {
-        spoke.apply_intent(Intent::Draw(generate_stroke(1, 1000 + i)));
+        spoke.apply_intent(Intent::Draw(generate_stroke(1, 1000 + i))).expect("Failed to apply intent");
     }
 
     // Sync
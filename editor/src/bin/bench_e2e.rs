@@ -13,57 +13,21 @@
 //! Terminal 2 (sender — start after receiver is connected):
 //!   cargo run --release --bin bench_e2e -- sender <room_name> [trials] [delay_ms]
 //!
-//! Requires .env with LIVEKIT_URL, LIVEKIT_API_KEY, LIVEKIT_API_SECRET.
+//! Requires .env with LIVEKIT_URL and either LIVEKIT_TOKEN_URL (token server)
+//! or LIVEKIT_API_KEY, LIVEKIT_API_SECRET (local tokens, development only).
 
 use collaboratite_editor::automerge_backend::AutomergeBackend;
 use collaboratite_editor::backend_api::{DocBackend, Intent, Point, Stroke};
+use collaboratite_editor::protocol::{self, NetworkMessage, Reassembler};
+use collaboratite_editor::token::{Role, TokenSource};
 
 use livekit::prelude::*;
-use livekit_api::access_token;
-use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-
-// ---- protocol types (mirrors ui.rs) ----------------------------------------
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-enum TransportPacket {
-    Message(Vec<u8>),
-    Chunk {
-        id: u64,
-        index: u32,
-        total: u32,
-        data: Vec<u8>,
-    },
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-enum NetworkMessage {
-    Sync(Vec<u8>),
-    Chat(String),
-    Cursor { x: i32, y: i32 },
-}
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 // ---- helpers ---------------------------------------------------------------
 
-fn create_token(room: &str, identity: &str) -> String {
-    let api_key = std::env::var("LIVEKIT_API_KEY").expect("LIVEKIT_API_KEY not set");
-    let api_secret = std::env::var("LIVEKIT_API_SECRET").expect("LIVEKIT_API_SECRET not set");
-    access_token::AccessToken::with_api_key(&api_key, &api_secret)
-        .with_identity(identity)
-        .with_name(identity)
-        .with_grants(access_token::VideoGrants {
-            room_join: true,
-            room: room.to_string(),
-            can_publish: true,
-            can_publish_data: true,
-            ..Default::default()
-        })
-        .to_jwt()
-        .expect("Failed to create token")
-}
-
 fn livekit_url() -> String {
     let host = std::env::var("LIVEKIT_URL").expect("LIVEKIT_URL not set");
     if host.starts_with("ws://") || host.starts_with("wss://") {
@@ -107,93 +71,51 @@ fn generate_stroke(i: usize) -> Stroke {
             255,
         ],
         width: 2.0 + (i % 10) as f32,
+        id: String::new(),
+        shape: None,
     }
 }
 
-/// Publish a NetworkMessage via LiveKit data channel (broadcast), with chunking for >14KB.
+/// Publish a NetworkMessage via LiveKit data channel (broadcast), split into fragments if large.
 async fn publish_msg(room: &Room, msg: &NetworkMessage) {
     publish_msg_inner(room, msg, Vec::new()).await;
 }
 
-/// Publish a NetworkMessage to a specific participant (directed), with chunking for >14KB.
+/// Publish a NetworkMessage to a specific participant (directed), split into fragments if large.
 async fn publish_msg_to(room: &Room, msg: &NetworkMessage, identity: &str) {
     let dest: Vec<ParticipantIdentity> = vec![identity.to_string().into()];
     publish_msg_inner(room, msg, dest).await;
 }
 
 async fn publish_msg_inner(room: &Room, msg: &NetworkMessage, destination_identities: Vec<ParticipantIdentity>) {
-    let data = serde_json::to_vec(msg).unwrap();
-    let chunk_size = 14_000;
-    let chunks_count = (data.len() + chunk_size - 1) / chunk_size;
-
-    if chunks_count <= 1 {
-        let packet = TransportPacket::Message(data);
-        let payload = serde_json::to_vec(&packet).unwrap();
+    // The editor identifies the shared document by the room name.
+    let packets = protocol::encode(msg, protocol::document_for_room(&room.name()));
+    // Delivered as the editor delivers it with its default settings.
+    let reliable = protocol::DeliveryPolicy::default().is_reliable(msg, packets.len());
+    for payload in packets {
         let _ = room
             .local_participant()
             .publish_data(DataPacket {
                 payload,
-                reliable: true,
+                reliable,
+                topic: Some(msg.topic().to_string()),
                 destination_identities: destination_identities.clone(),
-                ..Default::default()
             })
             .await;
-    } else {
-        let id: u64 = rand::random();
-        for (i, chunk) in data.chunks(chunk_size).enumerate() {
-            let packet = TransportPacket::Chunk {
-                id,
-                index: i as u32,
-                total: chunks_count as u32,
-                data: chunk.to_vec(),
-            };
-            let payload = serde_json::to_vec(&packet).unwrap();
-            let _ = room
-                .local_participant()
-                .publish_data(DataPacket {
-                    payload,
-                    reliable: true,
-                    destination_identities: destination_identities.clone(),
-                    ..Default::default()
-                })
-                .await;
-        }
     }
 }
 
-/// Decode a raw LiveKit payload into a NetworkMessage (handles chunking).
-fn decode_payload(
-    transfers: &mut HashMap<u64, (u32, Vec<Option<Vec<u8>>>)>,
-    payload: &[u8],
-) -> Option<NetworkMessage> {
-    match serde_json::from_slice::<TransportPacket>(payload) {
-        Ok(TransportPacket::Message(data)) => serde_json::from_slice(&data).ok(),
-        Ok(TransportPacket::Chunk {
-            id,
-            index,
-            total,
-            data,
-        }) => {
-            let entry = transfers
-                .entry(id)
-                .or_insert_with(|| (0, vec![None; total as usize]));
-            if (index as usize) < entry.1.len() && entry.1[index as usize].is_none() {
-                entry.1[index as usize] = Some(data);
-                entry.0 += 1;
-            }
-            if entry.0 == total {
-                let full: Vec<u8> = entry
-                    .1
-                    .iter()
-                    .flat_map(|c| c.as_ref().unwrap().clone())
-                    .collect();
-                transfers.remove(&id);
-                serde_json::from_slice(&full).ok()
-            } else {
-                None
-            }
+/// Decode a raw LiveKit payload into a NetworkMessage (handles fragments).
+fn decode_payload(reassembler: &mut Reassembler, sender: &str, payload: &[u8]) -> Option<NetworkMessage> {
+    for peer in reassembler.expire(Instant::now()) {
+        eprintln!("Dropped an incomplete message from {}", peer);
+    }
+    match reassembler.receive(sender, payload) {
+        Ok(incoming) => incoming.map(|incoming| incoming.message),
+        Err(e) => {
+            eprintln!("Ignoring packet from {}: {}", sender, e);
+            None
         }
-        Err(_) => serde_json::from_slice::<NetworkMessage>(payload).ok(),
     }
 }
 
@@ -205,7 +127,7 @@ async fn run_sender(room_name: &str, trials: usize, delay_ms: u64, suffix: Optio
         Some(s) => format!("bench_sender_{}", s),
         None => "bench_sender".to_string(),
     };
-    let token = create_token(room_name, &identity);
+    let token = TokenSource::from_env().fetch(room_name, &identity, Role::Editor).await.expect("Failed to get a token");
 
     println!("=== E2E Benchmark — SENDER ===");
     println!("  Server:  {}", url);
@@ -226,7 +148,7 @@ async fn run_sender(room_name: &str, trials: usize, delay_ms: u64, suffix: Optio
     println!("[sender] Connected!");
 
     let mut backend = AutomergeBackend::new();
-    let mut transfers: HashMap<String, HashMap<u64, (u32, Vec<Option<Vec<u8>>>)>> = HashMap::new();
+    let mut reassembler = Reassembler::default();
 
     // Register already-present peers
     for (_, p) in room.remote_participants() {
@@ -257,7 +179,7 @@ async fn run_sender(room_name: &str, trials: usize, delay_ms: u64, suffix: Optio
 
     // Seed shared strokes list + initial sync
     println!("[sender] Seeding initial stroke...");
-    backend.apply_intent(Intent::Draw(generate_stroke(0)));
+    backend.apply_intent(Intent::Draw(generate_stroke(0))).expect("Failed to apply intent");
     for (_, p) in room.remote_participants() {
         let pid = p.identity().to_string();
         if let Some(msg) = backend.generate_sync_message(&pid) {
@@ -275,9 +197,8 @@ async fn run_sender(room_name: &str, trials: usize, delay_ms: u64, suffix: Optio
                     Some(RoomEvent::DataReceived { payload, participant, .. }) => {
                         if let Some(p) = participant {
                             let sid = p.identity().to_string();
-                            let t = transfers.entry(sid.clone()).or_default();
-                            if let Some(NetworkMessage::Sync(data)) = decode_payload(t, &payload) {
-                                backend.receive_sync_message(&sid, data);
+                            if let Some(NetworkMessage::Sync(data)) = decode_payload(&mut reassembler, &sid, &payload) {
+                                backend.receive_sync_message(&sid, data).ok();
                                 if let Some(reply) = backend.generate_sync_message(&sid) {
                                     publish_msg_to(&room, &NetworkMessage::Sync(reply), &sid).await;
                                 }
@@ -305,7 +226,7 @@ async fn run_sender(room_name: &str, trials: usize, delay_ms: u64, suffix: Optio
         let send_us = now_us();
 
         // Draw + sync + send timestamp via Chat
-        backend.apply_intent(Intent::Draw(stroke));
+        backend.apply_intent(Intent::Draw(stroke)).expect("Failed to apply intent");
         for (_, p) in room.remote_participants() {
             let pid = p.identity().to_string();
             if let Some(msg) = backend.generate_sync_message(&pid) {
@@ -332,9 +253,8 @@ async fn run_sender(room_name: &str, trials: usize, delay_ms: u64, suffix: Optio
                         Some(RoomEvent::DataReceived { payload, participant, .. }) => {
                             if let Some(p) = participant {
                                 let sid = p.identity().to_string();
-                                let t = transfers.entry(sid.clone()).or_default();
-                                if let Some(NetworkMessage::Sync(data)) = decode_payload(t, &payload) {
-                                    backend.receive_sync_message(&sid, data);
+                                if let Some(NetworkMessage::Sync(data)) = decode_payload(&mut reassembler, &sid, &payload) {
+                                    backend.receive_sync_message(&sid, data).ok();
                                     if let Some(reply) = backend.generate_sync_message(&sid) {
                                         publish_msg_to(&room, &NetworkMessage::Sync(reply), &sid).await;
                                     }
@@ -375,7 +295,7 @@ async fn run_receiver(room_name: &str, suffix: Option<&str>) {
         Some(s) => format!("bench_receiver_{}", s),
         None => "bench_receiver".to_string(),
     };
-    let token = create_token(room_name, &identity);
+    let token = TokenSource::from_env().fetch(room_name, &identity, Role::Editor).await.expect("Failed to get a token");
 
     println!("=== E2E Benchmark — RECEIVER ===");
     println!("  Server:  {}", url);
@@ -394,8 +314,7 @@ async fn run_receiver(room_name: &str, suffix: Option<&str>) {
     println!("[receiver] Connected! Waiting for sender...");
 
     let mut backend = AutomergeBackend::new();
-    let mut transfers_by_sender: HashMap<String, HashMap<u64, (u32, Vec<Option<Vec<u8>>>)>> =
-        HashMap::new();
+    let mut reassembler = Reassembler::default();
 
     // Register already-present peers
     for (_, p) in room.remote_participants() {
@@ -424,7 +343,7 @@ async fn run_receiver(room_name: &str, suffix: Option<&str>) {
             }
             Some(RoomEvent::ParticipantDisconnected(p)) => {
                 let pid = p.identity().to_string();
-                transfers_by_sender.remove(&pid);
+                reassembler.forget(&pid);
                 backend.peer_disconnected(&pid);
                 println!("[receiver] Peer left: {}", pid);
             }
@@ -435,13 +354,9 @@ async fn run_receiver(room_name: &str, suffix: Option<&str>) {
             }) => {
                 if let Some(p) = participant {
                     let sender_id = p.identity().to_string();
-                    let transfers = transfers_by_sender
-                        .entry(sender_id.clone())
-                        .or_default();
-
-                    match decode_payload(transfers, &payload) {
+                    match decode_payload(&mut reassembler, &sender_id, &payload) {
                         Some(NetworkMessage::Sync(sync_data)) => {
-                            backend.receive_sync_message(&sender_id, sync_data);
+                            backend.receive_sync_message(&sender_id, sync_data).ok();
 
                             // Check if new strokes arrived
                             let current = backend.get_strokes().len();
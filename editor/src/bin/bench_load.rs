@@ -22,6 +22,8 @@ fn generate_stroke(i: usize) -> Stroke {
         ],
         color: [(i % 256) as u8, ((i * 7) % 256) as u8, ((i * 13) % 256) as u8, 255],
         width: 2.0 + (i % 10) as f32,
+        id: String::new(),
+        shape: None,
     }
 }
 
@@ -34,7 +36,7 @@ fn main() {
         // Generate document with `count` strokes
         let mut backend = AutomergeBackend::new();
         for i in 0..count {
-            backend.apply_intent(Intent::Draw(generate_stroke(i)));
+            backend.apply_intent(Intent::Draw(generate_stroke(i))).expect("Failed to apply intent");
         }
 
         let data = backend.save();
@@ -47,7 +49,7 @@ fn main() {
             let data_clone = data.clone();
 
             let start = Instant::now();
-            loader.load(data_clone);
+            loader.load(data_clone).expect("Failed to load document");
             let elapsed = start.elapsed();
             times_ms.push(elapsed.as_secs_f64() * 1000.0);
         }
@@ -22,6 +22,8 @@ fn generate_stroke(i: usize) -> Stroke {
         ],
         color: [(i % 256) as u8, ((i * 7) % 256) as u8, ((i * 13) % 256) as u8, 255],
         width: 2.0 + (i % 10) as f32,
+        id: String::new(),
+        shape: None,
     }
 }
 
@@ -36,10 +38,10 @@ fn sync_loop(a: &mut AutomergeBackend, b: &mut AutomergeBackend) -> usize {
             break;
         }
         if let Some(m) = msg_ab {
-            b.receive_sync_message("a", m);
+            b.receive_sync_message("a", m).expect("Failed to apply sync message");
         }
         if let Some(m) = msg_ba {
-            a.receive_sync_message("b", m);
+            a.receive_sync_message("b", m).expect("Failed to apply sync message");
         }
         rounds += 1;
     }
@@ -56,7 +58,7 @@ fn main() {
     peer_b.peer_connected("a");
 
     // Seed a shared list so both peers operate on the same Automerge object
-    peer_a.apply_intent(Intent::Draw(generate_stroke(0)));
+    peer_a.apply_intent(Intent::Draw(generate_stroke(0))).expect("Failed to apply intent");
     sync_loop(&mut peer_a, &mut peer_b);
 
     // println!("trial,draw_us,sync_us,total_us,rounds,strokes_after");
@@ -71,7 +73,7 @@ fn main() {
 
         // Measure: apply intent on A
         let t0 = Instant::now();
-        peer_a.apply_intent(Intent::Draw(stroke));
+        peer_a.apply_intent(Intent::Draw(stroke)).expect("Failed to apply intent");
         let draw_time = t0.elapsed();
 
         // Measure: sync A → B until convergence
@@ -26,6 +26,8 @@ fn generate_stroke(i: usize) -> Stroke {
             255,
         ],
         width: 2.0 + (i % 5) as f32,
+        id: String::new(),
+        shape: None,
     }
 }
 
@@ -42,7 +44,7 @@ fn main() {
 
     let mut backend = AutomergeBackend::new();
     for i in 0..count {
-        backend.apply_intent(Intent::Draw(generate_stroke(i)));
+        backend.apply_intent(Intent::Draw(generate_stroke(i))).expect("Failed to apply intent");
     }
 
     let data = backend.save();
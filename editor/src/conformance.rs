@@ -0,0 +1,273 @@
+//! Conformance checks shared by all `DocBackend` implementations.
+//!
+//! `check_backend` drives a backend through a scripted set of intents and
+//! asserts the invariants every implementation must uphold. It panics on the
+//! first violation, so it can be called directly from a `#[test]`.
+use crate::backend_api::{BackendError, DocBackend, Intent, Point, Presence, Shape, Stroke, UserInfo};
+
+/// Runs every conformance check against fresh backends produced by `make`.
+pub fn check_backend(make: impl Fn() -> Box<dyn DocBackend>) {
+    check_render(make());
+    check_empty_document(make());
+    check_invalid_input(make());
+    check_persistence(make(), make());
+    check_metadata(make());
+    check_transaction(make());
+    check_compaction(make());
+    check_erase(make());
+    check_fork_merge(make());
+    check_pages(make());
+    check_shapes(make());
+    check_presence(make());
+    check_sync(make(), make());
+    check_read_only(make(), make());
+}
+
+/// A stroke with `n` points, distinguishable by its width (and id).
+fn stroke(n: i32, width: f32) -> Stroke {
+    Stroke {
+        points: (0..n).map(|i| Point { x: i, y: i * 2 }).collect(),
+        color: [0, 0, 0, 255],
+        width,
+        id: format!("{}x{}", n, width),
+        shape: None,
+    }
+}
+
+/// Exchanges sync messages between peers "a" and "b" until neither has anything left to send.
+fn sync(a: &mut dyn DocBackend, b: &mut dyn DocBackend) {
+    for _ in 0..20 {
+        let to_b = a.generate_sync_message("b");
+        let to_a = b.generate_sync_message("a");
+        if to_b.is_none() && to_a.is_none() {
+            return;
+        }
+        if let Some(m) = to_b {
+            b.receive_sync_message("a", m).expect("sync message must apply");
+        }
+        if let Some(m) = to_a {
+            a.receive_sync_message("b", m).expect("sync message must apply");
+        }
+    }
+    panic!("sync did not settle");
+}
+
+/// Drawn strokes are rendered in order, and updates match `get_strokes`.
+fn check_render(mut backend: Box<dyn DocBackend>) {
+    let first = backend.apply_intent(Intent::Draw(stroke(2, 1.0))).unwrap();
+    assert_eq!(first.strokes, vec![stroke(2, 1.0)]);
+    let second = backend.apply_intent(Intent::Draw(stroke(3, 2.0))).unwrap();
+    assert_eq!(second.strokes, vec![stroke(2, 1.0), stroke(3, 2.0)]);
+    assert_eq!(second.strokes, backend.get_strokes());
+
+    let cleared = backend.apply_intent(Intent::Clear).unwrap();
+    assert!(cleared.strokes.is_empty());
+    assert!(backend.get_strokes().is_empty());
+}
+
+/// Operations on an empty document are safe no-ops.
+fn check_empty_document(mut backend: Box<dyn DocBackend>) {
+    assert!(backend.get_strokes().is_empty());
+    assert!(backend.get_comments().is_empty());
+    assert!(backend.apply_intent(Intent::Clear).unwrap().strokes.is_empty());
+    if !backend.can_undo() {
+        assert!(backend.apply_intent(Intent::Undo).unwrap().strokes.is_empty());
+    }
+    if !backend.can_redo() {
+        assert!(backend.apply_intent(Intent::Redo).unwrap().strokes.is_empty());
+    }
+    assert!(backend.get_background().is_none());
+}
+
+/// Invalid input is rejected with an error and leaves the document unchanged.
+fn check_invalid_input(mut backend: Box<dyn DocBackend>) {
+    backend.apply_intent(Intent::Draw(stroke(2, 1.0))).unwrap();
+    let before = backend.get_strokes();
+
+    assert!(matches!(
+        backend.apply_intent(Intent::ResolveComment { id: "missing".into() }),
+        Err(BackendError::InvalidIntent(_))
+    ));
+    backend.peer_connected("peer");
+    assert!(matches!(
+        backend.receive_sync_message("peer", vec![0xff, 0x00, 0x13]),
+        Err(BackendError::Sync(_))
+    ));
+    assert!(matches!(backend.load(vec![1, 2, 3]), Err(BackendError::Load(_))));
+
+    assert_eq!(backend.get_strokes(), before);
+}
+
+/// A saved document loads into another instance with identical content.
+fn check_persistence(mut source: Box<dyn DocBackend>, mut target: Box<dyn DocBackend>) {
+    source.apply_intent(Intent::Draw(stroke(4, 3.0))).unwrap();
+    source.set_background(vec![9, 8, 7]).unwrap();
+    target.load(source.save()).unwrap();
+
+    assert_eq!(target.get_strokes(), source.get_strokes());
+    assert_eq!(target.get_background(), Some(vec![9, 8, 7]));
+}
+
+/// Title, document id and modification time are tracked.
+fn check_metadata(mut backend: Box<dyn DocBackend>) {
+    assert_eq!(backend.title(), "");
+    backend.set_title("Plan").unwrap();
+    assert_eq!(backend.title(), "Plan");
+    assert_eq!(backend.document_id(), None);
+    backend.set_document_id("plan-1").unwrap();
+    assert_eq!(backend.document_id().as_deref(), Some("plan-1"));
+    backend.apply_intent(Intent::Draw(stroke(1, 1.0))).unwrap();
+    assert!(backend.modified_at().is_some());
+}
+
+/// Transactions must be balanced and commit the grouped changes.
+fn check_transaction(mut backend: Box<dyn DocBackend>) {
+    assert!(matches!(backend.commit(), Err(BackendError::InvalidIntent(_))));
+    backend.begin_transaction().unwrap();
+    assert!(matches!(backend.begin_transaction(), Err(BackendError::InvalidIntent(_))));
+    backend.apply_intent(Intent::Draw(stroke(1, 1.0))).unwrap();
+    backend.apply_intent(Intent::Draw(stroke(2, 1.0))).unwrap();
+    assert_eq!(backend.commit().unwrap().strokes.len(), 2);
+}
+
+/// Presence is only kept for connected peers.
+fn check_compaction(mut backend: Box<dyn DocBackend>) {
+    backend.set_title("Plan").unwrap();
+    backend.apply_intent(Intent::Draw(stroke(1, 1.0))).unwrap();
+    backend.apply_intent(Intent::Draw(stroke(2, 2.0))).unwrap();
+    let strokes = backend.get_strokes();
+    backend.compact(0).unwrap();
+    assert_eq!(backend.get_strokes(), strokes, "Compaction must keep the content");
+    assert_eq!(backend.title(), "Plan");
+}
+
+fn check_erase(mut backend: Box<dyn DocBackend>) {
+    backend.apply_intent(Intent::Draw(stroke(1, 1.0))).unwrap();
+    backend.apply_intent(Intent::Draw(Stroke { id: String::new(), ..stroke(2, 2.0) })).unwrap();
+    let assigned = backend.get_strokes()[1].id.clone();
+    assert!(!assigned.is_empty(), "Strokes drawn without an id must get one");
+
+    let update = backend.apply_intent(Intent::EraseStroke(assigned)).unwrap();
+    assert_eq!(update.strokes, vec![stroke(1, 1.0)]);
+    assert!(matches!(
+        backend.apply_intent(Intent::EraseStroke("missing".into())),
+        Err(BackendError::InvalidIntent(_))
+    ));
+}
+
+fn check_fork_merge(mut backend: Box<dyn DocBackend>) {
+    backend.apply_intent(Intent::Draw(stroke(1, 1.0))).unwrap();
+    let mut branch = backend.fork().unwrap();
+    branch.apply_intent(Intent::Draw(stroke(2, 2.0))).unwrap();
+    assert_eq!(backend.get_strokes().len(), 1, "Forks must be independent");
+    let update = backend.merge(branch.as_mut()).unwrap();
+    assert_eq!(update.strokes, vec![stroke(1, 1.0), stroke(2, 2.0)]);
+}
+
+/// Every page keeps its own strokes; the first page cannot be deleted.
+fn check_pages(mut backend: Box<dyn DocBackend>) {
+    assert_eq!((backend.page_count(), backend.current_page()), (1, 0));
+    backend.apply_intent(Intent::Draw(stroke(1, 1.0))).unwrap();
+    let added = backend.apply_intent(Intent::AddPage).unwrap();
+    assert!(added.strokes.is_empty(), "A new page starts empty");
+    assert_eq!((backend.page_count(), backend.current_page()), (2, 1));
+    backend.apply_intent(Intent::Draw(stroke(2, 2.0))).unwrap();
+
+    let first = backend.apply_intent(Intent::SwitchPage(0)).unwrap();
+    assert_eq!(first.strokes, vec![stroke(1, 1.0)]);
+    assert!(matches!(backend.apply_intent(Intent::SwitchPage(2)), Err(BackendError::InvalidIntent(_))));
+    assert!(matches!(backend.apply_intent(Intent::DeletePage(0)), Err(BackendError::InvalidIntent(_))));
+
+    backend.apply_intent(Intent::SwitchPage(1)).unwrap();
+    let deleted = backend.apply_intent(Intent::DeletePage(1)).unwrap();
+    assert_eq!(deleted.strokes, vec![stroke(1, 1.0)], "Deleting the current page shows the previous one");
+    assert_eq!((backend.page_count(), backend.current_page()), (1, 0));
+}
+
+/// Shapes are returned as strokes spanning their two corners.
+fn check_shapes(mut backend: Box<dyn DocBackend>) {
+    let (start, end) = (Point { x: 1, y: 2 }, Point { x: 30, y: 40 });
+    for shape in [Shape::Rectangle, Shape::Ellipse, Shape::Line, Shape::Text("Label".into())] {
+        let update = backend
+            .apply_intent(Intent::DrawShape { shape: shape.clone(), start: start.clone(), end: end.clone(), color: [1, 2, 3, 255], width: 2.0 })
+            .unwrap();
+        let drawn = update.strokes.last().unwrap();
+        assert_eq!(drawn.shape, Some(shape));
+        assert_eq!(drawn.points, vec![start.clone(), end.clone()]);
+        assert!(!drawn.id.is_empty());
+    }
+    assert_eq!(backend.get_strokes().len(), 4);
+    assert!(matches!(
+        backend.apply_intent(Intent::DrawShape { shape: Shape::Text(" ".into()), start, end, color: [0; 4], width: 2.0 }),
+        Err(BackendError::InvalidIntent(_))
+    ));
+}
+
+fn check_presence(mut backend: Box<dyn DocBackend>) {
+    let presence = Presence {
+        cursor: Some(Point { x: 1, y: 1 }),
+        selection: None,
+        user: UserInfo { name: "bob".into(), color: None },
+    };
+    backend.update_peer_presence("ghost", presence.clone());
+    assert!(backend.peers().is_empty(), "Presence of unknown peers is ignored");
+
+    backend.peer_connected("bob");
+    backend.update_peer_presence("bob", presence.clone());
+    assert_eq!(backend.peers().len(), 1);
+    assert_eq!(backend.peers()[0].presence, presence);
+
+    backend.peer_disconnected("bob");
+    assert!(backend.peers().is_empty());
+}
+
+/// Concurrent edits on two instances converge after syncing.
+fn check_sync(mut a: Box<dyn DocBackend>, mut b: Box<dyn DocBackend>) {
+    a.peer_connected("b");
+    b.peer_connected("a");
+    a.apply_intent(Intent::Draw(stroke(2, 1.0))).unwrap();
+    b.apply_intent(Intent::Draw(stroke(3, 2.0))).unwrap();
+    sync(a.as_mut(), b.as_mut());
+
+    assert_eq!(a.get_strokes().len(), 2);
+    assert_eq!(a.get_strokes(), b.get_strokes());
+
+    a.apply_intent(Intent::Clear).unwrap();
+    sync(a.as_mut(), b.as_mut());
+    assert!(b.get_strokes().is_empty());
+}
+
+/// A read-only document rejects local edits but still takes in those of peers.
+fn check_read_only(mut a: Box<dyn DocBackend>, mut b: Box<dyn DocBackend>) {
+    a.peer_connected("b");
+    b.peer_connected("a");
+    a.apply_intent(Intent::AddPage).unwrap();
+    a.set_read_only(true);
+    assert!(a.is_read_only());
+    assert!(matches!(a.apply_intent(Intent::Draw(stroke(2, 1.0))), Err(BackendError::ReadOnly)));
+    assert!(!a.can_undo());
+    assert!(matches!(a.apply_intent(Intent::Undo), Err(BackendError::ReadOnly)));
+    assert!(matches!(a.set_title("Plan"), Err(BackendError::ReadOnly)));
+    a.apply_intent(Intent::SwitchPage(0)).unwrap();
+    assert_eq!(a.current_page(), 0, "Switching pages only changes the local view");
+
+    b.apply_intent(Intent::Draw(stroke(3, 2.0))).unwrap();
+    sync(a.as_mut(), b.as_mut());
+    assert_eq!(a.get_strokes().len(), 1, "Changes from peers still apply");
+
+    a.set_read_only(false);
+    a.apply_intent(Intent::Draw(stroke(2, 1.0))).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend_registry::{self, BACKENDS};
+
+    #[test]
+    fn test_registered_backends_conform() {
+        for entry in BACKENDS {
+            check_backend(|| backend_registry::create(entry.name).unwrap());
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! In-memory `DocStore` implementation.
+use crate::backend_api::{BackendError, DocBackend, DocId, DocStore};
+use rand::{distr::Alphanumeric, Rng};
+
+/// Factory producing a fresh, empty backend for each new document.
+pub type BackendFactory = Box<dyn Fn() -> Box<dyn DocBackend> + Send>;
+
+/// Keeps open documents in memory, in the order they were opened.
+pub struct MemoryDocStore {
+    /// Creates the backend for each new or opened document.
+    factory: BackendFactory,
+    /// Open documents with their ids.
+    docs: Vec<(DocId, Box<dyn DocBackend>)>,
+}
+
+impl MemoryDocStore {
+    /// Creates an empty store using `factory` to construct document backends.
+    pub fn new(factory: impl Fn() -> Box<dyn DocBackend> + Send + 'static) -> Self {
+        Self {
+            factory: Box::new(factory),
+            docs: Vec::new(),
+        }
+    }
+
+    /// Generates a random document id, unique among the open documents.
+    fn new_id(&self) -> DocId {
+        loop {
+            let id: String = rand::rng()
+                .sample_iter(&Alphanumeric)
+                .take(12)
+                .map(char::from)
+                .collect();
+            if !self.docs.iter().any(|(existing, _)| *existing == id) {
+                return id;
+            }
+        }
+    }
+}
+
+impl DocStore for MemoryDocStore {
+    fn create(&mut self) -> DocId {
+        let id = self.new_id();
+        self.docs.push((id.clone(), (self.factory)()));
+        id
+    }
+
+    fn open(&mut self, data: Vec<u8>) -> Result<DocId, BackendError> {
+        let mut backend = (self.factory)();
+        backend.load(data)?;
+        let id = self.new_id();
+        self.docs.push((id.clone(), backend));
+        Ok(id)
+    }
+
+    fn close(&mut self, id: &str) -> Option<Box<dyn DocBackend>> {
+        let pos = self.docs.iter().position(|(doc_id, _)| doc_id == id)?;
+        Some(self.docs.remove(pos).1)
+    }
+
+    fn list(&self) -> Vec<DocId> {
+        self.docs.iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    fn get(&self, id: &str) -> Option<&dyn DocBackend> {
+        self.docs
+            .iter()
+            .find(|(doc_id, _)| doc_id == id)
+            .map(|(_, backend)| backend.as_ref())
+    }
+
+    fn get_mut(&mut self, id: &str) -> Option<&mut Box<dyn DocBackend>> {
+        self.docs
+            .iter_mut()
+            .find(|(doc_id, _)| doc_id == id)
+            .map(|(_, backend)| backend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automerge_backend::AutomergeBackend;
+    use crate::backend_api::{Intent, Point, Stroke};
+
+    fn store() -> MemoryDocStore {
+        MemoryDocStore::new(|| Box::new(AutomergeBackend::new()))
+    }
+
+    fn create_test_stroke() -> Stroke {
+        Stroke {
+            points: vec![Point { x: 10, y: 10 }, Point { x: 20, y: 20 }],
+            color: [255, 0, 0, 255],
+            width: 5.0,
+            id: String::new(),
+            shape: None,
+        }
+    }
+
+    #[test]
+    fn test_create_list_close() {
+        let mut store = store();
+        let a = store.create();
+        let b = store.create();
+        assert_ne!(a, b);
+        assert_eq!(store.list(), vec![a.clone(), b.clone()]);
+
+        assert!(store.close(&a).is_some());
+        assert!(store.close(&a).is_none(), "Closing twice must be a no-op");
+        assert_eq!(store.list(), vec![b]);
+    }
+
+    #[test]
+    fn test_documents_are_independent() {
+        let mut store = store();
+        let a = store.create();
+        let b = store.create();
+
+        store
+            .get_mut(&a)
+            .unwrap()
+            .apply_intent(Intent::Draw(create_test_stroke()))
+            .unwrap();
+
+        assert_eq!(store.get(&a).unwrap().get_strokes().len(), 1);
+        assert!(store.get(&b).unwrap().get_strokes().is_empty());
+    }
+
+    #[test]
+    fn test_open_from_saved_data() {
+        let mut store = store();
+        let a = store.create();
+        store
+            .get_mut(&a)
+            .unwrap()
+            .apply_intent(Intent::Draw(create_test_stroke()))
+            .unwrap();
+        let data = store.get_mut(&a).unwrap().save();
+
+        let opened = store.open(data).unwrap();
+        assert_ne!(opened, a);
+        assert_eq!(store.get(&opened).unwrap().get_strokes().len(), 1);
+
+        assert!(matches!(
+            store.open(vec![1, 2, 3]),
+            Err(BackendError::Load(_))
+        ));
+        assert_eq!(
+            store.list().len(),
+            2,
+            "A failed open must not add a document"
+        );
+    }
+}
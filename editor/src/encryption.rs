@@ -0,0 +1,206 @@
+//! Passphrase-based encryption of saved documents and room data packets.
+//!
+//! Saved bytes are sealed in frames with ChaCha20-Poly1305, which detects any
+//! modification as well as hiding the content. The key is derived from the
+//! passphrase with PBKDF2-HMAC-SHA256 and a random salt stored in each frame.
+//!
+//! A frame is laid out as `MAGIC | salt | nonce | length (u32, little endian) |
+//! ciphertext`, the header being authenticated along with the ciphertext.
+//! Frames can be concatenated, so a document file may hold an encrypted
+//! snapshot followed by encrypted incremental saves (see `persistence`).
+//!
+//! Room traffic uses a cipher made by `Cipher::for_room`, whose salt follows
+//! from the room name: every participant derives the same key once, and
+//! frames with another salt are rejected without deriving a key for them, so
+//! nobody can make a peer spend a derivation on every packet.
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::io;
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+
+/// Marks the start of every frame.
+const MAGIC: &[u8; 4] = b"CWE1";
+
+const SALT_LEN: usize = 16;
+
+/// Prefix of the room name hashed into the salt of `Cipher::for_room`.
+const ROOM_SALT_PREFIX: &str = "collaboratite_editor room ";
+
+/// Most keys kept for the salts of frames sealed by other ciphers.
+const MAX_CACHED_KEYS: usize = 16;
+
+/// Bytes before the ciphertext of a frame.
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + NONCE_LEN + 4;
+
+/// PBKDF2 rounds; deriving a key takes a noticeable fraction of a second on
+/// purpose, to slow down guessing the passphrase.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Encrypts and decrypts saved documents with a key derived from a passphrase.
+pub struct Cipher {
+    passphrase: String,
+    /// Salt of the frames sealed by this cipher.
+    salt: [u8; SALT_LEN],
+    /// Key derived from the passphrase and `salt`.
+    key: LessSafeKey,
+    /// Keys derived for the salts of frames sealed by other sessions, kept
+    /// once a frame opened with them so each is derived only once.
+    keys: Mutex<HashMap<[u8; SALT_LEN], LessSafeKey>>,
+    /// Whether frames with another salt than `salt` are rejected outright.
+    own_salt_only: bool,
+    rng: SystemRandom,
+}
+
+impl Cipher {
+    /// Derives a key from `passphrase` with a fresh random salt.
+    pub fn new(passphrase: &str) -> Self {
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; SALT_LEN];
+        rng.fill(&mut salt).expect("the system random number generator is available");
+        Self::with_salt(passphrase, salt, false, rng)
+    }
+
+    /// Derives the key of the traffic in `room` from `passphrase`. Only frames
+    /// sealed by the same room's cipher open.
+    pub fn for_room(passphrase: &str, room: &str) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, format!("{}{}", ROOM_SALT_PREFIX, room).as_bytes());
+        let salt = digest.as_ref()[..SALT_LEN].try_into().expect("SHA-256 is longer than a salt");
+        Self::with_salt(passphrase, salt, true, SystemRandom::new())
+    }
+
+    fn with_salt(passphrase: &str, salt: [u8; SALT_LEN], own_salt_only: bool, rng: SystemRandom) -> Self {
+        Self {
+            passphrase: passphrase.to_string(),
+            key: derive_key(passphrase, &salt),
+            salt,
+            keys: Mutex::new(HashMap::new()),
+            own_salt_only,
+            rng,
+        }
+    }
+
+    /// Returns `true` if `data` starts with an encrypted frame.
+    pub fn is_encrypted(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    /// Encrypts `data` into one frame.
+    pub fn seal(&self, data: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).expect("the system random number generator is available");
+        let length = (data.len() + CHACHA20_POLY1305.tag_len()) as u32;
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + length as usize);
+        frame.extend_from_slice(MAGIC);
+        frame.extend_from_slice(&self.salt);
+        frame.extend_from_slice(&nonce);
+        frame.extend_from_slice(&length.to_le_bytes());
+        let mut ciphertext = data.to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(&frame[..HEADER_LEN]), &mut ciphertext)
+            .expect("frames are far below the size limit of ChaCha20-Poly1305");
+        frame.extend_from_slice(&ciphertext);
+        frame
+    }
+
+    /// Decrypts a sequence of frames and returns their concatenated content.
+    ///
+    /// # Errors
+    /// Returns `io::ErrorKind::InvalidData` if `data` is not a sequence of
+    /// frames, was modified, or was encrypted with another passphrase.
+    pub fn open(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let mut keys = self.keys.lock().unwrap();
+        let mut content = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            if rest.len() < HEADER_LEN || !Self::is_encrypted(rest) {
+                return Err(invalid("not an encrypted document"));
+            }
+            let (header, body) = rest.split_at(HEADER_LEN);
+            let salt: [u8; SALT_LEN] = header[MAGIC.len()..MAGIC.len() + SALT_LEN].try_into().unwrap();
+            let nonce: [u8; NONCE_LEN] = header[MAGIC.len() + SALT_LEN..HEADER_LEN - 4].try_into().unwrap();
+            let length = u32::from_le_bytes(header[HEADER_LEN - 4..].try_into().unwrap()) as usize;
+            if body.len() < length {
+                return Err(invalid("encrypted document is truncated"));
+            }
+            let derived = if salt == self.salt || keys.contains_key(&salt) {
+                None
+            } else if self.own_salt_only {
+                return Err(invalid("wrong passphrase or damaged document"));
+            } else {
+                Some(derive_key(&self.passphrase, &salt))
+            };
+            let key = derived.as_ref().or_else(|| keys.get(&salt)).unwrap_or(&self.key);
+            let mut frame = body[..length].to_vec();
+            let plain = key
+                .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::from(header), &mut frame)
+                .map_err(|_| invalid("wrong passphrase or damaged document"))?;
+            content.extend_from_slice(plain);
+            // Only keys that opened a frame are kept, and only so many.
+            if let Some(key) = derived {
+                if keys.len() < MAX_CACHED_KEYS {
+                    keys.insert(salt, key);
+                }
+            }
+            rest = &body[length..];
+        }
+        Ok(content)
+    }
+}
+
+/// Derives a ChaCha20-Poly1305 key from `passphrase` and `salt`.
+fn derive_key(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0u8; 32];
+    let iterations = NonZeroU32::new(PBKDF2_ITERATIONS).expect("iteration count is not zero");
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, passphrase.as_bytes(), &mut key);
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &key).expect("key has the algorithm's length"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_round_trip_and_concatenate() {
+        let cipher = Cipher::new("correct horse");
+        let mut data = cipher.seal(b"snapshot");
+        assert!(Cipher::is_encrypted(&data));
+        assert!(!data.windows(8).any(|w| w == b"snapshot"), "Content is not stored in plain text");
+
+        // A frame from another session (different salt) appended to the file.
+        data.extend(Cipher::new("correct horse").seal(b" + change"));
+        data.extend(cipher.seal(b""));
+        assert_eq!(cipher.open(&data).unwrap(), b"snapshot + change");
+    }
+
+    #[test]
+    fn test_wrong_passphrase_and_tampering_are_rejected() {
+        let cipher = Cipher::new("correct horse");
+        let mut data = cipher.seal(b"snapshot");
+        assert_eq!(Cipher::new("battery staple").open(&data).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(cipher.open(&data).is_err(), "Modified ciphertext");
+        assert!(cipher.open(&data[..data.len() - 4]).is_err(), "Truncated frame");
+        assert!(cipher.open(b"plain automerge bytes").is_err());
+        assert!(cipher.keys.lock().unwrap().is_empty(), "Keys of frames that did not open are not kept");
+    }
+
+    #[test]
+    fn test_room_ciphers_only_open_their_own_frames() {
+        let alice = Cipher::for_room("room secret", "whiteboard-doc");
+        let bob = Cipher::for_room("room secret", "whiteboard-doc");
+        assert_eq!(bob.open(&alice.seal(b"edit")).unwrap(), b"edit");
+
+        // Frames with any other salt are rejected without deriving a key.
+        assert!(bob.open(&Cipher::new("room secret").seal(b"edit")).is_err());
+        assert!(bob.open(&Cipher::for_room("room secret", "whiteboard-other").seal(b"edit")).is_err());
+        assert!(bob.keys.lock().unwrap().is_empty());
+        assert!(Cipher::for_room("guess", "whiteboard-doc").open(&alice.seal(b"edit")).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Keyboard shortcuts of the editor's commands, which users can rebind in the settings.
+use egui::{Key, KeyboardShortcut, Modifiers};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// An editor command that can be bound to a keyboard shortcut.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    ToggleSidebar,
+    Open,
+    Save,
+    SaveAs,
+    Undo,
+    Redo,
+    NextTab,
+    PreviousTab,
+    SelectNone,
+    CommandPalette,
+    GoToDocument,
+}
+
+impl Action {
+    /// All actions, in the order the settings list them.
+    pub const ALL: [Action; 11] = [
+        Action::ToggleSidebar,
+        Action::Open,
+        Action::Save,
+        Action::SaveAs,
+        Action::Undo,
+        Action::Redo,
+        Action::NextTab,
+        Action::PreviousTab,
+        Action::SelectNone,
+        Action::CommandPalette,
+        Action::GoToDocument,
+    ];
+
+    /// Name shown in the settings.
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::ToggleSidebar => "Toggle sidebar",
+            Action::Open => "Open file",
+            Action::Save => "Save",
+            Action::SaveAs => "Save as",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::NextTab => "Next tab",
+            Action::PreviousTab => "Previous tab",
+            Action::SelectNone => "Clear selection",
+            Action::CommandPalette => "Command palette",
+            Action::GoToDocument => "Go to document",
+        }
+    }
+
+    /// Shortcut used unless the user binds another one.
+    pub fn default_chord(self) -> Chord {
+        let command_shift = Modifiers::COMMAND | Modifiers::SHIFT;
+        let (modifiers, key) = match self {
+            Action::ToggleSidebar => (Modifiers::COMMAND, Key::Backslash),
+            Action::Open => (Modifiers::COMMAND, Key::O),
+            Action::Save => (Modifiers::COMMAND, Key::S),
+            Action::SaveAs => (command_shift, Key::S),
+            Action::Undo => (Modifiers::COMMAND, Key::Z),
+            Action::Redo => (command_shift, Key::Z),
+            Action::NextTab => (Modifiers::COMMAND, Key::Tab),
+            Action::PreviousTab => (command_shift, Key::Tab),
+            Action::SelectNone => (Modifiers::NONE, Key::Escape),
+            Action::CommandPalette => (command_shift, Key::P),
+            Action::GoToDocument => (Modifiers::COMMAND, Key::P),
+        };
+        Chord::new(modifiers, key)
+    }
+}
+
+/// A key with modifiers, written like `Ctrl+Shift+S` in the settings file.
+/// `Ctrl` stands for Cmd on macOS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Chord(pub KeyboardShortcut);
+
+impl Chord {
+    /// Chord of `key` with `modifiers` as egui reports them; Ctrl and Cmd are
+    /// both kept as `Modifiers::COMMAND`, so the chord works on every platform.
+    pub fn new(modifiers: Modifiers, key: Key) -> Self {
+        let mut normalized = Modifiers::NONE;
+        if modifiers.command || modifiers.ctrl || modifiers.mac_cmd {
+            normalized |= Modifiers::COMMAND;
+        }
+        if modifiers.alt {
+            normalized |= Modifiers::ALT;
+        }
+        if modifiers.shift {
+            normalized |= Modifiers::SHIFT;
+        }
+        Chord(KeyboardShortcut::new(normalized, key))
+    }
+
+    /// Whether the chord was pressed this frame, with exactly its modifiers.
+    pub fn pressed(&self, input: &egui::InputState) -> bool {
+        input.key_pressed(self.0.logical_key) && input.modifiers.matches_exact(self.0.modifiers)
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let modifiers = self.0.modifiers;
+        if modifiers.command {
+            f.write_str("Ctrl+")?;
+        }
+        if modifiers.alt {
+            f.write_str("Alt+")?;
+        }
+        if modifiers.shift {
+            f.write_str("Shift+")?;
+        }
+        f.write_str(self.0.logical_key.name())
+    }
+}
+
+impl FromStr for Chord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key_name = parts.pop().unwrap_or_default();
+        let key = Key::from_name(key_name).ok_or_else(|| format!("unknown key '{}' in shortcut '{}'", key_name, s))?;
+        let mut modifiers = Modifiers::NONE;
+        for part in parts {
+            match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "cmd" | "command" => modifiers |= Modifiers::COMMAND,
+                "alt" | "option" => modifiers |= Modifiers::ALT,
+                "shift" => modifiers |= Modifiers::SHIFT,
+                _ => return Err(format!("unknown modifier '{}' in shortcut '{}'", part, s)),
+            }
+        }
+        Ok(Chord::new(modifiers, key))
+    }
+}
+
+impl TryFrom<String> for Chord {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Chord> for String {
+    fn from(chord: Chord) -> Self {
+        chord.to_string()
+    }
+}
+
+/// Shortcuts the user rebound; the other actions keep their default chord.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Keymap {
+    overrides: BTreeMap<Action, Chord>,
+}
+
+impl Keymap {
+    /// The shortcut currently bound to `action`.
+    pub fn chord(&self, action: Action) -> Chord {
+        self.overrides.get(&action).copied().unwrap_or_else(|| action.default_chord())
+    }
+
+    /// Binds `chord` to `action`; binding the default chord drops the override.
+    pub fn bind(&mut self, action: Action, chord: Chord) {
+        if chord == action.default_chord() {
+            self.overrides.remove(&action);
+        } else {
+            self.overrides.insert(action, chord);
+        }
+    }
+
+    /// Whether `action` is bound to something other than its default chord.
+    pub fn is_rebound(&self, action: Action) -> bool {
+        self.overrides.contains_key(&action)
+    }
+
+    /// Restores the default chord of `action`.
+    pub fn reset(&mut self, action: Action) {
+        self.overrides.remove(&action);
+    }
+
+    /// Actions other than `action` bound to the same chord.
+    pub fn conflicts(&self, action: Action) -> Vec<Action> {
+        let chord = self.chord(action);
+        Action::ALL
+            .into_iter()
+            .filter(|&other| other != action && self.chord(other) == chord)
+            .collect()
+    }
+
+    /// The actions whose shortcuts were pressed this frame.
+    pub fn pressed(&self, input: &egui::InputState) -> Vec<Action> {
+        Action::ALL.into_iter().filter(|&action| self.chord(action).pressed(input)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chords_parse_and_rebinding_keeps_only_overrides() {
+        let chord: Chord = "ctrl + shift + S".parse().unwrap();
+        assert_eq!(chord, Action::SaveAs.default_chord());
+        assert_eq!(chord.to_string(), "Ctrl+Shift+S");
+        assert_eq!("Alt+Plus".parse::<Chord>().unwrap().to_string(), "Alt+Plus");
+        assert!("Ctrl+Nope".parse::<Chord>().is_err());
+        assert!("Hyper+S".parse::<Chord>().is_err());
+        let linux_ctrl = Modifiers { ctrl: true, command: true, ..Modifiers::NONE };
+        assert_eq!(Chord::new(linux_ctrl, Key::Z), Action::Undo.default_chord());
+
+        let mut keymap = Keymap::default();
+        assert_eq!(keymap.chord(Action::Undo), Action::Undo.default_chord());
+        keymap.bind(Action::Undo, "Ctrl+S".parse().unwrap());
+        assert!(keymap.is_rebound(Action::Undo));
+        assert_eq!(keymap.conflicts(Action::Undo), vec![Action::Save]);
+        keymap.bind(Action::Undo, Action::Undo.default_chord());
+        assert!(!keymap.is_rebound(Action::Undo), "Binding the default drops the override");
+        assert!(keymap.conflicts(Action::Undo).is_empty());
+    }
+}
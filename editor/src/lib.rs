@@ -1,3 +1,16 @@
 //! Library re-exports for benchmarks and tests.
 pub mod backend_api;
 pub mod automerge_backend;
+pub mod doc_store;
+pub mod backend_registry;
+pub mod conformance;
+pub mod persistence;
+pub mod encryption;
+pub mod workspace;
+pub mod settings;
+pub mod keymap;
+pub mod palette;
+pub mod statistics;
+pub mod protocol;
+pub mod token;
+pub mod rooms;
@@ -8,14 +8,28 @@
 //!
 //! - `backend_api`: Defines the core document backend traits and data structures.
 //! - `automerge_backend`: Implements the `DocBackend` using Automerge.
+//! - `backend_registry`: Selects a `DocBackend` by name (`--backend=<name>`).
+//! - `persistence`: Append-only document files with periodic compaction.
+//! - `encryption`: Passphrase-based encryption of saved documents.
+//! - `workspace`: Lists a workspace folder for the sidebar's file tree.
+//! - `settings`: User preferences and connection settings kept in a TOML config file.
+//! - `keymap`: Keyboard shortcuts of the editor's commands, rebindable in the settings.
+//! - `palette`: Fuzzy matching for the command palette.
+//! - `statistics`: Word counts and other statistics of a page.
+//! - `protocol`: Versioned envelope of the data packets exchanged over LiveKit.
+//! - `token`: Join tokens from a token server, or generated locally for development.
+//! - `rooms`: Active rooms of the LiveKit server, for the room browser.
+//! - `voice`: Microphone capture published as an audio track in the room.
+//! - `screen_share`: Decoding of the screen shares other participants publish.
 //! - `ui`: Contains the `eframe`/`egui` user interface and network handling logic.
 //! - `ui_panels`: Submodules for different UI panels (sidebar, editor, status_bar etc.).
 
-mod backend_api;
-mod automerge_backend;
+// The backend modules are compiled once, in the library crate, and shared with the benchmarks.
+use collaboratite_editor::{backend_api, backend_registry, encryption, keymap, palette, persistence, protocol, rooms, settings, statistics, token, workspace};
+mod screen_share;
 mod ui;
+mod voice;
 
-use crate::automerge_backend::AutomergeBackend;
 use crate::ui::AppView;
 use eframe::NativeOptions;
 
@@ -23,23 +37,39 @@ use eframe::NativeOptions;
 ///
 /// Initializes the application window, loads environment variables,
 /// and starts the `eframe` event loop with the `AppView`.
+/// The document backend is chosen with `--backend=<name>`, falling back to the
+/// one in the settings file (default: automerge).
 ///
 /// # Returns
 ///
 /// * `eframe::Result<()>` - Result of the application execution.
 fn main() -> eframe::Result<()> {
     println!("Starting Collaborative Whiteboard...");
+    dotenv::dotenv().ok();
+    let backend_name = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--backend=").map(str::to_string))
+        .unwrap_or_else(|| {
+            let path = settings::Settings::default_path();
+            settings::Settings::load(&path).unwrap_or_default().with_env_overrides().backend
+        });
+    let backend = match backend_registry::create(&backend_name) {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
     // Configuration options for the native window execution environment, initialized with default settings
     // such as window size, icon, and rendering context attributes.
     let mut native_options = NativeOptions::default();
     native_options.centered = true;
-    dotenv::dotenv().ok();
 
     eframe::run_native(
         "Collaborative Whiteboard",
         native_options,
         Box::new(move |_cc| {
-            Ok(Box::new(AppView::new(Box::new(AutomergeBackend::new()))))
+            Ok(Box::new(AppView::new(backend, &backend_name)))
         }),
     )
 }
@@ -0,0 +1,56 @@
+//! Fuzzy matching for the command palette.
+
+/// Scores how well `query` matches `text`, ignoring case: every character of
+/// the query has to appear in `text`, in order. Characters starting a word and
+/// runs of consecutive characters score higher. Returns `None` on no match;
+/// an empty query matches everything with a score of 0.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<u32> {
+    let text: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut from = 0;
+    let mut previous: Option<usize> = None;
+    for wanted in query.chars().flat_map(char::to_lowercase).filter(|c| !c.is_whitespace()) {
+        let found = from + text[from..].iter().position(|&c| c == wanted)?;
+        score += 1;
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 5;
+        }
+        if previous == Some(found.wrapping_sub(1)) {
+            score += 3;
+        }
+        previous = Some(found);
+        from = found + 1;
+    }
+    Some(score)
+}
+
+/// Indices of the `items` matching `query`, best match first; equally good
+/// matches keep their order.
+pub fn rank<'a>(query: &str, items: impl IntoIterator<Item = &'a str>) -> Vec<usize> {
+    let mut matches: Vec<(usize, u32)> = items
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, item)| fuzzy_score(query, item).map(|score| (i, score)))
+        .collect();
+    matches.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+    matches.into_iter().map(|(i, _)| i).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_rank_prefers_word_starts_and_runs() {
+        assert_eq!(fuzzy_score("", "Save"), Some(0));
+        assert!(fuzzy_score("sv", "Save").is_some());
+        assert!(fuzzy_score("vs", "Save").is_none(), "Characters must appear in order");
+        assert!(fuzzy_score("SAVE AS", "Save as").is_some(), "Case and spaces are ignored");
+
+        let items = ["Undo", "Toggle theme", "Save", "Save as", "Open settings"];
+        assert_eq!(rank("tt", items), vec![1, 4], "Word starts beat letters inside words");
+        assert_eq!(rank("sa", items), vec![2, 3]);
+        assert_eq!(rank("", items), vec![0, 1, 2, 3, 4]);
+        assert_eq!(rank("st", ["Last", "Settings"]), vec![1, 0]);
+    }
+}
@@ -0,0 +1,443 @@
+//! Append-only document files.
+//!
+//! A document file holds a full snapshot (`DocBackend::save`) followed by the
+//! incremental changes saved after it (`DocBackend::save_incremental`). Saving
+//! after every edit only appends the new changes; once the appended log grows
+//! larger than the snapshot, the file is compacted into a fresh snapshot.
+//!
+//! With a `Cipher` the snapshot and every appended piece are written as
+//! encrypted frames, so nothing reaches the disk in plain text.
+use crate::backend_api::DocBackend;
+use crate::encryption::Cipher;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Appended bytes are always allowed up to this size before compacting,
+/// so small documents are not rewritten on every save.
+const MIN_LOG_BYTES: usize = 64 * 1024;
+
+/// A document file on disk, kept in step with a backend.
+pub struct DocFile {
+    /// Location of the file.
+    path: PathBuf,
+    /// Size of the snapshot at the start of the file.
+    snapshot_bytes: usize,
+    /// Size of the incremental changes appended after the snapshot.
+    log_bytes: usize,
+    /// Encrypts everything written to the file, if set.
+    cipher: Option<Cipher>,
+}
+
+impl DocFile {
+    /// Writes a full snapshot of `backend` to `path`, replacing any existing file.
+    pub fn create(path: impl Into<PathBuf>, backend: &mut dyn DocBackend, cipher: Option<Cipher>) -> io::Result<Self> {
+        let mut file = Self {
+            path: path.into(),
+            snapshot_bytes: 0,
+            log_bytes: 0,
+            cipher,
+        };
+        file.compact(backend)?;
+        Ok(file)
+    }
+
+    /// Loads the file at `path` into `backend` and keeps tracking it.
+    ///
+    /// The whole file counts as snapshot, so the next compaction happens once
+    /// as many bytes have been appended. Encrypted files need a `cipher` made
+    /// from the same passphrase; a plain file opened with a `cipher` is
+    /// rewritten encrypted right away.
+    ///
+    /// # Errors
+    /// Returns `io::ErrorKind::InvalidInput` if the file is encrypted but no
+    /// `cipher` is given, and `io::ErrorKind::InvalidData` if it cannot be
+    /// decrypted or the backend rejects its content.
+    pub fn open(path: impl Into<PathBuf>, backend: &mut dyn DocBackend, cipher: Option<Cipher>) -> io::Result<Self> {
+        let path = path.into();
+        let (len, encrypted) = load_file(&path, backend, cipher.as_ref())?;
+        let mut file = Self {
+            path,
+            snapshot_bytes: len,
+            log_bytes: 0,
+            cipher,
+        };
+        if file.cipher.is_some() && !encrypted {
+            file.compact(backend)?;
+        }
+        Ok(file)
+    }
+
+    /// Changes the encryption of the file (`None` to store it in plain text)
+    /// and rewrites it to match.
+    pub fn set_cipher(&mut self, cipher: Option<Cipher>, backend: &mut dyn DocBackend) -> io::Result<()> {
+        self.cipher = cipher;
+        self.compact(backend)
+    }
+
+    /// Location of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Persists the changes made in `backend` since the last save, appending
+    /// them to the file or compacting it when the log has grown too large.
+    ///
+    /// The backend counts the changes as saved only once they are on disk, so
+    /// after a failure the next save writes them again.
+    pub fn save(&mut self, backend: &mut dyn DocBackend) -> io::Result<()> {
+        let saved = backend.save_incremental().map_err(|e| io::Error::other(e.to_string()))?;
+        if saved.data.is_empty() {
+            return Ok(());
+        }
+        let delta = seal(self.cipher.as_ref(), saved.data);
+        if self.log_bytes + delta.len() > self.snapshot_bytes.max(MIN_LOG_BYTES) {
+            return self.compact(backend);
+        }
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        if let Err(e) = file.write_all(&delta) {
+            // Drop a partly written piece, which would make the file unreadable.
+            let _ = file.set_len((self.snapshot_bytes + self.log_bytes) as u64);
+            return Err(e);
+        }
+        backend.mark_saved(&saved.version);
+        self.log_bytes += delta.len();
+        Ok(())
+    }
+
+    /// Rewrites the file as a single snapshot (see `replace_file`).
+    pub fn compact(&mut self, backend: &mut dyn DocBackend) -> io::Result<()> {
+        let version = backend.version();
+        let data = seal(self.cipher.as_ref(), backend.save());
+        replace_file(&self.path, &data)?;
+        backend.mark_saved(&version);
+        self.snapshot_bytes = data.len();
+        self.log_bytes = 0;
+        Ok(())
+    }
+}
+
+/// `data` encrypted with `cipher`, or unchanged without one.
+fn seal(cipher: Option<&Cipher>, data: Vec<u8>) -> Vec<u8> {
+    match cipher {
+        Some(cipher) => cipher.seal(&data),
+        None => data,
+    }
+}
+
+/// Replaces the file at `path` with `data`, writing it next to the file first
+/// and renaming it over, so a crash mid-write leaves the previous version intact.
+fn replace_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(".tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+/// Reads a document file, decrypting it if needed, and loads it into
+/// `backend`. Returns the size of the file and whether it was encrypted.
+fn load_file(path: &Path, backend: &mut dyn DocBackend, cipher: Option<&Cipher>) -> io::Result<(usize, bool)> {
+    let data = fs::read(path)?;
+    let len = data.len();
+    let encrypted = Cipher::is_encrypted(&data);
+    let content = match (cipher, encrypted) {
+        (Some(cipher), true) => cipher.open(&data)?,
+        (None, true) => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the document is encrypted; enter its passphrase"));
+        }
+        (_, false) => data,
+    };
+    backend
+        .load(content)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    Ok((len, encrypted))
+}
+
+/// Snapshot of unsaved work, rewritten periodically so it survives a crash.
+///
+/// Next to the snapshot, a `.source` file names the document file the work
+/// belongs to (if it has one), so a snapshot older than that file is ignored.
+pub struct RecoveryFile {
+    /// Location of the snapshot.
+    path: PathBuf,
+}
+
+impl RecoveryFile {
+    /// Keeps the snapshot in `dir` as `name.crdt`; `dir` is created when first needed.
+    pub fn in_dir(dir: impl Into<PathBuf>, name: &str) -> Self {
+        Self { path: dir.into().join(format!("{}.crdt", name)) }
+    }
+
+    /// A snapshot with a random name in `default_dir`, so editors running at
+    /// the same time (or documents open in one) do not overwrite each other's.
+    pub fn new_default() -> Self {
+        Self::in_dir(Self::default_dir(), &format!("{:016x}", rand::random::<u64>()))
+    }
+
+    /// Recovery directory of the editor in the user's data directory
+    /// (`$XDG_DATA_HOME`, `%LOCALAPPDATA%` or `~/.local/share`), which,
+    /// unlike the temporary directory, is not emptied when the system restarts.
+    pub fn default_dir() -> PathBuf {
+        let data = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))
+            .unwrap_or_else(std::env::temp_dir);
+        data.join("collaboratite_editor").join("recovery")
+    }
+
+    /// Snapshots in `dir` holding work newer than their document file (see
+    /// `pending`), most recently written first.
+    pub fn pending_in(dir: &Path) -> Vec<(RecoveryFile, SystemTime)> {
+        let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+        let mut found: Vec<_> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "crdt"))
+            .filter_map(|path| {
+                let file = RecoveryFile { path };
+                let written = file.pending()?;
+                Some((file, written))
+            })
+            .collect();
+        found.sort_by(|a, b| b.1.cmp(&a.1));
+        found
+    }
+
+    /// Location of the snapshot.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn source_path(&self) -> PathBuf {
+        self.path.with_extension("source")
+    }
+
+    /// Replaces the snapshot with the state of `backend`, which was opened
+    /// from (or last saved to) `source`.
+    pub fn write(&self, backend: &mut dyn DocBackend, source: Option<&Path>, cipher: Option<Cipher>) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            create_private_dir(dir)?;
+        }
+        // Written without `DocFile`, as a snapshot does not save the document.
+        replace_file(&self.path, &seal(cipher.as_ref(), backend.save()))?;
+        match source {
+            Some(source) => fs::write(self.source_path(), source.to_string_lossy().as_bytes()),
+            None => match fs::remove_file(self.source_path()) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// Returns when the snapshot was written, if there is one holding work
+    /// newer than its document file.
+    pub fn pending(&self) -> Option<SystemTime> {
+        let written = fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        let saved = fs::read_to_string(self.source_path())
+            .and_then(|source| fs::metadata(source.trim_end()))
+            .and_then(|m| m.modified());
+        match saved {
+            Ok(saved) if saved >= written => None,
+            _ => Some(written),
+        }
+    }
+
+    /// Loads the snapshot into `backend`.
+    ///
+    /// # Errors
+    /// As `DocFile::open`: encrypted snapshots need a `cipher` from the same passphrase.
+    pub fn restore(&self, backend: &mut dyn DocBackend, cipher: Option<&Cipher>) -> io::Result<()> {
+        load_file(&self.path, backend, cipher).map(|_| ())
+    }
+
+    /// Deletes the snapshot, once the work it holds is saved or unwanted.
+    pub fn discard(&self) -> io::Result<()> {
+        for path in [self.path.clone(), self.source_path()] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Creates `dir` and its parents; on Unix the directory is readable by the
+/// user only, as snapshots of documents without a passphrase are not encrypted.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+    }
+    #[cfg(not(unix))]
+    {
+        fs::create_dir_all(dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automerge_backend::AutomergeBackend;
+    use crate::backend_api::{Intent, Point, Stroke};
+
+    fn create_test_stroke(x: i32) -> Stroke {
+        Stroke {
+            points: vec![Point { x, y: 0 }, Point { x, y: 10 }],
+            color: [0, 0, 255, 255],
+            width: 2.0,
+            id: String::new(),
+            shape: None,
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.crdt", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_appends_then_compacts() {
+        let path = temp_path("doc_file_append");
+        let mut backend = AutomergeBackend::new();
+        backend.apply_intent(Intent::Draw(create_test_stroke(0))).unwrap();
+        let mut file = DocFile::create(&path, &mut backend, None).unwrap();
+        let snapshot_len = fs::metadata(&path).unwrap().len();
+
+        backend.apply_intent(Intent::Draw(create_test_stroke(1))).unwrap();
+        file.save(&mut backend).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() > snapshot_len, "The change is appended");
+        assert!(file.log_bytes > 0);
+
+        let mut loaded = AutomergeBackend::new();
+        loaded.load(fs::read(&path).unwrap()).unwrap();
+        assert_eq!(loaded.get_strokes(), backend.get_strokes());
+
+        // Force the log past its limit; the file becomes a single snapshot again.
+        file.snapshot_bytes = 0;
+        file.log_bytes = MIN_LOG_BYTES;
+        backend.apply_intent(Intent::Draw(create_test_stroke(2))).unwrap();
+        file.save(&mut backend).unwrap();
+        assert_eq!(file.log_bytes, 0);
+
+        let mut loaded = AutomergeBackend::new();
+        loaded.load(fs::read(&path).unwrap()).unwrap();
+        assert_eq!(loaded.get_strokes().len(), 3);
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_failed_save_is_retried() {
+        let path = temp_path("doc_file_retry");
+        let mut backend = AutomergeBackend::new();
+        let mut file = DocFile::create(&path, &mut backend, None).unwrap();
+        let snapshot = fs::read(&path).unwrap();
+
+        backend.apply_intent(Intent::Draw(create_test_stroke(0))).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(file.save(&mut backend).is_err());
+
+        fs::write(&path, &snapshot).unwrap();
+        file.save(&mut backend).unwrap();
+        let mut loaded = AutomergeBackend::new();
+        loaded.load(fs::read(&path).unwrap()).unwrap();
+        assert_eq!(loaded.get_strokes(), backend.get_strokes(), "The change is written by the next save");
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_encrypted_file_round_trip() {
+        let path = temp_path("doc_file_encrypted");
+        let mut backend = AutomergeBackend::new();
+        backend.set_title("Secret plans").unwrap();
+        let mut file = DocFile::create(&path, &mut backend, Some(Cipher::new("hunter2"))).unwrap();
+        backend.apply_intent(Intent::Draw(create_test_stroke(1))).unwrap();
+        file.save(&mut backend).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert!(!bytes.windows(12).any(|w| w == b"Secret plans"), "Nothing is stored in plain text");
+        assert!(AutomergeBackend::new().load(bytes).is_err());
+
+        let mut loaded = AutomergeBackend::new();
+        let err = DocFile::open(&path, &mut loaded, None).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = DocFile::open(&path, &mut loaded, Some(Cipher::new("hunter3"))).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let mut reopened = DocFile::open(&path, &mut loaded, Some(Cipher::new("hunter2"))).unwrap();
+        assert_eq!(loaded.get_strokes(), backend.get_strokes());
+        assert_eq!(loaded.title(), "Secret plans");
+
+        // Removing the passphrase rewrites the file in plain text.
+        reopened.set_cipher(None, &mut loaded).unwrap();
+        let mut plain = AutomergeBackend::new();
+        plain.load(fs::read(&path).unwrap()).unwrap();
+        assert_eq!(plain.get_strokes(), backend.get_strokes());
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_plain_file_opened_with_passphrase_is_encrypted() {
+        let path = temp_path("doc_file_plain");
+        let mut backend = AutomergeBackend::new();
+        backend.apply_intent(Intent::Draw(create_test_stroke(0))).unwrap();
+        DocFile::create(&path, &mut backend, None).unwrap();
+
+        let mut loaded = AutomergeBackend::new();
+        DocFile::open(&path, &mut loaded, Some(Cipher::new("hunter2"))).unwrap();
+        assert_eq!(loaded.get_strokes(), backend.get_strokes());
+        assert!(Cipher::is_encrypted(&fs::read(&path).unwrap()));
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_recovery_snapshot_is_offered_until_discarded() {
+        let dir = std::env::temp_dir().join(format!("recovery-{}", std::process::id()));
+        let recovery = RecoveryFile::in_dir(&dir, "tab");
+        assert!(recovery.pending().is_none());
+        assert!(RecoveryFile::pending_in(&dir).is_empty());
+
+        let mut backend = AutomergeBackend::new();
+        backend.apply_intent(Intent::Draw(create_test_stroke(0))).unwrap();
+        recovery.write(&mut backend, None, Some(Cipher::new("hunter2"))).unwrap();
+        assert!(recovery.pending().is_some());
+        let other = RecoveryFile::in_dir(&dir, "other tab");
+        other.write(&mut backend, None, None).unwrap();
+        let found: Vec<PathBuf> = RecoveryFile::pending_in(&dir).into_iter().map(|(file, _)| file.path).collect();
+        assert_eq!(found.len(), 2, "Each snapshot is found");
+        assert!(found.contains(&recovery.path) && found.contains(&other.path));
+        other.discard().unwrap();
+
+        let mut restored = AutomergeBackend::new();
+        assert!(recovery.restore(&mut restored, None).is_err(), "The snapshot is encrypted");
+        recovery.restore(&mut restored, Some(&Cipher::new("hunter2"))).unwrap();
+        assert_eq!(restored.get_strokes(), backend.get_strokes());
+
+        recovery.discard().unwrap();
+        assert!(recovery.pending().is_none());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_recovery_snapshot_older_than_its_document_is_ignored() {
+        let dir = std::env::temp_dir().join(format!("recovery-source-{}", std::process::id()));
+        let recovery = RecoveryFile::in_dir(&dir, "tab");
+        let source = temp_path("recovery_source");
+        let mut backend = AutomergeBackend::new();
+        DocFile::create(&source, &mut backend, None).unwrap();
+        recovery.write(&mut backend, Some(&source), None).unwrap();
+
+        let set_modified = |path: &Path, time: SystemTime| {
+            fs::File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+        };
+        let second = std::time::Duration::from_secs(1);
+        set_modified(&source, SystemTime::UNIX_EPOCH + second);
+        assert!(recovery.pending().is_some(), "The document was saved before the snapshot");
+        set_modified(&source, SystemTime::now() + second);
+        assert!(recovery.pending().is_none(), "The document was saved after the snapshot");
+
+        fs::remove_file(&source).ok();
+        fs::remove_dir_all(&dir).ok();
+    }
+}
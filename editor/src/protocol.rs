@@ -0,0 +1,680 @@
+//! Wire format of the data packets collaborators exchange over LiveKit.
+//!
+//! Every packet is a protobuf-encoded `Envelope` stating the protocol version,
+//! the type of message, the document it belongs to and the payload. Receivers
+//! reject envelopes of another protocol version with an error instead of
+//! misreading them, so the editor and the command line tools can evolve the
+//! format without silently breaking each other.
+//!
+//! Messages larger than `MAX_FRAGMENT_LEN` are split into several envelopes,
+//! each carrying a `Fragment` of the payload, and put back together by a
+//! `Reassembler` on the receiving side. Every fragment carries the SHA-256 of
+//! the whole payload, checked once the message is complete, and messages whose
+//! fragments stop arriving are dropped after `REASSEMBLY_TIMEOUT`.
+//!
+//! In a room with a passphrase, every packet is sealed with the room's
+//! `Cipher::for_room` (see `seal_packets`), and packets that do not open with the same
+//! passphrase are rejected (see `open_packet`), whatever tracks their sender
+//! publishes.
+//!
+//! Snapshots and chat are published reliably or lossily as the
+//! `DeliveryPolicy` says; messages split into fragments always go reliably,
+//! since losing one fragment loses the whole message. Sync messages always go
+//! reliably: a lost one leaves the peers apart until the next edit. Cursor and
+//! presence updates, the traffic where only the latest value matters, are not
+//! data packets at all (see below), so they have no delivery setting.
+//!
+//! A peer's snapshot is asked for with a LiveKit RPC call of `SNAPSHOT_METHOD`
+//! rather than a message, so a peer that cannot answer fails the call right
+//! away; the snapshot itself is too large for an RPC response and comes as a
+//! `Snapshot` message.
+//!
+//! Presence does not travel in data packets: it is published as participant
+//! attributes (see `presence_attributes`), which LiveKit keeps up to date for
+//! everyone in the room, including participants who join later.
+//!
+//! Each document is edited in a room of its own, named after the document's id
+//! (see `room_for_document`).
+use crate::backend_api::{Point, Presence, Selection, UserInfo};
+use crate::encryption::Cipher;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Version written in every envelope; envelopes of other versions are rejected.
+/// Bumped only for changes older clients cannot read: additions such as the
+/// checksum of fragmented messages are optional fields instead.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Largest payload sent in one packet, leaving room for the envelope below
+/// LiveKit's limit on the size of data packets.
+pub const MAX_FRAGMENT_LEN: usize = 14_000;
+
+/// Most fragments a message may be split into (about 64 MiB).
+pub const MAX_FRAGMENTS: u32 = 4_800;
+
+/// Most incomplete messages kept per sender. Together with `MAX_FRAGMENTS` this
+/// bounds the memory one corrupt or hostile sender can make the receiver hold.
+pub const MAX_PENDING_MESSAGES: usize = 4;
+
+/// How long the fragments of a message may take to arrive before the
+/// incomplete message is dropped (see `Reassembler::expire`).
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// LiveKit data topic carrying Automerge sync messages.
+pub const SYNC_TOPIC: &str = "whiteboard.sync";
+/// LiveKit data topic carrying chat messages.
+pub const CHAT_TOPIC: &str = "whiteboard.chat";
+
+/// LiveKit byte stream topic carrying files sent to the room or to one participant.
+pub const FILE_TOPIC: &str = "whiteboard.file";
+
+/// LiveKit RPC method asking a peer to send its snapshot of the document whose
+/// id is the payload; the peer answers once it published the `Snapshot`.
+pub const SNAPSHOT_METHOD: &str = "whiteboard.snapshot";
+
+/// Prefix of the names of rooms in which a document is edited.
+pub const ROOM_PREFIX: &str = "doc-";
+
+/// Participant attribute with the id of the document the participant has open.
+pub const DOCUMENT_ATTRIBUTE: &str = "whiteboard.document";
+/// Participant attribute with the pointer position, as `x,y`.
+pub const CURSOR_ATTRIBUTE: &str = "whiteboard.cursor";
+/// Participant attribute with the selected area, as `x,y,x,y`.
+pub const SELECTION_ATTRIBUTE: &str = "whiteboard.selection";
+/// Participant attribute with the display name.
+pub const NAME_ATTRIBUTE: &str = "whiteboard.name";
+/// Participant attribute with the user's color, as `#rrggbbaa`.
+pub const COLOR_ATTRIBUTE: &str = "whiteboard.color";
+
+/// A message of the collaboration protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkMessage {
+    /// CRDT synchronization data.
+    Sync(Vec<u8>),
+    /// Chat message.
+    Chat(String),
+    /// The whole document, as saved by `DocBackend::save`.
+    Snapshot(Vec<u8>),
+}
+
+impl NetworkMessage {
+    /// Topic the message is published on, so the document sync stream is kept
+    /// apart from chat (and from other apps in the room).
+    pub fn topic(&self) -> &'static str {
+        match self {
+            NetworkMessage::Sync(_) | NetworkMessage::Snapshot(_) => SYNC_TOPIC,
+            NetworkMessage::Chat(_) => CHAT_TOPIC,
+        }
+    }
+
+    fn kind(&self) -> MessageKind {
+        match self {
+            NetworkMessage::Sync(_) => MessageKind::Sync,
+            NetworkMessage::Chat(_) => MessageKind::Chat,
+            NetworkMessage::Snapshot(_) => MessageKind::Snapshot,
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        match self {
+            NetworkMessage::Sync(data) | NetworkMessage::Snapshot(data) => data.clone(),
+            NetworkMessage::Chat(text) => text.clone().into_bytes(),
+        }
+    }
+
+    fn from_payload(kind: MessageKind, payload: Vec<u8>) -> Result<Self, ProtocolError> {
+        match kind {
+            MessageKind::Sync => Ok(NetworkMessage::Sync(payload)),
+            MessageKind::Chat => String::from_utf8(payload)
+                .map(NetworkMessage::Chat)
+                .map_err(|e| ProtocolError::Malformed(e.to_string())),
+            MessageKind::Snapshot => Ok(NetworkMessage::Snapshot(payload)),
+            MessageKind::Unspecified => Err(ProtocolError::UnknownKind(kind as i32)),
+        }
+    }
+}
+
+/// How LiveKit delivers a data packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Delivery {
+    /// Retransmitted until received and delivered in order; a lost packet
+    /// holds back the ones after it.
+    #[default]
+    Reliable,
+    /// Sent once and never waited for, for traffic where only the latest
+    /// packet matters.
+    Lossy,
+}
+
+/// Delivery of the classes of messages the user may choose it for, kept in
+/// the settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeliveryPolicy {
+    /// Snapshots of the document.
+    pub snapshot: Delivery,
+    pub chat: Delivery,
+}
+
+impl Default for DeliveryPolicy {
+    fn default() -> Self {
+        Self { snapshot: Delivery::Reliable, chat: Delivery::Reliable }
+    }
+}
+
+impl DeliveryPolicy {
+    /// Whether `message`, encoded into `packets` packets, is published reliably.
+    pub fn is_reliable(&self, message: &NetworkMessage, packets: usize) -> bool {
+        let delivery = match message {
+            NetworkMessage::Sync(_) => Delivery::Reliable,
+            NetworkMessage::Snapshot(_) => self.snapshot,
+            NetworkMessage::Chat(_) => self.chat,
+        };
+        delivery == Delivery::Reliable || packets > 1
+    }
+}
+
+/// Type of the message an envelope carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum MessageKind {
+    Unspecified = 0,
+    Sync = 1,
+    Chat = 2,
+    // 3 carried presence, now published as participant attributes.
+    // 4 asked for a snapshot, now done with a `SNAPSHOT_METHOD` call.
+    Snapshot = 5,
+}
+
+/// Outer layer of every data packet.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Envelope {
+    /// `PROTOCOL_VERSION` of the sender.
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+    /// A `MessageKind`.
+    #[prost(enumeration = "MessageKind", tag = "2")]
+    pub kind: i32,
+    /// Document the message belongs to.
+    #[prost(string, tag = "3")]
+    pub document_id: String,
+    /// The encoded message, or one part of it if `fragment` is set.
+    #[prost(bytes = "vec", tag = "4")]
+    pub payload: Vec<u8>,
+    #[prost(message, optional, tag = "5")]
+    pub fragment: Option<Fragment>,
+}
+
+/// Position of an envelope's payload within a message split into several envelopes.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Fragment {
+    /// Identifier shared by the fragments of one message, random per message.
+    #[prost(uint64, tag = "1")]
+    pub message_id: u64,
+    #[prost(uint32, tag = "2")]
+    pub index: u32,
+    #[prost(uint32, tag = "3")]
+    pub total: u32,
+    /// SHA-256 of the whole payload, the same in every fragment of the message.
+    /// Empty from senders that predate it, whose messages are not checked.
+    #[prost(bytes = "vec", tag = "4")]
+    pub sha256: Vec<u8>,
+}
+
+/// Why a received packet was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolError {
+    /// The packet is not an envelope, or its payload does not match its type.
+    Malformed(String),
+    /// The envelope was written by another version of the protocol.
+    UnsupportedVersion(u32),
+    /// The envelope carries a type of message this version does not know.
+    UnknownKind(i32),
+    /// The fragments of a message did not add up to the payload they were taken from.
+    ChecksumMismatch,
+    /// The message was split into more than `MAX_FRAGMENTS` fragments.
+    TooLarge(u32),
+    /// The sender already has `MAX_PENDING_MESSAGES` incomplete messages.
+    TooManyPending,
+    /// The packet was sealed with another room passphrase, or with none
+    /// while this room has one (or the other way round).
+    WrongPassphrase,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Malformed(e) => write!(f, "Malformed packet: {}", e),
+            ProtocolError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported protocol version {} (this version speaks {})",
+                version, PROTOCOL_VERSION
+            ),
+            ProtocolError::UnknownKind(kind) => write!(f, "Unknown message type {}", kind),
+            ProtocolError::ChecksumMismatch => write!(f, "Reassembled message does not match its checksum"),
+            ProtocolError::TooLarge(total) => write!(f, "Message of {} fragments is too large", total),
+            ProtocolError::TooManyPending => write!(f, "Too many incomplete messages from one sender"),
+            ProtocolError::WrongPassphrase => write!(f, "Packet does not match the room passphrase"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Encodes `message` of `document_id` into the packets to publish, more than
+/// one if its payload is larger than `MAX_FRAGMENT_LEN`.
+pub fn encode(message: &NetworkMessage, document_id: &str) -> Vec<Vec<u8>> {
+    let kind = message.kind() as i32;
+    let payload = message.payload();
+    let envelope = |payload: Vec<u8>, fragment| {
+        Envelope { version: PROTOCOL_VERSION, kind, document_id: document_id.to_string(), payload, fragment }
+            .encode_to_vec()
+    };
+    if payload.len() <= MAX_FRAGMENT_LEN {
+        return vec![envelope(payload, None)];
+    }
+    let message_id = rand::random();
+    let total = payload.len().div_ceil(MAX_FRAGMENT_LEN) as u32;
+    let sha256 = sha256(&payload);
+    payload
+        .chunks(MAX_FRAGMENT_LEN)
+        .enumerate()
+        .map(|(index, part)| {
+            let fragment = Fragment { message_id, index: index as u32, total, sha256: sha256.clone() };
+            envelope(part.to_vec(), Some(fragment))
+        })
+        .collect()
+}
+
+/// Seals each of `packets` (from `encode`) with the room passphrase's
+/// `cipher`; without one they are published as they are.
+pub fn seal_packets(packets: Vec<Vec<u8>>, cipher: Option<&Cipher>) -> Vec<Vec<u8>> {
+    match cipher {
+        Some(cipher) => packets.iter().map(|packet| cipher.seal(packet)).collect(),
+        None => packets,
+    }
+}
+
+/// Splits a file to stream on `FILE_TOPIC` into chunks of at most `chunk_len`
+/// bytes, each sealed with the room passphrase's `cipher` like `seal_packets`
+/// (byte streams are not covered by LiveKit's encryption either). Sealed
+/// chunks are frames that can be concatenated, so the receiver opens the whole
+/// file with `open_packet`.
+pub fn seal_file(data: &[u8], chunk_len: usize, cipher: Option<&Cipher>) -> Vec<Vec<u8>> {
+    seal_packets(data.chunks(chunk_len).map(<[u8]>::to_vec).collect(), cipher)
+}
+
+/// Opens a received packet sealed by `seal_packets`, to be passed to a `Reassembler`.
+///
+/// # Errors
+/// Returns `ProtocolError::WrongPassphrase` if the packet was not sealed with
+/// the passphrase of `cipher`, or is sealed while there is no `cipher`.
+pub fn open_packet(packet: &[u8], cipher: Option<&Cipher>) -> Result<Vec<u8>, ProtocolError> {
+    match cipher {
+        Some(cipher) => cipher.open(packet).map_err(|_| ProtocolError::WrongPassphrase),
+        None if Cipher::is_encrypted(packet) => Err(ProtocolError::WrongPassphrase),
+        None => Ok(packet.to_vec()),
+    }
+}
+
+/// SHA-256 digest of `data`.
+fn sha256(data: &[u8]) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, data).as_ref().to_vec()
+}
+
+/// A complete message taken out of received packets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Incoming {
+    pub document_id: String,
+    pub message: NetworkMessage,
+}
+
+/// Fragments received so far of a message split into several envelopes.
+struct Partial {
+    parts: Vec<Option<Vec<u8>>>,
+    received: usize,
+    /// Checksum the first fragment announced.
+    sha256: Vec<u8>,
+    /// When the first fragment arrived.
+    started: Instant,
+}
+
+/// Decodes received packets, putting fragmented messages back together.
+#[derive(Default)]
+pub struct Reassembler {
+    /// Incomplete messages by sender and message id.
+    partial: HashMap<(String, u64), Partial>,
+}
+
+impl Reassembler {
+    /// Decodes a packet from `sender`. Returns the message it completes, or
+    /// `None` while fragments of the message are still missing.
+    ///
+    /// # Errors
+    /// Returns a `ProtocolError` if the packet is not an envelope of this
+    /// protocol version; the packet is dropped.
+    pub fn receive(&mut self, sender: &str, packet: &[u8]) -> Result<Option<Incoming>, ProtocolError> {
+        self.receive_at(sender, packet, Instant::now())
+    }
+
+    /// `receive` of a packet arriving at `now`.
+    fn receive_at(&mut self, sender: &str, packet: &[u8], now: Instant) -> Result<Option<Incoming>, ProtocolError> {
+        let envelope = Envelope::decode(packet).map_err(|e| ProtocolError::Malformed(e.to_string()))?;
+        if envelope.version != PROTOCOL_VERSION {
+            return Err(ProtocolError::UnsupportedVersion(envelope.version));
+        }
+        let kind = MessageKind::try_from(envelope.kind).map_err(|_| ProtocolError::UnknownKind(envelope.kind))?;
+        let payload = match envelope.fragment {
+            None => envelope.payload,
+            Some(fragment) => {
+                let total = fragment.total as usize;
+                let index = fragment.index as usize;
+                if index >= total {
+                    return Err(ProtocolError::Malformed(format!("fragment {} of {}", index, total)));
+                }
+                if fragment.total > MAX_FRAGMENTS {
+                    return Err(ProtocolError::TooLarge(fragment.total));
+                }
+                let key = (sender.to_string(), fragment.message_id);
+                if !self.partial.contains_key(&key)
+                    && self.partial.keys().filter(|(from, _)| from == sender).count() >= MAX_PENDING_MESSAGES
+                {
+                    return Err(ProtocolError::TooManyPending);
+                }
+                let partial = self.partial.entry(key.clone()).or_insert_with(|| Partial {
+                    parts: vec![None; total],
+                    received: 0,
+                    sha256: fragment.sha256.clone(),
+                    started: now,
+                });
+                if partial.parts.len() != total || partial.sha256 != fragment.sha256 {
+                    self.partial.remove(&key);
+                    return Err(ProtocolError::Malformed("fragments disagree on their message".into()));
+                }
+                if partial.parts[index].is_none() {
+                    partial.parts[index] = Some(envelope.payload);
+                    partial.received += 1;
+                }
+                if partial.received < total {
+                    return Ok(None);
+                }
+                let partial = self.partial.remove(&key).expect("the message was just updated");
+                let payload: Vec<u8> = partial.parts.into_iter().flatten().flatten().collect();
+                if !partial.sha256.is_empty() && sha256(&payload) != partial.sha256 {
+                    return Err(ProtocolError::ChecksumMismatch);
+                }
+                payload
+            }
+        };
+        let message = NetworkMessage::from_payload(kind, payload)?;
+        Ok(Some(Incoming { document_id: envelope.document_id, message }))
+    }
+
+    /// Drops the incomplete messages of `sender`, e.g. after they disconnected.
+    pub fn forget(&mut self, sender: &str) {
+        self.partial.retain(|(from, _), _| from != sender);
+    }
+
+    /// Drops the messages whose first fragment arrived more than
+    /// `REASSEMBLY_TIMEOUT` before `now`, returning their senders (once per message)
+    /// so the loss can be reported instead of passing silently.
+    pub fn expire(&mut self, now: Instant) -> Vec<String> {
+        let mut expired = Vec::new();
+        self.partial.retain(|(sender, _), partial| {
+            let keep = now.saturating_duration_since(partial.started) < REASSEMBLY_TIMEOUT;
+            if !keep {
+                expired.push(sender.clone());
+            }
+            keep
+        });
+        expired
+    }
+}
+
+/// Name of the room in which the document `document_id` is edited.
+pub fn room_for_document(document_id: &str) -> String {
+    format!("{}{}", ROOM_PREFIX, document_id)
+}
+
+/// Id of the document edited in `room`. Rooms not named by `room_for_document`
+/// hold a document identified by the room's name.
+pub fn document_for_room(room: &str) -> &str {
+    room.strip_prefix(ROOM_PREFIX).filter(|id| !id.is_empty()).unwrap_or(room)
+}
+
+/// Participant attributes publishing `presence` in `document_id`. A missing
+/// cursor or selection is written as an empty value, which removes the attribute.
+pub fn presence_attributes(presence: &Presence, document_id: &str) -> HashMap<String, String> {
+    let cursor = presence.cursor.as_ref().map_or(String::new(), |p| format!("{},{}", p.x, p.y));
+    let selection = presence.selection.as_ref().map_or(String::new(), |s| {
+        format!("{},{},{},{}", s.start.x, s.start.y, s.end.x, s.end.y)
+    });
+    let color = presence.user.color.map_or(String::new(), |[r, g, b, a]| format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a));
+    HashMap::from([
+        (DOCUMENT_ATTRIBUTE.to_string(), document_id.to_string()),
+        (CURSOR_ATTRIBUTE.to_string(), cursor),
+        (SELECTION_ATTRIBUTE.to_string(), selection),
+        (NAME_ATTRIBUTE.to_string(), presence.user.name.clone()),
+        (COLOR_ATTRIBUTE.to_string(), color),
+    ])
+}
+
+/// Reads the presence a participant published with `presence_attributes`.
+/// Returns `None` unless it is about `document_id`; malformed values are
+/// treated as missing.
+pub fn presence_from_attributes(attributes: &HashMap<String, String>, document_id: &str) -> Option<Presence> {
+    if attributes.get(DOCUMENT_ATTRIBUTE).map(String::as_str) != Some(document_id) {
+        return None;
+    }
+    let numbers = |key: &str| -> Option<Vec<i32>> {
+        attributes.get(key)?.split(',').map(|n| n.trim().parse().ok()).collect()
+    };
+    let cursor = numbers(CURSOR_ATTRIBUTE).and_then(|n| match n[..] {
+        [x, y] => Some(Point { x, y }),
+        _ => None,
+    });
+    let selection = numbers(SELECTION_ATTRIBUTE).and_then(|n| match n[..] {
+        [x1, y1, x2, y2] => Some(Selection { start: Point { x: x1, y: y1 }, end: Point { x: x2, y: y2 } }),
+        _ => None,
+    });
+    let color = attributes.get(COLOR_ATTRIBUTE).and_then(|hex| {
+        let hex = hex.strip_prefix('#')?;
+        let value = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 8)?;
+        Some(value.to_be_bytes())
+    });
+    let name = attributes.get(NAME_ATTRIBUTE).cloned().unwrap_or_default();
+    Some(Presence { cursor, selection, user: UserInfo { name, color } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_roundtrip_and_fragments_are_reassembled() {
+        let mut reassembler = Reassembler::default();
+        let chat = NetworkMessage::Chat("Hello".into());
+        let packets = encode(&chat, "doc");
+        assert_eq!(packets.len(), 1);
+        let incoming = reassembler.receive("alice", &packets[0]).unwrap().unwrap();
+        assert_eq!(incoming, Incoming { document_id: "doc".into(), message: chat });
+
+        let sync = reassembler.receive("bob", &encode(&NetworkMessage::Sync(vec![1, 2]), "doc")[0]).unwrap().unwrap();
+        assert_eq!(sync.message, NetworkMessage::Sync(vec![1, 2]));
+
+        let large = NetworkMessage::Snapshot((0..MAX_FRAGMENT_LEN * 2 + 10).map(|i| i as u8).collect());
+        let packets = encode(&large, "doc");
+        assert_eq!(packets.len(), 3);
+        // Fragments may arrive out of order and interleaved with other senders.
+        assert_eq!(reassembler.receive("alice", &packets[2]), Ok(None));
+        assert_eq!(reassembler.receive("bob", &packets[0]), Ok(None));
+        assert_eq!(reassembler.receive("alice", &packets[0]), Ok(None));
+        let incoming = reassembler.receive("alice", &packets[1]).unwrap().unwrap();
+        assert_eq!(incoming.message, large);
+        reassembler.forget("bob");
+        assert!(reassembler.partial.is_empty());
+    }
+
+    #[test]
+    fn test_fragments_are_checked_and_expire() {
+        let large = NetworkMessage::Snapshot(vec![7; MAX_FRAGMENT_LEN + 1]);
+        let mut packets = encode(&large, "doc");
+        let start = Instant::now();
+
+        // A fragment altered on the way fails the checksum of the whole message.
+        let mut altered = Envelope::decode(packets[1].as_slice()).unwrap();
+        altered.payload[0] ^= 1;
+        let mut reassembler = Reassembler::default();
+        assert_eq!(reassembler.receive_at("alice", &packets[0], start), Ok(None));
+        let altered = altered.encode_to_vec();
+        assert_eq!(reassembler.receive_at("alice", &altered, start), Err(ProtocolError::ChecksumMismatch));
+        assert!(reassembler.partial.is_empty());
+
+        // Fragments that stop arriving are dropped after the timeout.
+        assert_eq!(reassembler.receive_at("alice", &packets[0], start), Ok(None));
+        assert!(reassembler.expire(start + REASSEMBLY_TIMEOUT / 2).is_empty());
+        assert_eq!(reassembler.expire(start + REASSEMBLY_TIMEOUT), vec!["alice".to_string()]);
+        assert!(reassembler.partial.is_empty());
+
+        let mut huge = Envelope::decode(packets.remove(0).as_slice()).unwrap();
+        huge.fragment.as_mut().unwrap().total = MAX_FRAGMENTS + 1;
+        assert_eq!(
+            reassembler.receive_at("alice", &huge.encode_to_vec(), start),
+            Err(ProtocolError::TooLarge(MAX_FRAGMENTS + 1))
+        );
+    }
+
+    #[test]
+    fn test_unchecked_fragments_and_pending_limit() {
+        let large = NetworkMessage::Snapshot(vec![7; MAX_FRAGMENT_LEN + 1]);
+        let packets = encode(&large, "doc");
+        let mut reassembler = Reassembler::default();
+
+        // Senders that predate the checksum leave it empty.
+        let unchecked: Vec<Vec<u8>> = packets
+            .iter()
+            .map(|packet| {
+                let mut envelope = Envelope::decode(packet.as_slice()).unwrap();
+                envelope.fragment.as_mut().unwrap().sha256.clear();
+                envelope.encode_to_vec()
+            })
+            .collect();
+        assert_eq!(reassembler.receive("alice", &unchecked[0]), Ok(None));
+        assert_eq!(reassembler.receive("alice", &unchecked[1]).unwrap().unwrap().message, large);
+
+        // A sender cannot keep more than `MAX_PENDING_MESSAGES` messages open.
+        for _ in 0..MAX_PENDING_MESSAGES {
+            assert_eq!(reassembler.receive("alice", &encode(&large, "doc")[0]), Ok(None));
+        }
+        assert_eq!(reassembler.receive("alice", &packets[0]), Err(ProtocolError::TooManyPending));
+        assert_eq!(reassembler.receive("bob", &packets[0]), Ok(None));
+        assert_eq!(reassembler.receive("bob", &packets[1]).unwrap().unwrap().message, large);
+    }
+
+    #[test]
+    fn test_passphrase_peers_exchange_sealed_packets() {
+        // Two peers that entered the same passphrase; neither publishes a track.
+        let alice = Cipher::for_room("room secret", "whiteboard-doc");
+        let bob = Cipher::for_room("room secret", "whiteboard-doc");
+        let large = NetworkMessage::Snapshot(vec![3; MAX_FRAGMENT_LEN * 2]);
+        let packets = seal_packets(encode(&large, "doc"), Some(&alice));
+        assert_eq!(packets.len(), 3);
+
+        let mut reassembler = Reassembler::default();
+        let mut received = None;
+        for packet in &packets {
+            received = reassembler.receive("alice", &open_packet(packet, Some(&bob)).unwrap()).unwrap();
+        }
+        assert_eq!(received.unwrap().message, large);
+
+        let plain = encode(&NetworkMessage::Chat("hi".into()), "doc").remove(0);
+        assert_eq!(open_packet(&plain, Some(&bob)), Err(ProtocolError::WrongPassphrase), "Unsealed packets are ignored");
+        assert_eq!(open_packet(&packets[0], None), Err(ProtocolError::WrongPassphrase));
+        let eve = Cipher::for_room("guess", "whiteboard-doc");
+        assert_eq!(open_packet(&packets[0], Some(&eve)), Err(ProtocolError::WrongPassphrase));
+        assert_eq!(open_packet(&plain, None).unwrap(), plain);
+    }
+
+    #[test]
+    fn test_sealed_files_only_open_with_the_passphrase() {
+        let alice = Cipher::for_room("room secret", "whiteboard-doc");
+        let file: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let chunks = seal_file(&file, 400, Some(&alice));
+        assert_eq!(chunks.len(), 3);
+        assert!(!chunks[0].windows(16).any(|w| w == &file[..16]), "Chunks are not sent in plain text");
+
+        let received = chunks.concat();
+        let bob = Cipher::for_room("room secret", "whiteboard-doc");
+        assert_eq!(open_packet(&received, Some(&bob)).unwrap(), file);
+        assert_eq!(open_packet(&chunks[1], None), Err(ProtocolError::WrongPassphrase));
+        let eve = Cipher::for_room("guess", "whiteboard-doc");
+        assert_eq!(open_packet(&chunks[1], Some(&eve)), Err(ProtocolError::WrongPassphrase));
+        assert_eq!(open_packet(&file, Some(&bob)), Err(ProtocolError::WrongPassphrase), "Plain files are rejected");
+        assert_eq!(seal_file(&file, 400, None).concat(), file);
+    }
+
+    #[test]
+    fn test_delivery_policy_keeps_fragments_reliable() {
+        let policy = DeliveryPolicy { chat: Delivery::Lossy, snapshot: Delivery::Lossy };
+        let chat = NetworkMessage::Chat("Hello".into());
+        assert!(!policy.is_reliable(&chat, 1));
+        assert!(policy.is_reliable(&NetworkMessage::Sync(Vec::new()), 1));
+
+        let large = NetworkMessage::Snapshot(vec![0; MAX_FRAGMENT_LEN + 1]);
+        assert!(policy.is_reliable(&large, encode(&large, "doc").len()), "A lost fragment would lose the snapshot");
+        assert!(!policy.is_reliable(&NetworkMessage::Snapshot(vec![0; 10]), 1));
+    }
+
+    #[test]
+    fn test_presence_roundtrips_through_attributes() {
+        let presence = Presence {
+            cursor: Some(Point { x: 10, y: -4 }),
+            selection: Some(Selection { start: Point { x: 1, y: 2 }, end: Point { x: 30, y: 40 } }),
+            user: UserInfo { name: "alice".into(), color: Some([255, 16, 0, 128]) },
+        };
+        let attributes = presence_attributes(&presence, "doc");
+        assert_eq!(attributes[COLOR_ATTRIBUTE], "#ff100080");
+        assert_eq!(presence_from_attributes(&attributes, "doc"), Some(presence));
+        assert_eq!(presence_from_attributes(&attributes, "other"), None, "Presence in another document is ignored");
+        assert_eq!(presence_from_attributes(&HashMap::new(), "doc"), None);
+
+        let idle = presence_attributes(&Presence::default(), "doc");
+        assert_eq!(idle[CURSOR_ATTRIBUTE], "", "A missing cursor removes the attribute");
+        let mut remaining = idle.clone();
+        remaining.retain(|_, value| !value.is_empty());
+        assert_eq!(presence_from_attributes(&remaining, "doc"), Some(Presence::default()));
+    }
+
+    #[test]
+    fn test_rooms_are_named_after_documents() {
+        assert_eq!(room_for_document("Ab3xY"), "doc-Ab3xY");
+        assert_eq!(document_for_room(&room_for_document("Ab3xY")), "Ab3xY");
+        assert_eq!(document_for_room("design-review"), "design-review");
+        assert_eq!(document_for_room(ROOM_PREFIX), ROOM_PREFIX);
+    }
+
+    #[test]
+    fn test_other_versions_and_garbage_are_rejected() {
+        let mut reassembler = Reassembler::default();
+        let future = Envelope {
+            version: PROTOCOL_VERSION + 1,
+            kind: MessageKind::Chat as i32,
+            document_id: "doc".into(),
+            payload: b"hi".to_vec(),
+            fragment: None,
+        };
+        assert_eq!(
+            reassembler.receive("alice", &future.encode_to_vec()),
+            Err(ProtocolError::UnsupportedVersion(PROTOCOL_VERSION + 1))
+        );
+        let unknown = Envelope { version: PROTOCOL_VERSION, kind: 42, ..future };
+        assert_eq!(reassembler.receive("alice", &unknown.encode_to_vec()), Err(ProtocolError::UnknownKind(42)));
+        assert!(matches!(
+            reassembler.receive("alice", br#"{"Message":[1,2,3]}"#),
+            Err(ProtocolError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,99 @@
+//! Active rooms of the LiveKit server, listed and created through its room
+//! service API for the room browser.
+//!
+//! The service API is authorized with `LIVEKIT_API_KEY` and `LIVEKIT_API_SECRET`
+//! (see `token::api_credentials`), so only setups holding them can browse rooms.
+use livekit_api::services::room::{CreateRoomOptions, RoomClient};
+use std::fmt;
+
+/// Seconds a room created from the browser stays open while nobody is in it,
+/// so others can find it before its creator joins.
+pub const EMPTY_TIMEOUT_SECS: u32 = 600;
+
+/// A room as shown in the room browser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomSummary {
+    /// Name of the room.
+    pub name: String,
+    /// Number of participants in the room.
+    pub participants: u32,
+    /// Time the room was created, in seconds since the Unix epoch.
+    pub created_at: i64,
+}
+
+/// Why the rooms could not be listed or created.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RoomsError {
+    /// `LIVEKIT_API_KEY` or `LIVEKIT_API_SECRET` is not set.
+    MissingCredentials,
+    /// The server refused the request or could not be reached.
+    Service(String),
+}
+
+impl fmt::Display for RoomsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoomsError::MissingCredentials => {
+                write!(f, "Browsing rooms needs LIVEKIT_API_KEY and LIVEKIT_API_SECRET")
+            }
+            RoomsError::Service(e) => write!(f, "Room service error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RoomsError {}
+
+/// Client of the room service of the server at `http_url` (see `Settings::http_url`).
+fn client(http_url: &str) -> Result<RoomClient, RoomsError> {
+    let (api_key, api_secret) = crate::token::api_credentials().ok_or(RoomsError::MissingCredentials)?;
+    Ok(RoomClient::with_api_key(http_url, &api_key, &api_secret))
+}
+
+/// Lists the active rooms, busiest first (see `sort`).
+///
+/// # Errors
+/// Returns a `RoomsError` if the credentials are missing or the request failed.
+pub async fn list(http_url: &str) -> Result<Vec<RoomSummary>, RoomsError> {
+    let rooms = client(http_url)?
+        .list_rooms(Vec::new())
+        .await
+        .map_err(|e| RoomsError::Service(e.to_string()))?;
+    let mut rooms: Vec<RoomSummary> = rooms
+        .into_iter()
+        .map(|room| RoomSummary { name: room.name, participants: room.num_participants, created_at: room.creation_time })
+        .collect();
+    sort(&mut rooms);
+    Ok(rooms)
+}
+
+/// Creates the room `name`, kept open for `EMPTY_TIMEOUT_SECS` until someone joins.
+///
+/// # Errors
+/// Returns a `RoomsError` if the credentials are missing or the request failed.
+pub async fn create(http_url: &str, name: &str) -> Result<(), RoomsError> {
+    let options = CreateRoomOptions { empty_timeout: EMPTY_TIMEOUT_SECS, ..Default::default() };
+    client(http_url)?
+        .create_room(name, options)
+        .await
+        .map_err(|e| RoomsError::Service(e.to_string()))?;
+    Ok(())
+}
+
+/// Orders rooms by number of participants, most first, then by name.
+pub fn sort(rooms: &mut [RoomSummary]) {
+    rooms.sort_by(|a, b| b.participants.cmp(&a.participants).then_with(|| a.name.cmp(&b.name)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rooms_are_sorted_busiest_first() {
+        let room = |name: &str, participants| RoomSummary { name: name.into(), participants, created_at: 0 };
+        let mut rooms = vec![room("doc-b", 1), room("lobby", 3), room("doc-a", 1), room("empty", 0)];
+        sort(&mut rooms);
+        let names: Vec<&str> = rooms.iter().map(|room| room.name.as_str()).collect();
+        assert_eq!(names, ["lobby", "doc-a", "doc-b", "empty"]);
+    }
+}
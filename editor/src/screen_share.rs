@@ -0,0 +1,46 @@
+//! Screen shares of other participants, decoded for display beside the document.
+//!
+//! Frames are converted to RGBA as they arrive, and only the latest one is
+//! kept: a UI frame that comes late skips the frames in between instead of
+//! falling behind the presenter.
+use eframe::egui;
+use livekit::prelude::RemoteVideoTrack;
+use livekit::webrtc::native::yuv_helper;
+use livekit::webrtc::prelude::VideoBuffer;
+use livekit::webrtc::video_stream::native::NativeVideoStream;
+use std::sync::{Arc, Mutex};
+
+/// Latest frame of a screen share not yet shown, shared between the decoding
+/// task and the UI.
+pub type LatestFrame = Arc<Mutex<Option<egui::ColorImage>>>;
+
+/// Decodes `track` into `latest` until the track ends, calling `wake` after
+/// every frame so the UI shows it.
+pub async fn receive(track: RemoteVideoTrack, latest: LatestFrame, wake: impl Fn() + Send) {
+    use futures_util::StreamExt;
+    let mut stream = NativeVideoStream::new(track.rtc_track());
+    let mut rgba = Vec::new();
+    while let Some(frame) = stream.next().await {
+        let buffer = frame.buffer.to_i420();
+        let (width, height) = (buffer.width(), buffer.height());
+        let (stride_y, stride_u, stride_v) = buffer.strides();
+        let (data_y, data_u, data_v) = buffer.data();
+        rgba.resize((width * height * 4) as usize, 0);
+        // libyuv's ABGR is R, G, B, A in memory.
+        yuv_helper::i420_to_abgr(
+            data_y,
+            stride_y,
+            data_u,
+            stride_u,
+            data_v,
+            stride_v,
+            &mut rgba,
+            width * 4,
+            width as i32,
+            height as i32,
+        );
+        let image = egui::ColorImage::from_rgba_unmultiplied([width as usize, height as usize], &rgba);
+        *latest.lock().unwrap() = Some(image);
+        wake();
+    }
+}
@@ -0,0 +1,254 @@
+//! User preferences kept across sessions in a TOML config file.
+//!
+//! Connection settings can be overridden with environment variables (or a
+//! `.env` file), which take precedence over the file without being saved to it.
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Preferences of the local user; fields missing from the file keep their defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Color scheme of the interface.
+    pub theme: Theme,
+    /// Font text labels are drawn in on the canvas.
+    pub label_font: LabelFont,
+    /// Scale of the whole interface (1.0 = 100%), changed with Ctrl+= and Ctrl+-;
+    /// between `MIN_ZOOM` and `MAX_ZOOM`.
+    pub zoom: f32,
+    /// Address of the LiveKit server, with or without a `ws://`/`http://` scheme.
+    pub livekit_url: String,
+    /// HTTPS endpoint issuing join tokens (see `token::TokenSource`); when
+    /// empty, tokens are generated from the API secret, for development only.
+    pub token_url: String,
+    /// Identity to join rooms with; a random one is generated when empty.
+    pub identity: String,
+    /// Room offered when joining a session.
+    pub default_room: String,
+    /// Times to retry joining a room that could not be reached, waiting twice
+    /// as long before each attempt; 0 gives up after the first failure.
+    pub connect_retries: u32,
+    /// Milliseconds local edits are gathered for before being sent to peers
+    /// as one sync message; 0 sends every edit right away.
+    pub sync_batch_ms: u64,
+    /// Whether each class of messages is published reliably or lossily.
+    pub delivery: crate::protocol::DeliveryPolicy,
+    /// Seconds between recovery snapshots of unsaved work; 0 disables them.
+    pub recovery_interval_secs: u64,
+    /// Registry name of the document backend used at startup.
+    pub backend: String,
+    /// Keyboard shortcuts the user rebound.
+    pub keymap: crate::keymap::Keymap,
+}
+
+/// Bounds of `Settings::zoom`.
+pub const MIN_ZOOM: f32 = 0.5;
+pub const MAX_ZOOM: f32 = 3.0;
+
+/// Environment variable overriding `Settings::livekit_url`.
+pub const URL_VAR: &str = "LIVEKIT_URL";
+/// Environment variable overriding `Settings::token_url`.
+pub const TOKEN_URL_VAR: &str = "LIVEKIT_TOKEN_URL";
+/// Environment variable overriding `Settings::identity`.
+pub const IDENTITY_VAR: &str = "LIVEKIT_IDENTITY";
+/// Environment variable overriding `Settings::default_room`.
+pub const ROOM_VAR: &str = "LIVEKIT_ROOM";
+/// Environment variable overriding `Settings::backend`.
+pub const BACKEND_VAR: &str = "WHITEBOARD_BACKEND";
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            theme: Theme::default(),
+            label_font: LabelFont::default(),
+            zoom: 1.0,
+            livekit_url: "127.0.0.1:7880".into(),
+            token_url: String::new(),
+            identity: String::new(),
+            default_room: String::new(),
+            connect_retries: 4,
+            sync_batch_ms: 50,
+            delivery: Default::default(),
+            recovery_interval_secs: 30,
+            backend: crate::backend_registry::DEFAULT_BACKEND.into(),
+            keymap: Default::default(),
+        }
+    }
+}
+
+/// Font family of text labels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LabelFont {
+    /// egui's proportional font, as in the rest of the interface.
+    #[default]
+    Proportional,
+    /// egui's monospace font, e.g. for labels lined up in columns.
+    Monospace,
+}
+
+/// Color scheme of the user interface.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum Theme {
+    /// egui's dark visuals.
+    #[default]
+    Dark,
+    /// egui's light visuals.
+    Light,
+    /// Dark or light visuals with the user's own colors.
+    Custom {
+        /// Whether the colors are laid over the dark visuals rather than the light ones.
+        dark: bool,
+        /// Fill of panels and windows.
+        background: [u8; 3],
+        /// Color of selected items and hyperlinks.
+        accent: [u8; 3],
+    },
+}
+
+impl Settings {
+    /// `settings.toml` in the editor's directory under the platform's config
+    /// directory (`$XDG_CONFIG_HOME`, `~/.config` or `%APPDATA%`).
+    pub fn default_path() -> PathBuf {
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .unwrap_or_else(std::env::temp_dir);
+        config.join("collaboratite_editor").join("settings.toml")
+    }
+
+    /// Reads settings from `path`; a missing file gives the defaults. A zoom
+    /// out of bounds, e.g. edited by hand, is brought within them.
+    ///
+    /// # Errors
+    /// Returns `io::ErrorKind::InvalidData` if the file is not valid settings TOML.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(data) => {
+                let mut settings: Self = toml::from_str(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                settings.zoom = if settings.zoom.is_finite() { settings.zoom.clamp(MIN_ZOOM, MAX_ZOOM) } else { 1.0 };
+                Ok(settings)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the settings to `path`, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = toml::to_string_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, data)
+    }
+
+    /// The settings with the values of the `*_VAR` environment variables that are set.
+    pub fn with_env_overrides(&self) -> Self {
+        self.overridden_by(|name| std::env::var(name).ok())
+    }
+
+    /// The settings with the values `lookup` returns for the `*_VAR` variable names.
+    fn overridden_by(&self, lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let mut settings = self.clone();
+        for (var, field) in [
+            (URL_VAR, &mut settings.livekit_url),
+            (TOKEN_URL_VAR, &mut settings.token_url),
+            (IDENTITY_VAR, &mut settings.identity),
+            (ROOM_VAR, &mut settings.default_room),
+            (BACKEND_VAR, &mut settings.backend),
+        ] {
+            if let Some(value) = lookup(var).filter(|v| !v.is_empty()) {
+                *field = value;
+            }
+        }
+        settings
+    }
+
+    /// WebSocket URL of the LiveKit server; `http(s)://` becomes `ws(s)://`
+    /// and a bare `host:port` gets `ws://`.
+    pub fn websocket_url(&self) -> String {
+        let host = self.livekit_url.trim();
+        if host.starts_with("ws://") || host.starts_with("wss://") {
+            host.to_string()
+        } else if let Some(rest) = host.strip_prefix("http://") {
+            format!("ws://{}", rest)
+        } else if let Some(rest) = host.strip_prefix("https://") {
+            format!("wss://{}", rest)
+        } else {
+            format!("ws://{}", host)
+        }
+    }
+
+    /// HTTP URL of the LiveKit server's service API, the counterpart of
+    /// `websocket_url`: `ws(s)://` becomes `http(s)://`.
+    pub fn http_url(&self) -> String {
+        let websocket = self.websocket_url();
+        match websocket.strip_prefix("wss://") {
+            Some(rest) => format!("https://{}", rest),
+            None => format!("http://{}", websocket.trim_start_matches("ws://")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_round_trip_and_default_when_missing() {
+        let path = std::env::temp_dir()
+            .join(format!("settings-{}", std::process::id()))
+            .join("settings.toml");
+        assert_eq!(Settings::load(&path).unwrap(), Settings::default());
+
+        let mut settings = Settings {
+            theme: Theme::Custom { dark: false, background: [250, 240, 220], accent: [200, 80, 0] },
+            label_font: LabelFont::Monospace,
+            zoom: 1.25,
+            livekit_url: "wss://example.livekit.cloud".into(),
+            token_url: "https://example.com/token".into(),
+            identity: "alice".into(),
+            default_room: "design-review".into(),
+            connect_retries: 0,
+            sync_batch_ms: 0,
+            delivery: crate::protocol::DeliveryPolicy { chat: crate::protocol::Delivery::Lossy, ..Default::default() },
+            recovery_interval_secs: 0,
+            ..Settings::default()
+        };
+        settings.keymap.bind(crate::keymap::Action::Save, "Alt+S".parse().unwrap());
+        settings.save(&path).unwrap();
+        assert_eq!(Settings::load(&path).unwrap(), settings);
+
+        fs::write(&path, b"zoom = 2.0\n").unwrap();
+        let partial = Settings::load(&path).unwrap();
+        assert_eq!(partial, Settings { zoom: 2.0, ..Settings::default() }, "Missing fields keep their defaults");
+        fs::write(&path, b"zoom = 40.0\n").unwrap();
+        assert_eq!(Settings::load(&path).unwrap().zoom, MAX_ZOOM);
+        fs::write(&path, b"zoom = nan\n").unwrap();
+        assert_eq!(Settings::load(&path).unwrap().zoom, 1.0);
+        fs::write(&path, b"not = [toml").unwrap();
+        assert_eq!(Settings::load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_env_overrides_and_websocket_url() {
+        let settings = Settings { livekit_url: "https://example.com".into(), identity: "alice".into(), ..Settings::default() };
+        assert_eq!(settings.websocket_url(), "wss://example.com");
+        assert_eq!(Settings::default().websocket_url(), "ws://127.0.0.1:7880");
+        assert_eq!(settings.http_url(), "https://example.com");
+        assert_eq!(Settings::default().http_url(), "http://127.0.0.1:7880");
+
+        let overridden = settings.overridden_by(|var| match var {
+            URL_VAR => Some("ws://10.0.0.1:7880".into()),
+            IDENTITY_VAR => Some(String::new()),
+            _ => None,
+        });
+        assert_eq!(overridden.livekit_url, "ws://10.0.0.1:7880");
+        assert_eq!(overridden.identity, "alice", "Empty variables are ignored");
+        assert_eq!(overridden.backend, settings.backend);
+    }
+}
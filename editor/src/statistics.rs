@@ -0,0 +1,92 @@
+//! Word counts and other statistics of a page, shown in the statistics panel.
+use crate::backend_api::{Shape, Stroke};
+use std::collections::HashMap;
+
+/// Words read per minute when estimating the reading time.
+pub const WORDS_PER_MINUTE: usize = 200;
+
+/// Statistics of the text labels and strokes of one page.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DocumentStatistics {
+    /// Words in the text labels.
+    pub words: usize,
+    /// Characters in the text labels, not counting line breaks.
+    pub characters: usize,
+    /// Lines of text in the text labels.
+    pub lines: usize,
+    /// Number of text labels.
+    pub labels: usize,
+    /// Number of strokes, shapes and labels.
+    pub strokes: usize,
+    /// Share of the strokes each collaborator drew, in percent, largest first.
+    /// Strokes of unknown authorship count towards an empty identity.
+    pub contributions: Vec<(String, f32)>,
+}
+
+impl DocumentStatistics {
+    /// Computes the statistics of `strokes`, with the `(stroke id, identity)`
+    /// pairs of `DocBackend::stroke_authors` attributing them to collaborators.
+    pub fn compute(strokes: &[Stroke], authors: &[(String, String)]) -> Self {
+        let mut statistics = DocumentStatistics { strokes: strokes.len(), ..Default::default() };
+        let authors: HashMap<&str, &str> = authors.iter().map(|(id, author)| (id.as_str(), author.as_str())).collect();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for stroke in strokes {
+            *counts.entry(authors.get(stroke.id.as_str()).copied().unwrap_or_default()).or_default() += 1;
+            if let Some(Shape::Text(text)) = &stroke.shape {
+                statistics.labels += 1;
+                statistics.words += text.split_whitespace().count();
+                statistics.characters += text.chars().filter(|&c| c != '\n' && c != '\r').count();
+                statistics.lines += text.lines().count();
+            }
+        }
+        let mut contributions: Vec<(String, f32)> = counts
+            .into_iter()
+            .map(|(author, count)| (author.to_string(), count as f32 * 100.0 / strokes.len() as f32))
+            .collect();
+        contributions.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        statistics.contributions = contributions;
+        statistics
+    }
+
+    /// Estimated minutes needed to read the text, rounded up; 0 without text.
+    pub fn reading_minutes(&self) -> usize {
+        self.words.div_ceil(WORDS_PER_MINUTE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend_api::Point;
+
+    fn stroke(id: &str, shape: Option<Shape>) -> Stroke {
+        Stroke { points: vec![Point { x: 0, y: 0 }], color: [0, 0, 0, 255], width: 2.0, id: id.into(), shape }
+    }
+
+    #[test]
+    fn test_statistics_count_label_text_and_contributions() {
+        let strokes = vec![
+            stroke("a", Some(Shape::Text("Hello big\nworld".into()))),
+            stroke("b", None),
+            stroke("c", Some(Shape::Text("  one ".into()))),
+            stroke("d", Some(Shape::Rectangle)),
+        ];
+        let authors = vec![("a".into(), "alice".into()), ("b".into(), "bob".into()), ("c".into(), "alice".into())];
+        let statistics = DocumentStatistics::compute(&strokes, &authors);
+        assert_eq!(statistics.words, 4);
+        assert_eq!(statistics.characters, 20);
+        assert_eq!(statistics.lines, 3);
+        assert_eq!(statistics.labels, 2);
+        assert_eq!(statistics.strokes, 4);
+        assert_eq!(statistics.reading_minutes(), 1);
+        assert_eq!(
+            statistics.contributions,
+            vec![("alice".into(), 50.0), ("".into(), 25.0), ("bob".into(), 25.0)],
+            "Strokes without a known author are counted separately"
+        );
+
+        let empty = DocumentStatistics::compute(&[], &[]);
+        assert_eq!(empty, DocumentStatistics::default());
+        assert_eq!(empty.reading_minutes(), 0);
+    }
+}
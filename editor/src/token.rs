@@ -0,0 +1,228 @@
+//! Access tokens for joining LiveKit rooms.
+//!
+//! Outside development, a token server issues the tokens: it is sent
+//! `POST {"room": ..., "identity": ...}` and answers `{"token": ...}`, so the
+//! LiveKit API secret never leaves it. Without a token server, tokens are
+//! generated locally from `LIVEKIT_API_KEY` and `LIVEKIT_API_SECRET`, which is
+//! only fit for development since every client then holds the secret.
+//!
+//! Tokens carry the participant's `Role`: viewers get no `can_publish_data`
+//! grant, so the server drops any edit they would send.
+use livekit_api::access_token;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// How long a token server may take to answer before joining fails.
+pub const SERVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where access tokens come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenSource {
+    /// Token server at this URL.
+    Server(String),
+    /// Generated from the API key and secret in the environment.
+    Local,
+}
+
+/// What a participant may do in a room, granted by their token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// May edit the document and chat.
+    #[default]
+    Editor,
+    /// Only follows the document; its token cannot publish data.
+    Viewer,
+}
+
+impl Role {
+    /// Name of the role, as asked of the token server.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    /// The role named `name` by `as_str`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "editor" => Some(Role::Editor),
+            "viewer" => Some(Role::Viewer),
+            _ => None,
+        }
+    }
+
+    /// The role granted by `token`, read from its claims without verifying the
+    /// signature (the server does that); `None` if it is not a readable JWT.
+    pub fn of_token(token: &str) -> Option<Self> {
+        let claims = jsonwebtoken::dangerous::insecure_decode::<access_token::Claims>(token).ok()?.claims;
+        Some(Self::of_grant(claims.video.can_publish_data))
+    }
+
+    /// The role of a participant granted `can_publish_data` or not. Other
+    /// participants' roles come from the permissions the server reports for
+    /// them, never from what they publish about themselves.
+    pub fn of_grant(can_publish_data: bool) -> Self {
+        if can_publish_data { Role::Editor } else { Role::Viewer }
+    }
+}
+
+/// Why no token could be obtained.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenError {
+    /// `LIVEKIT_API_KEY` or `LIVEKIT_API_SECRET` is not set for local generation.
+    MissingCredentials,
+    /// Signing the token failed.
+    Generate(String),
+    /// The token server could not be reached or gave no token.
+    Server(String),
+    /// The token server's URL is not HTTPS (plain HTTP is only allowed to
+    /// localhost), so the token could be read on the way.
+    InsecureUrl(String),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::MissingCredentials => {
+                write!(f, "No token server is set and LIVEKIT_API_KEY / LIVEKIT_API_SECRET are missing")
+            }
+            TokenError::Generate(e) => write!(f, "Could not generate a token: {}", e),
+            TokenError::Server(e) => write!(f, "Token server error: {}", e),
+            TokenError::InsecureUrl(url) => write!(f, "The token server must use HTTPS: {}", url),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+#[derive(Serialize)]
+struct TokenRequest<'a> {
+    room: &'a str,
+    identity: &'a str,
+    role: Role,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: String,
+}
+
+impl TokenSource {
+    /// The token server at `url`, or local generation if `url` is empty.
+    pub fn new(url: &str) -> Self {
+        match url.trim() {
+            "" => TokenSource::Local,
+            url => TokenSource::Server(url.to_string()),
+        }
+    }
+
+    /// The token server of `$LIVEKIT_TOKEN_URL` (see `settings::TOKEN_URL_VAR`),
+    /// for the command line tools.
+    pub fn from_env() -> Self {
+        Self::new(&std::env::var(crate::settings::TOKEN_URL_VAR).unwrap_or_default())
+    }
+
+    /// Obtains a token letting `identity` join `room` with `role`. A token
+    /// server may grant less than asked for; see `Role::of_token`.
+    ///
+    /// # Errors
+    /// Returns a `TokenError` if the server's URL is not secure, the server
+    /// failed or did not answer within `SERVER_TIMEOUT` or, for local
+    /// generation, the credentials are missing.
+    pub async fn fetch(&self, room: &str, identity: &str, role: Role) -> Result<String, TokenError> {
+        match self {
+            TokenSource::Local => {
+                let (api_key, api_secret) = api_credentials().ok_or(TokenError::MissingCredentials)?;
+                generate(&api_key, &api_secret, room, identity, role)
+            }
+            TokenSource::Server(url) => {
+                check_url(url)?;
+                let server_error = |e: reqwest::Error| TokenError::Server(e.to_string());
+                let response = reqwest::Client::builder()
+                    .timeout(SERVER_TIMEOUT)
+                    .build()
+                    .map_err(server_error)?
+                    .post(url)
+                    .json(&TokenRequest { room, identity, role })
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(server_error)?;
+                Ok(response.json::<TokenResponse>().await.map_err(server_error)?.token)
+            }
+        }
+    }
+}
+
+/// Accepts HTTPS token server URLs, and plain HTTP ones only for a server on
+/// this machine.
+fn check_url(url: &str) -> Result<(), TokenError> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| TokenError::Server(format!("{}: {}", url, e)))?;
+    let local = matches!(parsed.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+    match parsed.scheme() {
+        "https" => Ok(()),
+        "http" if local => Ok(()),
+        _ => Err(TokenError::InsecureUrl(url.to_string())),
+    }
+}
+
+/// `LIVEKIT_API_KEY` and `LIVEKIT_API_SECRET`, if both are set. Only
+/// development setups (and administrators) have them.
+pub fn api_credentials() -> Option<(String, String)> {
+    let api_key = std::env::var("LIVEKIT_API_KEY").ok().filter(|key| !key.is_empty())?;
+    let api_secret = std::env::var("LIVEKIT_API_SECRET").ok().filter(|secret| !secret.is_empty())?;
+    Some((api_key, api_secret))
+}
+
+/// Signs a token with the API key and secret, as a token server would.
+pub fn generate(api_key: &str, api_secret: &str, room: &str, identity: &str, role: Role) -> Result<String, TokenError> {
+    access_token::AccessToken::with_api_key(api_key, api_secret)
+        .with_identity(identity)
+        .with_name(identity)
+        .with_grants(access_token::VideoGrants {
+            room_join: true,
+            room: room.to_string(),
+            can_publish: true,
+            can_publish_data: role == Role::Editor, // Required to send edits and chat messages
+            can_update_own_metadata: true, // Required to publish presence attributes
+            ..Default::default()
+        })
+        .to_jwt()
+        .map_err(|e| TokenError::Generate(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_source_and_local_generation() {
+        assert_eq!(TokenSource::new("  "), TokenSource::Local);
+        assert_eq!(
+            TokenSource::new(" https://tokens.example.com/token "),
+            TokenSource::Server("https://tokens.example.com/token".into())
+        );
+        let token = generate("key", "secret", "room", "alice", Role::Editor).unwrap();
+        assert_eq!(token.split('.').count(), 3, "A JWT has a header, claims and a signature");
+        assert_eq!(Role::of_token(&token), Some(Role::Editor));
+        let viewer = generate("key", "secret", "room", "bob", Role::Viewer).unwrap();
+        assert_eq!(Role::of_token(&viewer), Some(Role::Viewer));
+        assert_eq!(Role::of_token("not a token"), None);
+        assert_eq!(Role::parse(Role::Viewer.as_str()), Some(Role::Viewer));
+    }
+
+    #[test]
+    fn test_token_server_must_use_https() {
+        assert_eq!(check_url("https://tokens.example.com/token"), Ok(()));
+        assert_eq!(check_url("http://localhost:8080/token"), Ok(()));
+        assert_eq!(check_url("http://127.0.0.1/token"), Ok(()));
+        assert_eq!(
+            check_url("http://tokens.example.com/token"),
+            Err(TokenError::InsecureUrl("http://tokens.example.com/token".into()))
+        );
+        assert!(matches!(check_url("not a url"), Err(TokenError::Server(_))));
+    }
+}
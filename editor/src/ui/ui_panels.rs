@@ -4,23 +4,291 @@ use eframe::egui;
 impl AppView {
     /// Handles keyboard shortcuts for the application.
     ///
-    /// * `Cmd/Ctrl + \`: Toddle sidebar visibility.
-    /// * `Cmd/Ctrl + O`: Open file.
-    /// * `Cmd/Ctrl + S`: Save file.
+    /// The commands in `keymap::Action` run on the chords of `settings.keymap`
+    /// (by default Ctrl+\ sidebar, Ctrl+O open, Ctrl+S / Ctrl+Shift+S save,
+    /// Ctrl+Z / Ctrl+Shift+Z undo and redo, Ctrl+Tab / Ctrl+Shift+Tab tabs,
+    /// Ctrl+Shift+P / Ctrl+P the command palette and document switcher and
+    /// Escape to clear the selection). Copy, cut and paste follow the platform:
+    ///
+    /// * `Cmd/Ctrl + C` / `Cmd/Ctrl + X`: Copy / cut the strokes in the selected area.
+    /// * `Cmd/Ctrl + V`: Paste strokes, or text as a text label.
+    ///
+    /// Clipboard shortcuts and clearing the selection are left to text fields while one has focus.
     pub fn handle_shortcuts(&mut self, ctx: &egui::Context) {
-        ctx.input(|i| {
-            if i.modifiers.command && i.key_pressed(egui::Key::Backslash) {
-                self.sidebar.visible = !self.sidebar.visible;
+        use crate::keymap::Action;
+        let keymap = &self.settings.keymap;
+        let pressed = ctx.input_mut(|i| {
+            let pressed = keymap.pressed(i);
+            // Tab would also move the keyboard focus.
+            for action in [Action::NextTab, Action::PreviousTab] {
+                if pressed.contains(&action) {
+                    let chord = keymap.chord(action).0;
+                    i.consume_key(chord.modifiers, chord.logical_key);
+                }
+            }
+            pressed
+        });
+        let typing = ctx.wants_keyboard_input();
+        for action in pressed {
+            // Escape in the palette closes it rather than clearing the selection.
+            if action == Action::SelectNone && (typing || self.palette.is_some()) {
+                continue;
+            }
+            self.run_command(ctx, Command::Action(action));
+        }
+
+        if typing {
+            return;
+        }
+        let (copy, cut, paste) = ctx.input(|i| {
+            let mut paste = None;
+            for event in &i.events {
+                if let egui::Event::Paste(text) = event {
+                    paste = Some(text.clone());
+                }
+            }
+            (
+                i.events.contains(&egui::Event::Copy),
+                i.events.contains(&egui::Event::Cut),
+                paste,
+            )
+        });
+        if copy || cut {
+            self.copy_selection(ctx, cut);
+        }
+        if let Some(text) = paste {
+            self.paste(&text);
+        }
+    }
+
+    /// Renders the "Edit" menu, offering the actions of the editing shortcuts.
+    fn edit_menu(&mut self, ui: &mut egui::Ui) {
+        use crate::keymap::Action;
+        let ctx = ui.ctx().clone();
+        ui.menu_button("Edit", |ui| {
+            let item = |text: &str, shortcut: String| egui::Button::new(text).shortcut_text(shortcut);
+            let command = |key| shortcut_text(&ctx, egui::Modifiers::COMMAND, key);
+            if ui.add_enabled(self.backend.can_undo(), item("Undo", self.action_shortcut(&ctx, Action::Undo))).clicked() {
+                self.handle_intent(Intent::Undo);
+                ui.close();
+            }
+            let redo = item("Redo", self.action_shortcut(&ctx, Action::Redo));
+            if ui.add_enabled(self.backend.can_redo(), redo).clicked() {
+                self.handle_intent(Intent::Redo);
+                ui.close();
+            }
+            ui.separator();
+            let selected = !self.selected_strokes().is_empty();
+            if ui.add_enabled(selected, item("Cut", command(egui::Key::X))).clicked() {
+                self.copy_selection(&ctx, true);
+                ui.close();
+            }
+            if ui.add_enabled(selected, item("Copy", command(egui::Key::C))).clicked() {
+                self.copy_selection(&ctx, false);
+                ui.close();
+            }
+            let deselect = item("Select none", self.action_shortcut(&ctx, Action::SelectNone));
+            if ui.add_enabled(self.whiteboard.selection.is_some(), deselect).clicked() {
+                self.whiteboard.selection = None;
+                ui.close();
+            }
+            ui.separator();
+            if ui.add(item("Command palette…", self.action_shortcut(&ctx, Action::CommandPalette))).clicked() {
+                self.palette = Some(PaletteState::default());
+                ui.close();
+            }
+        });
+    }
+
+    /// Renders the command palette while it is open: a fuzzy search over the
+    /// commands (or just the open documents), run with Enter or a click.
+    pub fn command_palette(&mut self, ctx: &egui::Context) {
+        let Some(palette) = &self.palette else {
+            return;
+        };
+        let commands = self.palette_commands(palette.documents_only);
+        let ranked = crate::palette::rank(&palette.query, commands.iter().map(|(label, _)| label.as_str()));
+
+        // Handled before the text field sees the keys, which would move its cursor.
+        let (up, down, enter, escape) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+        let Some(palette) = &mut self.palette else {
+            return;
+        };
+        if up {
+            palette.selected = palette.selected.saturating_sub(1);
+        }
+        if down && palette.selected + 1 < ranked.len() {
+            palette.selected += 1;
+        }
+        palette.selected = palette.selected.min(ranked.len().saturating_sub(1));
+
+        let mut chosen = enter.then(|| ranked.get(palette.selected).map(|&i| commands[i].1)).flatten();
+        let mut close = escape || enter;
+        let hint = if palette.documents_only { "Go to document…" } else { "Type a command…" };
+        let area = egui::Area::new(egui::Id::new("command_palette"))
+            .order(egui::Order::Foreground)
+            .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 60.0))
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.set_width(420.0);
+                    let query = ui.add(
+                        egui::TextEdit::singleline(&mut palette.query)
+                            .hint_text(hint)
+                            .desired_width(f32::INFINITY),
+                    );
+                    query.request_focus();
+                    if query.changed() {
+                        palette.selected = 0;
+                    }
+                    ui.separator();
+                    egui::ScrollArea::vertical().max_height(320.0).show(ui, |ui| {
+                        if ranked.is_empty() {
+                            ui.weak("No matching commands");
+                        }
+                        for (row, &index) in ranked.iter().enumerate() {
+                            let (label, command) = &commands[index];
+                            let mut button = egui::Button::selectable(row == palette.selected, label.as_str());
+                            if let Command::Action(action) = command {
+                                button = button.shortcut_text(ctx.format_shortcut(&self.settings.keymap.chord(*action).0));
+                            }
+                            let response = ui.add_sized([ui.available_width(), 0.0], button);
+                            if row == palette.selected && (up || down) {
+                                response.scroll_to_me(None);
+                            }
+                            if response.clicked() {
+                                chosen = Some(*command);
+                                close = true;
+                            }
+                        }
+                    });
+                });
+            });
+        if area.response.clicked_elsewhere() {
+            close = true;
+        }
+
+        if close {
+            self.palette = None;
+        }
+        if let Some(command) = chosen {
+            self.run_command(ctx, command);
+        }
+    }
+
+    /// Describes the shortcut `action` is bound to in the keymap.
+    fn action_shortcut(&self, ctx: &egui::Context, action: crate::keymap::Action) -> String {
+        ctx.format_shortcut(&self.settings.keymap.chord(action).0)
+    }
+
+    /// Shows the state of the room connection, with a button to join the
+    /// last room again once disconnected. Laid out right to left.
+    fn connection_chip(&mut self, ui: &mut egui::Ui) {
+        use crate::ui::RoomStatus;
+        if self.room_status == RoomStatus::Disconnected && !self.livekit_room.is_empty() {
+            if ui.button("⟳ Reconnect").on_hover_text(format!("Join room {} again", self.livekit_room)).clicked() {
+                self.connect_or_create_to_room(ui.ctx().clone());
             }
-            if i.modifiers.command && i.key_pressed(egui::Key::O) {
-                self.open_file();
+        }
+        let (color, text) = match self.room_status {
+            RoomStatus::Disconnected => (egui::Color32::GRAY, "Disconnected".to_string()),
+            RoomStatus::Connecting => (egui::Color32::YELLOW, "Connecting…".to_string()),
+            RoomStatus::Connected => (egui::Color32::GREEN, format!("Connected · {} peers", self.backend.peers().len())),
+            RoomStatus::Reconnecting => (egui::Color32::ORANGE, "Reconnecting…".to_string()),
+        };
+        let chip = ui.label(egui::RichText::new(format!("● {}", text)).color(color));
+        if self.room_status != RoomStatus::Disconnected {
+            let encryption = if self.room_passphrase.is_empty() { "" } else { ", end-to-end encrypted" };
+            chip.on_hover_text(format!("Room {} as {}{}", self.livekit_room, self.livekit_identity, encryption));
+        }
+    }
+
+    /// Renders the "Settings" menu; changes are applied and saved right away.
+    fn settings_menu(&mut self, ui: &mut egui::Ui) {
+        ui.menu_button("Settings", |ui| {
+            ui.label("Theme");
+            self.theme_editor(ui);
+
+            ui.separator();
+            ui.label("Label font");
+            let mut font = self.settings.label_font;
+            ui.radio_value(&mut font, crate::settings::LabelFont::Proportional, "Proportional");
+            ui.radio_value(&mut font, crate::settings::LabelFont::Monospace, "Monospace");
+            if font != self.settings.label_font {
+                self.settings.label_font = font;
+                self.save_settings();
             }
-            if i.modifiers.command && i.key_pressed(egui::Key::S) {
-                self.save_file();
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Zoom");
+                let mut zoom = self.settings.zoom;
+                if ui.button("➖").on_hover_text(shortcut_text(ui.ctx(), egui::Modifiers::COMMAND, egui::Key::Minus)).clicked() {
+                    zoom -= 0.1;
+                }
+                if ui.button(format!("{:.0}%", zoom * 100.0)).on_hover_text("Reset to 100%").clicked() {
+                    zoom = 1.0;
+                }
+                if ui.button("➕").on_hover_text(shortcut_text(ui.ctx(), egui::Modifiers::COMMAND, egui::Key::Equals)).clicked() {
+                    zoom += 0.1;
+                }
+                let zoom = zoom.clamp(crate::settings::MIN_ZOOM, crate::settings::MAX_ZOOM);
+                if zoom != self.settings.zoom {
+                    self.settings.zoom = zoom;
+                    self.save_settings();
+                }
+            });
+
+            ui.separator();
+            if ui.button("All settings…").clicked() {
+                self.page = Page::Settings;
+                ui.close();
             }
         });
     }
 
+    /// Radio buttons choosing the theme, with color pickers for a custom one.
+    fn theme_editor(&mut self, ui: &mut egui::Ui) {
+        use crate::settings::Theme;
+        let mut theme = self.settings.theme.clone();
+        ui.radio_value(&mut theme, Theme::Dark, "Dark");
+        ui.radio_value(&mut theme, Theme::Light, "Light");
+        let custom = matches!(theme, Theme::Custom { .. });
+        if ui.radio(custom, "Custom").clicked() && !custom {
+            theme = Theme::Custom { dark: true, background: [30, 30, 40], accent: [0, 120, 215] };
+        }
+        if let Theme::Custom { dark, background, accent } = &mut theme {
+            ui.indent("custom_theme", |ui| {
+                ui.checkbox(dark, "Dark base");
+                ui.horizontal(|ui| {
+                    ui.color_edit_button_srgb(background);
+                    ui.label("Background");
+                });
+                ui.horizontal(|ui| {
+                    ui.color_edit_button_srgb(accent);
+                    ui.label("Accent");
+                });
+            });
+        }
+        if theme != self.settings.theme {
+            // Shown at once, but written to the file only when the pointer is
+            // released, not on every frame of a drag in a color picker.
+            self.settings.theme = theme;
+            self.settings_changed = true;
+            self.theme_unsaved = true;
+        }
+        if self.theme_unsaved && !ui.input(|i| i.pointer.any_down()) {
+            self.theme_unsaved = false;
+            self.save_settings();
+        }
+    }
+
     /// Renders the top menu bar containing action buttons and tool controls.
     pub fn top_bar(&mut self, ctx: &egui::Context) {
         egui::TopBottomPanel::top("topbar").show(ctx, |ui| {
@@ -36,28 +304,164 @@ impl AppView {
                 if ui.button("Save").clicked() {
                     self.save_file();
                 }
+
+                if ui.button("Save As").clicked() {
+                    self.save_file_as();
+                }
                 
                 if ui.button("Open").clicked() {
                     self.open_file();
                 }
 
+                self.edit_menu(ui);
+                let open = self.comments.iter().filter(|c| c.reply_to.is_none() && !c.resolved).count();
+                if ui
+                    .selectable_label(self.comments_panel.visible, format!("💬 Comments ({})", open))
+                    .clicked()
+                {
+                    self.comments_panel.visible = !self.comments_panel.visible;
+                }
+                if ui
+                    .selectable_label(self.history.is_some(), "🕘 History")
+                    .on_hover_text("Preview and restore earlier versions of this page")
+                    .clicked()
+                {
+                    if self.history.is_some() {
+                        self.close_history();
+                    } else {
+                        self.open_history();
+                    }
+                }
+                let read_only = self.backend.is_read_only();
+                if ui
+                    .selectable_label(read_only, "🔒 Read-only")
+                    .on_hover_text("Present the document without editing it; changes from peers still appear")
+                    .clicked()
+                {
+                    self.set_read_only(!read_only);
+                }
+                if ui
+                    .selectable_label(self.event_log_open, "🔔 Log")
+                    .on_hover_text("All room events, including the dismissed notifications")
+                    .clicked()
+                {
+                    self.event_log_open = !self.event_log_open;
+                }
+                if ui
+                    .selectable_label(self.statistics_open, "📊 Statistics")
+                    .on_hover_text("Word count and who drew what on this page")
+                    .clicked()
+                {
+                    self.statistics_open = !self.statistics_open;
+                }
+                self.settings_menu(ui);
+
+                ui.separator();
+
+                let undo_shortcut = self.action_shortcut(ctx, crate::keymap::Action::Undo);
+                let redo_shortcut = self.action_shortcut(ctx, crate::keymap::Action::Redo);
+                if ui
+                    .add_enabled(self.backend.can_undo(), egui::Button::new("↶ Undo"))
+                    .on_hover_text(undo_shortcut)
+                    .clicked()
+                {
+                    self.handle_intent(Intent::Undo);
+                }
+                if ui
+                    .add_enabled(self.backend.can_redo(), egui::Button::new("↷ Redo"))
+                    .on_hover_text(redo_shortcut)
+                    .clicked()
+                {
+                    self.handle_intent(Intent::Redo);
+                }
+
+                ui.separator();
+
+                let (page, pages) = (self.backend.current_page(), self.backend.page_count());
+                if ui.add_enabled(page > 0, egui::Button::new("◀")).clicked() {
+                    self.change_page(Intent::SwitchPage(page - 1));
+                }
+                ui.label(format!("Page {} / {}", page + 1, pages));
+                if ui.add_enabled(page + 1 < pages, egui::Button::new("▶")).clicked() {
+                    self.change_page(Intent::SwitchPage(page + 1));
+                }
+                if ui.add_enabled(!read_only, egui::Button::new("➕ Page")).on_hover_text("Add a page after this one").clicked() {
+                    self.change_page(Intent::AddPage);
+                }
+                if ui
+                    .add_enabled(page > 0 && !read_only, egui::Button::new("🗑 Page"))
+                    .on_hover_text("Delete this page")
+                    .clicked()
+                {
+                    self.change_page(Intent::DeletePage(page));
+                }
+
                 ui.separator();
+
+                if read_only {
+                    ui.weak("Presenting: drawing tools are hidden");
+                } else {
+                    ui.label("Tool:");
+                    ui.radio_value(&mut self.whiteboard.tool, Tool::Pen, "✏ Pen");
+                    ui.radio_value(&mut self.whiteboard.tool, Tool::Eraser, "🧹 Eraser");
+                    ui.radio_value(&mut self.whiteboard.tool, Tool::StrokeEraser, "✂ Stroke eraser");
+                    ui.radio_value(&mut self.whiteboard.tool, Tool::Rectangle, "▭ Rectangle");
+                    ui.radio_value(&mut self.whiteboard.tool, Tool::Ellipse, "◯ Ellipse");
+                    ui.radio_value(&mut self.whiteboard.tool, Tool::Line, "╱ Line");
+                    ui.radio_value(&mut self.whiteboard.tool, Tool::Text, "T Text");
+                    ui.radio_value(&mut self.whiteboard.tool, Tool::Select, "⬚ Select");
                 
-                ui.label("Tool:");
-                ui.radio_value(&mut self.whiteboard.tool, Tool::Pen, "✏ Pen");
-                ui.radio_value(&mut self.whiteboard.tool, Tool::Eraser, "🧹 Eraser");
+                    ui.separator();
                 
-                ui.separator();
+                    if !matches!(self.whiteboard.tool, Tool::Eraser | Tool::StrokeEraser | Tool::Select) {
+                        ui.label("Color:");
+                        ui.color_edit_button_srgba(&mut self.whiteboard.stroke_color);
+                    }
+                    if self.whiteboard.tool == Tool::Text {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.whiteboard.text_label)
+                                .hint_text("Label, then click the canvas")
+                                .desired_width(160.0),
+                        );
+                    }
                 
-                if self.whiteboard.tool == Tool::Pen {
-                    ui.label("Color:");
-                    ui.color_edit_button_srgba(&mut self.whiteboard.stroke_color);
-                }
+                    ui.separator();
                 
+                    ui.label("Size:");
+                    ui.add(egui::Slider::new(&mut self.whiteboard.stroke_width, 1.0..=50.0));
+                }
+
                 ui.separator();
-                
-                ui.label("Size:");
-                ui.add(egui::Slider::new(&mut self.whiteboard.stroke_width, 1.0..=50.0));
+
+                if ui
+                    .checkbox(&mut self.whiteboard.color_by_author, "Color by author")
+                    .on_hover_text("Show each stroke in the color of the collaborator who drew it")
+                    .changed()
+                {
+                    self.redraw();
+                }
+                ui.checkbox(&mut self.minimap.visible, "Minimap")
+                    .on_hover_text("Overview of the page beside the canvas");
+
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    self.connection_chip(ui);
+                    // Right to left: peers in reverse so they read in order, the local user last.
+                    for peer in self.backend.peers().iter().rev() {
+                        let response = avatar(ui, peer.display_name(), peer_color(peer))
+                            .on_hover_text(format!("{}\nClick to show their pointer", peer.peer_id));
+                        if response.clicked() {
+                            if peer.presence.cursor.is_some() {
+                                self.pinged_peer = Some((peer.peer_id.clone(), std::time::Instant::now()));
+                            } else {
+                                self.status = format!("{}'s pointer is not over the canvas", peer.display_name());
+                            }
+                        }
+                    }
+                    if self.in_room() {
+                        avatar(ui, &self.livekit_identity, crate::ui::get_user_color(&self.livekit_identity))
+                            .on_hover_text(format!("{} (you)", self.livekit_identity));
+                    }
+                });
             });
         });
     }
@@ -71,129 +475,655 @@ impl AppView {
             .resizable(true)
             .default_width(self.sidebar.default_width)
             .show(ctx, |ui| {
-                if self.livekit_connected {
+                ui.horizontal(|ui| {
+                    ui.label("Title:");
+                    let response = ui.text_edit_singleline(&mut self.title_draft);
+                    if response.lost_focus() && self.title_draft != self.backend.title() {
+                        let title = self.title_draft.clone();
+                        match self.backend.set_title(&title) {
+                            Ok(()) => {
+                                self.conflicts.retain(|c| c.key != "title");
+                                self.sync_with_all();
+                                self.autosave();
+                            }
+                            Err(e) => self.report_backend_error(e),
+                        }
+                    } else if !response.has_focus() {
+                        self.title_draft = self.backend.title();
+                    }
+                });
+                let title_values = self.conflicts.iter()
+                    .find(|c| c.key == "title")
+                    .map(|c| c.values.clone());
+                if let Some(values) = title_values {
+                    let mut picked = None;
+                    ui.menu_button(
+                        egui::RichText::new(format!("⚠ {} conflicting titles", values.len())).color(egui::Color32::ORANGE),
+                        |ui| {
+                            ui.label("Keep:");
+                            for value in &values {
+                                if ui.button(value).clicked() {
+                                    picked = Some(value.clone());
+                                }
+                            }
+                        },
+                    );
+                    if let Some(title) = picked {
+                        match self.backend.set_title(&title) {
+                            Ok(()) => {
+                                self.conflicts.retain(|c| c.key != "title");
+                                self.title_draft = title;
+                                self.sync_with_all();
+                                self.autosave();
+                            }
+                            Err(e) => self.report_backend_error(e),
+                        }
+                    }
+                }
+                if let Some(modified_at) = self.backend.modified_at() {
+                    ui.label(format!("Last modified: {}", crate::ui::format_modified_at(modified_at)));
+                }
+
+                ui.collapsing("Documents", |ui| {
+                    let mut switch = None;
+                    for (index, (name, dirty)) in self.tab_labels().into_iter().enumerate() {
+                        let label = if dirty { format!("● {}", name) } else { name };
+                        let response = ui.add_enabled(
+                            !self.in_room(),
+                            egui::Button::selectable(index == self.active_tab, label),
+                        );
+                        if response.clicked() {
+                            switch = Some(index);
+                        }
+                    }
+                    if let Some(index) = switch {
+                        self.switch_tab(index);
+                    }
+                });
+
+                ui.collapsing("Workspace", |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.button("Open Folder…").clicked() {
+                            self.open_workspace();
+                        }
+                        if ui
+                            .add_enabled(self.workspace.is_some(), egui::Button::new("⟳"))
+                            .on_hover_text("Re-read the folder from disk")
+                            .clicked()
+                        {
+                            self.workspace_listings.clear();
+                        }
+                    });
+                    if let Some(folder) = self.workspace.clone() {
+                        ui.add_enabled_ui(!self.in_room(), |ui| {
+                            egui::ScrollArea::vertical()
+                                .id_salt("workspace_tree")
+                                .max_height(300.0)
+                                .show(ui, |ui| self.workspace_tree(ui, &folder));
+                        })
+                        .response
+                        .on_disabled_hover_text("Disconnect first: peers sync with the document shown");
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Passphrase:");
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut self.passphrase)
+                            .password(true)
+                            .hint_text("none (not encrypted)"),
+                    );
+                    // Keystrokes are not applied one by one; each would rewrite the file.
+                    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        self.apply_passphrase();
+                    }
+                })
+                .response
+                .on_hover_text("Used to encrypt the document file; press Enter to apply it to the open file");
+
+                ui.horizontal(|ui| {
+                    ui.label("Compact once history exceeds");
+                    ui.add(egui::DragValue::new(&mut self.compaction_threshold).range(0..=100_000));
+                    ui.label("changes");
+                })
+                .response
+                .on_hover_text("Compacting drops the whole history, keeping only the current content");
+                if ui
+                    .add_enabled(!self.in_room(), egui::Button::new("Compact history"))
+                    .on_hover_text("Continues as a new document: copies shared earlier no longer merge with it")
+                    .on_disabled_hover_text("Disconnect first: peers still hold the old history")
+                    .clicked()
+                {
+                    self.compact_history();
+                }
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            !self.in_room() && self.branch_parent.is_none(),
+                            egui::Button::new("Duplicate"),
+                        )
+                        .on_hover_text("Experiment on a copy of the document")
+                        .clicked()
+                    {
+                        self.duplicate_document();
+                    }
+                    if ui
+                        .add_enabled(self.branch_parent.is_some(), egui::Button::new("Merge"))
+                        .on_hover_text("Bring the duplicate's changes back into the original")
+                        .clicked()
+                    {
+                        self.merge_branch();
+                    }
+                });
+
+                ui.separator();
+
+                if self.in_room() {
                     ui.colored_label(egui::Color32::GREEN, format!("Connected: {}", self.livekit_room));
                     if ui.button("Disconnect from Session").clicked() {
                         self.disconnect_room();
                     }
                 } else {
                     if ui.button("Share").clicked() {
-                        self.livekit_room = "".into(); // Force new name generation
+                        self.livekit_room = "".into(); // Share in the document's own room
                         self.connect_or_create_to_room(ctx.clone());
-                        self.page = Page::LiveKit;
+                        self.show_livekit();
                     }
                     
                     ui.horizontal(|ui| {
                         ui.label("Room:");
                         ui.text_edit_singleline(&mut self.livekit_room);
                     });
+                    ui.horizontal(|ui| {
+                        ui.label("Passphrase:");
+                        ui.add(egui::TextEdit::singleline(&mut self.room_passphrase).password(true).hint_text("none"))
+                            .on_hover_text(ROOM_PASSPHRASE_HINT);
+                    });
+                    ui.checkbox(&mut self.join_as_viewer, "Join as viewer")
+                        .on_hover_text("Follow the document without editing it or chatting");
                     if !self.livekit_room.is_empty() {
                          if ui.button("Join Session").clicked() {
                              self.connect_or_create_to_room(ctx.clone());
-                             self.page = Page::LiveKit;
+                             self.show_livekit();
                         }
                     }
+                    if ui.button("Browse rooms…").clicked() {
+                        self.open_room_browser(ctx);
+                    }
                 }
                 
                 ui.separator();
 
                 // new: open LiveKit page
                 if ui.button("Open LiveKit Console").clicked() {
-                    self.page = Page::LiveKit;
+                    self.show_livekit();
                 }
             });
     }
 
-    /// Renders the LiveKit connection and debugging panel.
-    pub fn livekit_panel(&mut self, ctx: &egui::Context) {
+    /// Renders the settings page; every change is saved to the settings file right away.
+    pub fn settings_page(&mut self, ctx: &egui::Context) {
+        use crate::protocol::Delivery;
+        use crate::settings::{BACKEND_VAR, IDENTITY_VAR, ROOM_VAR, TOKEN_URL_VAR, URL_VAR};
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.vertical(|ui| {
-                ui.horizontal(|ui| {
-                    if ui.button("Back to Editor").clicked() {
-                        self.page = Page::Editor;
+            ui.horizontal(|ui| {
+                if ui.button("Back to Editor").clicked() {
+                    self.page = Page::Editor;
+                }
+                ui.heading("Settings");
+            });
+            ui.weak(format!("Saved to {}", self.settings_path.display()));
+            ui.separator();
+
+            let before = self.settings.clone();
+            // Shown next to fields whose value comes from an environment variable instead.
+            let env_note = |ui: &mut egui::Ui, var: &str| {
+                if let Ok(value) = std::env::var(var) {
+                    if !value.is_empty() {
+                        ui.weak(format!("overridden by ${}", var)).on_hover_text(value);
+                    }
+                }
+            };
+
+            ui.heading("Collaboration");
+            egui::Grid::new("connection_settings").num_columns(3).show(ui, |ui| {
+                ui.label("LiveKit URL");
+                ui.text_edit_singleline(&mut self.settings.livekit_url);
+                env_note(ui, URL_VAR);
+                ui.end_row();
+                ui.label("Token server");
+                ui.add(egui::TextEdit::singleline(&mut self.settings.token_url).hint_text("local (development)"))
+                    .on_hover_text("HTTPS endpoint (HTTP only on localhost) answering POST {room, identity} with a join token; without one, tokens are signed with LIVEKIT_API_SECRET");
+                env_note(ui, TOKEN_URL_VAR);
+                ui.end_row();
+                ui.label("Identity");
+                ui.add(egui::TextEdit::singleline(&mut self.settings.identity).hint_text("random"));
+                env_note(ui, IDENTITY_VAR);
+                ui.end_row();
+                ui.label("Default room");
+                ui.text_edit_singleline(&mut self.settings.default_room);
+                env_note(ui, ROOM_VAR);
+                ui.end_row();
+                ui.label("Connection retries");
+                ui.add(egui::DragValue::new(&mut self.settings.connect_retries).range(0..=10))
+                    .on_hover_text("Times to retry joining a room that cannot be reached, waiting twice as long each time");
+                ui.end_row();
+                ui.label("Send edits every");
+                ui.add(egui::DragValue::new(&mut self.settings.sync_batch_ms).range(0..=1000).suffix(" ms"))
+                    .on_hover_text("Edits made within this time are sent to peers together, in one message; 0 sends each edit at once");
+                ui.end_row();
+            });
+            ui.collapsing("Delivery", |ui| {
+                ui.weak("Lossy messages are never resent, so they cannot hold back the others; edits and split messages are always reliable.");
+                egui::Grid::new("delivery_settings").num_columns(2).show(ui, |ui| {
+                    let delivery = &mut self.settings.delivery;
+                    let classes = [("Snapshots", &mut delivery.snapshot), ("Chat", &mut delivery.chat)];
+                    for (label, value) in classes {
+                        ui.label(label);
+                        ui.horizontal(|ui| {
+                            ui.selectable_value(value, Delivery::Reliable, "Reliable");
+                            ui.selectable_value(value, Delivery::Lossy, "Lossy");
+                        });
+                        ui.end_row();
                     }
-                    ui.label(if self.livekit_connecting {
-                        "Connecting..."
-                    } else {
-                        "LiveKit"
-                    });
                 });
+            });
+            if self.in_room() {
+                ui.weak("Connection changes apply to the next session.");
+            }
 
-                ui.separator();
+            ui.separator();
+            ui.heading("Appearance");
+            self.theme_editor(ui);
+
+            ui.separator();
+            ui.heading("Document");
+            ui.horizontal(|ui| {
+                ui.label("Recovery snapshot every");
+                ui.add(egui::DragValue::new(&mut self.settings.recovery_interval_secs).range(0..=3600).suffix(" s"));
+            })
+            .response
+            .on_hover_text("Unsaved work is copied aside this often, to be restored after a crash; 0 turns it off");
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("Backend")
+                    .selected_text(&self.settings.backend)
+                    .show_ui(ui, |ui| {
+                        for entry in crate::backend_registry::BACKENDS {
+                            ui.selectable_value(&mut self.settings.backend, entry.name.to_string(), entry.name)
+                                .on_hover_text(entry.description);
+                        }
+                    });
+                env_note(ui, BACKEND_VAR);
+            });
+
+            ui.separator();
+            ui.heading("Shortcuts");
+            self.shortcut_editor(ui);
+
+            if self.settings != before {
+                self.save_settings();
+                let connection = |s: &crate::settings::Settings| (s.livekit_url.clone(), s.identity.clone(), s.default_room.clone());
+                if connection(&self.settings) != connection(&before) {
+                    self.apply_connection_settings();
+                }
+                if self.settings.backend != before.backend && self.settings.backend != self.backend_name {
+                    let name = self.settings.backend.clone();
+                    self.switch_backend(&name);
+                }
+            }
+        });
+    }
 
+    /// Lists the keymap; clicking a shortcut records the next key pressed
+    /// (Escape cancels) as the new chord of that action.
+    fn shortcut_editor(&mut self, ui: &mut egui::Ui) {
+        use crate::keymap::{Action, Chord};
+        if let Some(action) = self.recording_shortcut {
+            let key = ui.input(|i| {
+                i.events.iter().find_map(|event| match event {
+                    egui::Event::Key { key, pressed: true, modifiers, .. } => Some((*key, *modifiers)),
+                    _ => None,
+                })
+            });
+            if let Some((key, modifiers)) = key {
+                self.recording_shortcut = None;
+                if key != egui::Key::Escape {
+                    self.settings.keymap.bind(action, Chord::new(modifiers, key));
+                }
+            }
+        }
+
+        egui::Grid::new("shortcuts").num_columns(3).show(ui, |ui| {
+            for action in Action::ALL {
+                ui.label(action.label());
+                let recording = self.recording_shortcut == Some(action);
+                let text = if recording {
+                    "Press a key…".to_string()
+                } else {
+                    self.action_shortcut(ui.ctx(), action)
+                };
+                if ui.add(egui::Button::selectable(recording, text)).clicked() {
+                    self.recording_shortcut = if recording { None } else { Some(action) };
+                }
                 ui.horizontal(|ui| {
-                    ui.label("Room:");
-                    ui.text_edit_singleline(&mut self.livekit_room);
+                    if ui.add_enabled(self.settings.keymap.is_rebound(action), egui::Button::new("Reset")).clicked() {
+                        self.settings.keymap.reset(action);
+                    }
+                    let conflicts = self.settings.keymap.conflicts(action);
+                    if !conflicts.is_empty() {
+                        let names: Vec<&str> = conflicts.iter().map(|a| a.label()).collect();
+                        ui.colored_label(ui.visuals().warn_fg_color, format!("⚠ also {}", names.join(", ")));
+                    }
                 });
-                ui.horizontal(|ui| {
-                    ui.label("Identity:");
-                    ui.text_edit_singleline(&mut self.livekit_identity);
+                ui.end_row();
+            }
+        });
+    }
+
+    /// Renders the LiveKit connection and debugging panel on its own page.
+    pub fn livekit_panel(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Back to Editor").clicked() {
+                    self.page = Page::Editor;
+                }
+                if ui.button("Dock beside editor").on_hover_text("Keep drawing with this panel on the side").clicked() {
+                    self.livekit_docked = true;
+                    self.page = Page::Editor;
+                }
+                ui.label(self.livekit_heading());
+            });
+            ui.separator();
+            self.livekit_contents(ui);
+        });
+    }
+
+    /// Renders the LiveKit panel beside the canvas, while it is docked there.
+    pub fn livekit_dock(&mut self, ctx: &egui::Context) {
+        egui::SidePanel::right("livekit").resizable(true).default_width(320.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.strong(self.livekit_heading());
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui.small_button("✖").on_hover_text("Close").clicked() {
+                        self.livekit_docked = false;
+                    }
+                    if ui.small_button("⛶").on_hover_text("Show on its own page").clicked() {
+                        self.livekit_docked = false;
+                        self.page = Page::LiveKit;
+                    }
                 });
+            });
+            ui.separator();
+            self.livekit_contents(ui);
+        });
+    }
 
-                ui.separator();
-                // Create room via Admin API (Cloud / Enterprise only)
-                if self.livekit_connected {
-                    if ui.button("Disconnect").clicked() {
-                        self.disconnect_room();
+    fn livekit_heading(&self) -> &'static str {
+        if self.room_status == crate::ui::RoomStatus::Connecting {
+            "Connecting..."
+        } else {
+            "LiveKit"
+        }
+    }
+
+    /// Connection settings, engine statistics, events, participants and chat
+    /// of the LiveKit panel, wherever it is shown.
+    fn livekit_contents(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Room:");
+            ui.text_edit_singleline(&mut self.livekit_room);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Identity:");
+            ui.text_edit_singleline(&mut self.livekit_identity);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Passphrase:");
+            ui.add_enabled(
+                !self.in_room(),
+                egui::TextEdit::singleline(&mut self.room_passphrase).password(true).hint_text("none"),
+            )
+            .on_hover_text(ROOM_PASSPHRASE_HINT);
+        });
+        ui.add_enabled(!self.in_room(), egui::Checkbox::new(&mut self.join_as_viewer, "Join as viewer"))
+            .on_hover_text("Follow the document without editing it or chatting");
+
+        ui.separator();
+        // Create room via Admin API (Cloud / Enterprise only)
+        if self.in_room() {
+            if ui.button("Disconnect").clicked() {
+                self.disconnect_room();
+            }
+        } else {
+            if ui.button("Connect").clicked() {
+                self.connect_or_create_to_room(ui.ctx().clone());
+            }
+        }
+        if ui.button("Browse rooms…").clicked() {
+            self.open_room_browser(&ui.ctx().clone());
+        }
+
+        ui.separator();
+
+        ui.heading("Engine:");
+        egui::Grid::new("backend_stats").show(ui, |ui| {
+            let stats = &self.stats;
+            ui.label("Operations");
+            ui.label(stats.op_count.to_string());
+            ui.end_row();
+            ui.label("Tombstones");
+            ui.label(stats.tombstone_count.to_string());
+            ui.end_row();
+            ui.label("Peers");
+            ui.label(stats.peer_count.to_string());
+            ui.end_row();
+            ui.label("Pending sync");
+            ui.label(format!("{} B", stats.pending_sync_bytes));
+            ui.end_row();
+            ui.label("Memory (approx.)");
+            ui.label(format!("{:.1} KB", stats.memory_bytes as f64 / 1024.0));
+            ui.end_row();
+        });
+
+        ui.separator();
+
+        ui.heading("Events:");
+        let events = {
+            let guard = self.livekit_events.lock().unwrap();
+            guard.clone()
+        };
+        // Leaves room for the participants and the message field below.
+        egui::ScrollArea::vertical().max_height(ui.available_height() / 2.0).show(ui, |ui| {
+            for ev in events.iter().rev() {
+                ui.label(ev);
+            }
+        });
+        // if connected to the room: Area where messages
+        // can be typed and sent displays
+        ui.separator();
+
+        if self.in_room() {
+            ui.heading("Participants:");
+            let participants = self.participant_names();
+            egui::ScrollArea::vertical()
+                .id_salt("participants_list") // Add unique ID
+                .max_height(100.0)
+                .show(ui, |ui| {
+                    for p in participants {
+                        ui.horizontal(|ui| {
+                            let speaking = self.speakers.contains(&p);
+                            let marker = ui.label(if speaking { "🔊" } else { "•" });
+                            if speaking {
+                                marker.on_hover_text("Speaking");
+                            }
+                            ui.label(&p);
+                            if let Some(badge) = self.role_badge(&p) {
+                                ui.weak(badge);
+                            }
+                            if let Some(share) = self.screen_shares.iter_mut().find(|share| share.peer == p) {
+                                if ui.small_button("🖥").on_hover_text("Show their shared screen").clicked() {
+                                    share.open = true;
+                                }
+                            }
+                            if p != self.livekit_identity {
+                                let mut volume = self.volumes.get(&p).copied().unwrap_or(1.0);
+                                let slider = egui::Slider::new(&mut volume, 0.0..=2.0).show_value(false);
+                                if ui.add(slider).on_hover_text("Volume").changed() {
+                                    self.set_volume(&p, volume);
+                                }
+                            }
+                        });
                     }
-                } else {
-                    if ui.button("Connect").clicked() {
-                        self.connect_or_create_to_room(ctx.clone());
+                });
+            ui.separator();
+            self.voice(ui);
+            ui.separator();
+            self.file_transfers(ui);
+            ui.separator();
+        }
+
+        // message input + send button (visible when not connecting)
+        if self.in_room() {
+            ui.horizontal(|ui| {
+                ui.label("Message:");
+                ui.text_edit_singleline(&mut self.livekit_message);
+                if ui.button("Send").clicked() {
+                    // locally record the outgoing message
+                    {
+                        let mut guard = self.livekit_events.lock().unwrap();
+                        guard.push(format!("You: {}", self.livekit_message));
                     }
+                    self.send_livekit_message(self.livekit_message.clone());
+                    self.livekit_message.clear();
                 }
+            });
+        } else {
+            ui.label("Connect to a room to send and see participants messages.");
+        }
+    }
 
-                ui.separator();
+    /// Voice section of the LiveKit panel: publishing the microphone and muting it.
+    fn voice(&mut self, ui: &mut egui::Ui) {
+        use crate::voice::MicrophoneState;
+        ui.heading("Voice:");
+        ui.horizontal(|ui| {
+            match self.microphone {
+                MicrophoneState::Off => {
+                    let enabled = self.role != Some(Role::Viewer);
+                    let button = ui.add_enabled(enabled, egui::Button::new("🎤 Start microphone"));
+                    if button.on_disabled_hover_text("Viewers cannot publish audio").clicked() {
+                        self.set_microphone(MicrophoneState::Live);
+                    }
+                }
+                state => {
+                    let muted = state == MicrophoneState::Muted;
+                    if ui.selectable_label(muted, if muted { "🔇 Unmute" } else { "🔊 Mute" }).clicked() {
+                        self.set_microphone(if muted { MicrophoneState::Live } else { MicrophoneState::Muted });
+                    }
+                    if ui.button("Stop").clicked() {
+                        self.set_microphone(MicrophoneState::Off);
+                    }
+                    ui.weak(if muted { "Muted" } else { "Live" });
+                }
+            }
+        });
+    }
 
-                ui.heading("Events:");
-                let events = {
-                    let guard = self.livekit_events.lock().unwrap();
-                    guard.clone()
-                };
-                egui::ScrollArea::vertical().show(ui, |ui| {
-                    for ev in events.iter().rev() {
-                        ui.label(ev);
+    /// Files section of the LiveKit panel: sending files or the document to the
+    /// room or one participant, and the transfers with their progress.
+    fn file_transfers(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Files:");
+        let can_send = self.role != Some(Role::Viewer);
+        ui.horizontal(|ui| {
+            ui.label("To:");
+            egui::ComboBox::from_id_salt("file_recipient")
+                .selected_text(self.file_recipient.as_deref().unwrap_or("Everyone"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.file_recipient, None, "Everyone");
+                    for peer in self.backend.peers() {
+                        let label = peer.peer_id.clone();
+                        ui.selectable_value(&mut self.file_recipient, Some(peer.peer_id), label);
                     }
                 });
-                // if connected to the room: Area where messages
-                // can be typed and sent displays
-                ui.separator();
+            if ui.add_enabled(can_send, egui::Button::new("Send file…")).clicked() {
+                self.pick_and_send_file();
+            }
+            let send_document = ui.add_enabled(can_send, egui::Button::new("Send document"));
+            if send_document.on_hover_text("Send the document as a .crdt file").clicked() {
+                self.send_document();
+            }
+        });
+        let mut save = None;
+        egui::ScrollArea::vertical().id_salt("file_transfers").max_height(120.0).show(ui, |ui| {
+            for (index, transfer) in self.transfers.iter().enumerate().rev() {
+                ui.horizontal(|ui| {
+                    let arrow = if transfer.incoming { "⬇" } else { "⬆" };
+                    ui.label(format!("{} {} ({})", arrow, transfer.name, transfer.peer));
+                    if let Some(error) = &transfer.error {
+                        ui.colored_label(egui::Color32::RED, "failed").on_hover_text(error);
+                    } else if transfer.finished {
+                        if transfer.data.is_some() && ui.small_button("Save…").clicked() {
+                            save = Some(index);
+                        }
+                    } else {
+                        let progress = transfer.total.filter(|total| *total > 0).map_or(0.0, |total| transfer.done as f32 / total as f32);
+                        ui.add(egui::ProgressBar::new(progress).desired_width(100.0).show_percentage());
+                    }
+                });
+            }
+        });
+        if let Some(index) = save {
+            self.save_received_file(index);
+        }
+        if self.transfers.iter().any(|t| t.finished) && ui.small_button("Clear finished").clicked() {
+            self.transfers.retain(|t| !t.finished);
+        }
+    }
 
-                if self.livekit_connected {
-                    ui.heading("Participants:");
-                    let participants = {
-                        let guard = self.livekit_participants.lock().unwrap();
-                        guard.clone()
-                    };
-                    egui::ScrollArea::vertical()
-                        .id_salt("participants_list") // Add unique ID
-                        .max_height(100.0)
-                        .show(ui, |ui| {
-                            for p in participants {
-                                ui.label(format!("• {}", p));
-                            }
-                        });
-                    ui.separator();
+    /// Zoomed-out view of the page beside the canvas, with the part shown in the
+    /// editor outlined and peers' pointers marked. Clicking or dragging on it
+    /// scrolls the editor there.
+    pub fn minimap(&mut self, ctx: &egui::Context) {
+        if !self.minimap.visible {
+            return;
+        }
+        let Some(texture_id) = self.whiteboard.texture.as_ref().map(|texture| texture.id()) else {
+            return;
+        };
+        let width = self.whiteboard.image.width() as f32;
+        let height = self.whiteboard.image.height() as f32;
+        egui::SidePanel::right("minimap").resizable(false).exact_width(MINIMAP_WIDTH + 16.0).show(ctx, |ui| {
+            ui.add_space(8.0);
+            let size = egui::vec2(MINIMAP_WIDTH, MINIMAP_WIDTH * height / width);
+            let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click_and_drag());
+            let painter = ui.painter_at(rect.expand(1.0));
+            let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+            painter.image(texture_id, rect, uv, egui::Color32::WHITE);
+            painter.rect_stroke(rect, 0.0, egui::Stroke::new(1.0, egui::Color32::GRAY), egui::StrokeKind::Outside);
+            let to_map = |x: f32, y: f32| rect.min + egui::vec2(x, y) * (rect.width() / width);
+
+            if let Some(viewport) = self.minimap.viewport {
+                let area = egui::Rect::from_min_max(to_map(viewport.min.x, viewport.min.y), to_map(viewport.max.x, viewport.max.y))
+                    .intersect(rect);
+                let color = ui.visuals().selection.bg_fill;
+                painter.rect_filled(area, 0.0, color.gamma_multiply(0.2));
+                painter.rect_stroke(area, 0.0, egui::Stroke::new(1.5, color), egui::StrokeKind::Inside);
+            }
+            for peer in self.backend.peers() {
+                if let Some(cursor) = &peer.presence.cursor {
+                    painter.circle_filled(to_map(cursor.x as f32, cursor.y as f32), 3.0, peer_color(&peer));
                 }
+            }
 
-                // message input + send button (visible when not connecting)
-                if self.livekit_connected {
-                    ui.horizontal(|ui| {
-                        ui.label("Message:");
-                        ui.text_edit_singleline(&mut self.livekit_message);
-                        if ui.button("Send").clicked() {
-                            // locally record the outgoing message
-                            {
-                                let mut guard = self.livekit_events.lock().unwrap();
-                                guard.push(format!("You: {}", self.livekit_message));
-                            }
-                            self.send_livekit_message(self.livekit_message.clone());
-                            self.livekit_message.clear();
-                        }
-                    });
-                } else {
-                    ui.label("Connect to a room to send and see participants messages.");
+            if response.is_pointer_button_down_on() {
+                if let Some(pos) = response.interact_pointer_pos() {
+                    // Center the editor on the row under the pointer.
+                    let row = (pos.y - rect.min.y) * height / rect.height();
+                    let half = self.minimap.viewport.map_or(0.0, |viewport| viewport.height() / 2.0);
+                    self.minimap.jump = Some((row - half).max(0.0));
                 }
-            });
+            }
         });
     }
 
@@ -212,47 +1142,121 @@ impl AppView {
                 ));
             }
 
-            let texture = self.whiteboard.texture.as_mut().unwrap();
-
-            // Display the image
-            // We want to handle clicks on the image.
-            // Using a sense of drag triggers response on drag
-            let image_response = ui.add(egui::Image::new(&*texture).sense(egui::Sense::drag()));
-
-            // Handle drawing
-            if image_response.hovered() || image_response.dragged() {
-                 if let Some(pointer_pos) = ui.input(|i| i.pointer.interact_pos()) {
-                     let rect = image_response.rect;
-                     if rect.contains(pointer_pos) {
-                         let rel_pos = pointer_pos - rect.min;
-                         let width = self.whiteboard.image.width();
-                         let height = self.whiteboard.image.height();
-                         let x = ((rel_pos.x / rect.width()) * width as f32) as i32;
-                         let y = ((rel_pos.y / rect.height()) * height as f32) as i32;
-                         
-                         // Broadcast cursor if time passed
-                         if self.livekit_connected && self.last_cursor_update.elapsed() > std::time::Duration::from_millis(50) {
-                             if let Some(sender) = &self.livekit_command_sender {
-                                 let _ = sender.send(AppCommand::Broadcast(NetworkMessage::Cursor { x, y }));
-                                 self.last_cursor_update = std::time::Instant::now();
-                             }
+            if self.whiteboard.color_by_author {
+                let mut authors: Vec<&String> = self.whiteboard.authors.values().collect();
+                authors.sort();
+                authors.dedup();
+                ui.horizontal(|ui| {
+                    ui.label("Authors:");
+                    for author in authors {
+                        let (rect, _) = ui.allocate_exact_size(egui::vec2(12.0, 12.0), egui::Sense::hover());
+                        ui.painter().circle_filled(rect.center(), 5.0, crate::ui::get_user_color(author));
+                        ui.label(author);
+                    }
+                });
+            }
+
+            // The canvas fills the width; a window too short for it scrolls.
+            let width = self.whiteboard.image.width() as f32;
+            let mut scroll = egui::ScrollArea::vertical().id_salt("canvas").auto_shrink(false)
+                // Dragging on the canvas draws instead of scrolling.
+                .scroll_source(egui::scroll_area::ScrollSource { drag: false, ..egui::scroll_area::ScrollSource::ALL });
+            if let Some(top) = self.minimap.jump.take() {
+                scroll = scroll.vertical_scroll_offset(top * self.minimap.scale);
+            }
+            let output = scroll.show(ui, |ui| self.canvas(ui));
+            // Points per canvas pixel, to express the visible part in canvas pixels.
+            let scale = output.content_size.x / width;
+            if scale > 0.0 {
+                self.minimap.scale = scale;
+                self.minimap.viewport = Some(egui::Rect::from_min_size(
+                    (output.state.offset / scale).to_pos2(),
+                    output.inner_rect.size() / scale,
+                ));
+            }
+        });
+    }
+
+    /// Draws the whiteboard texture with the overlays (labels, selection, comments,
+    /// peers) and turns pointer input on it into edits.
+    fn canvas(&mut self, ui: &mut egui::Ui) {
+        let texture = self.whiteboard.texture.as_mut().unwrap();
+
+        // Display the image
+        // We want to handle clicks on the image.
+        // Using a sense of drag triggers response on drag
+        let image_response = ui.add(egui::Image::new(&*texture).sense(egui::Sense::drag()));
+
+        // Handle drawing
+        if image_response.hovered() || image_response.dragged() {
+             if let Some(pointer_pos) = ui.input(|i| i.pointer.interact_pos()) {
+                 let rect = image_response.rect;
+                 if rect.contains(pointer_pos) {
+                     let rel_pos = pointer_pos - rect.min;
+                     let width = self.whiteboard.image.width();
+                     let height = self.whiteboard.image.height();
+                     let x = ((rel_pos.x / rect.width()) * width as f32) as i32;
+                     let y = ((rel_pos.y / rect.height()) * height as f32) as i32;
+                     
+                     // Broadcast cursor if time passed
+                     if self.room_status == crate::ui::RoomStatus::Connected && self.last_cursor_update.elapsed() > std::time::Duration::from_millis(50) {
+                         let user = crate::backend_api::UserInfo {
+                             name: self.livekit_identity.clone(),
+                             color: Some(crate::ui::get_user_color(&self.livekit_identity).to_array()),
+                         };
+                         self.backend.set_local_presence(Some(crate::backend_api::Point { x, y }), self.whiteboard.selection.clone(), user);
+                         if let Some(sender) = &self.livekit_command_sender {
+                             let _ = sender.send(AppCommand::SetPresence(self.backend.local_presence()));
+                             self.last_cursor_update = std::time::Instant::now();
                          }
                      }
                  }
-            }
+             }
+        }
+
+        let mut erase_at = None;
+        let mut label_at = None;
+        // Neither a past version previewed from the history panel nor a read-only document is editable.
+        let editable = self.history.is_none() && !self.backend.is_read_only();
+        if (image_response.dragged() || image_response.clicked()) && editable {
+            if let Some(pointer_pos) = ui.input(|i| i.pointer.interact_pos()) {
+                let rect = image_response.rect;
+                if rect.contains(pointer_pos) {
+                    let rel_pos = pointer_pos - rect.min;
+                    let width = self.whiteboard.image.width();
+                    let height = self.whiteboard.image.height();
 
-            if image_response.dragged() || image_response.clicked() {
-                if let Some(pointer_pos) = ui.input(|i| i.pointer.interact_pos()) {
-                    let rect = image_response.rect;
-                    if rect.contains(pointer_pos) {
-                        let rel_pos = pointer_pos - rect.min;
-                        let width = self.whiteboard.image.width();
-                        let height = self.whiteboard.image.height();
+                    // Map scaled image coordinates to actual pixel coordinates
+                    let x = ((rel_pos.x / rect.width()) * width as f32) as i32;
+                    let y = ((rel_pos.y / rect.height()) * height as f32) as i32;
 
-                        // Map scaled image coordinates to actual pixel coordinates
-                        let x = ((rel_pos.x / rect.width()) * width as f32) as i32;
-                        let y = ((rel_pos.y / rect.height()) * height as f32) as i32;
-                        
+                    if self.whiteboard.tool == Tool::StrokeEraser {
+                        erase_at = Some(crate::backend_api::Point { x, y });
+                    } else if self.whiteboard.tool == Tool::Select {
+                        let point = crate::backend_api::Point { x, y };
+                        if image_response.drag_started() || image_response.clicked() {
+                            // A click clears the selection; a drag starts a new one.
+                            self.whiteboard.selection = image_response.dragged().then(|| {
+                                crate::backend_api::Selection { start: point.clone(), end: point.clone() }
+                            });
+                        } else if let Some(selection) = &mut self.whiteboard.selection {
+                            selection.end = point;
+                        }
+                    } else if self.whiteboard.tool == Tool::Text {
+                        if image_response.clicked() {
+                            label_at = Some(crate::backend_api::Point { x, y });
+                        }
+                    } else if self.whiteboard.tool.dragged_shape().is_some() {
+                        // Only the first and the latest point matter; the outline is previewed below.
+                        // A click without dragging spans no area.
+                        if image_response.dragged() {
+                            if image_response.drag_started() {
+                                self.whiteboard.current_stroke.clear();
+                            }
+                            self.whiteboard.current_stroke.truncate(1);
+                            self.whiteboard.current_stroke.push(crate::backend_api::Point { x, y });
+                        }
+                    } else {
                         // Add point to current stroke
                         self.whiteboard.current_stroke.push(crate::backend_api::Point { x, y });
 
@@ -288,66 +1292,734 @@ impl AppView {
                     }
                 }
             }
-            
-            if image_response.drag_stopped() {
-                 if !self.whiteboard.current_stroke.is_empty() {
-                    let color = if self.whiteboard.tool == Tool::Eraser {
-                        egui::Color32::WHITE
-                    } else {
-                        self.whiteboard.stroke_color
-                    };
+        }
+        
+        if let Some(point) = erase_at {
+            let hit = crate::backend_api::stroke_at(&self.whiteboard.strokes, &point, self.whiteboard.stroke_width)
+                .map(|stroke| stroke.id.clone());
+            if let Some(id) = hit {
+                self.handle_intent(crate::backend_api::Intent::EraseStroke(id));
+            }
+        }
 
-                    let stroke = crate::backend_api::Stroke {
-                        points: self.whiteboard.current_stroke.clone(),
-                        color: color.to_array(),
+        if let Some(point) = label_at {
+            if self.whiteboard.text_label.trim().is_empty() {
+                self.status = "Type the label text first".into();
+            } else {
+                self.handle_intent(crate::backend_api::Intent::DrawShape {
+                    shape: crate::backend_api::Shape::Text(self.whiteboard.text_label.clone()),
+                    start: point.clone(),
+                    end: point,
+                    color: self.whiteboard.stroke_color.to_array(),
+                    width: self.whiteboard.stroke_width * 4.0,
+                });
+            }
+        }
+
+        if image_response.drag_stopped() {
+             if let Some(shape) = self.whiteboard.tool.dragged_shape() {
+                let corners = (self.whiteboard.current_stroke.first(), self.whiteboard.current_stroke.last());
+                if let (Some(start), Some(end)) = corners {
+                    let intent = crate::backend_api::Intent::DrawShape {
+                        shape,
+                        start: start.clone(),
+                        end: end.clone(),
+                        color: self.whiteboard.stroke_color.to_array(),
                         width: self.whiteboard.stroke_width,
                     };
-                    self.handle_intent(crate::backend_api::Intent::Draw(stroke));
-                    self.whiteboard.current_stroke.clear();
-                 }
+                    self.handle_intent(intent);
+                }
+                self.whiteboard.current_stroke.clear();
+             } else if !self.whiteboard.current_stroke.is_empty() {
+                let color = if self.whiteboard.tool == Tool::Eraser {
+                    egui::Color32::WHITE
+                } else {
+                    self.whiteboard.stroke_color
+                };
+
+                let stroke = crate::backend_api::Stroke {
+                    points: self.whiteboard.current_stroke.clone(),
+                    color: color.to_array(),
+                    width: self.whiteboard.stroke_width,
+                    id: String::new(),
+                    shape: None,
+                };
+                self.handle_intent(crate::backend_api::Intent::Draw(stroke));
+                self.whiteboard.current_stroke.clear();
+             }
+        }
+
+        // Render remote cursors
+        let painter = ui.painter();
+        let rect = image_response.rect;
+        let width = self.whiteboard.image.width() as f32;
+        let height = self.whiteboard.image.height() as f32;
+        
+        let to_screen = |point: &crate::backend_api::Point| {
+            let rx = (point.x as f32 / width) * rect.width();
+            let ry = (point.y as f32 / height) * rect.height();
+            rect.min + egui::Vec2::new(rx, ry)
+        };
+        let scale = rect.height() / height;
+
+        for stroke in &self.whiteboard.strokes {
+            if let (Some(crate::backend_api::Shape::Text(text)), Some(origin)) = (&stroke.shape, stroke.points.first()) {
+                let [r, g, b, a] = stroke.color;
+                let color = self
+                    .author_color(stroke)
+                    .unwrap_or_else(|| egui::Color32::from_rgba_premultiplied(r, g, b, a));
+                let family = match self.settings.label_font {
+                    crate::settings::LabelFont::Proportional => egui::FontFamily::Proportional,
+                    crate::settings::LabelFont::Monospace => egui::FontFamily::Monospace,
+                };
+                painter.text(to_screen(origin), egui::Align2::LEFT_TOP, text, egui::FontId::new(stroke.width * scale, family), color);
             }
+        }
 
-            // Render remote cursors
-            let painter = ui.painter();
-            let rect = image_response.rect;
-            let width = self.whiteboard.image.width() as f32;
-            let height = self.whiteboard.image.height() as f32;
-            
-            for (user, point) in &self.remote_cursors {
-                let rx = (point.x as f32 / width) * rect.width();
-                let ry = (point.y as f32 / height) * rect.height();
-                let pos = rect.min + egui::Vec2::new(rx, ry);
-                
-                let color = crate::ui::get_user_color(user);
-                painter.circle_filled(pos, 5.0, color);
-                painter.text(pos + egui::Vec2::new(8.0, 8.0), egui::Align2::LEFT_TOP, user, egui::FontId::proportional(12.0), color);
+        if let Some(selection) = &self.whiteboard.selection {
+            let area = egui::Rect::from_two_pos(to_screen(&selection.start), to_screen(&selection.end));
+            painter.rect_stroke(area, 0.0, egui::Stroke::new(1.0, egui::Color32::DARK_GRAY), egui::StrokeKind::Inside);
+        }
+
+        // Areas of open comment threads
+        let comment_color = egui::Color32::from_rgb(230, 160, 0);
+        for comment in self.comments.iter().filter(|c| c.reply_to.is_none() && !c.resolved) {
+            let area = egui::Rect::from_two_pos(to_screen(&comment.start), to_screen(&comment.end));
+            if self.comments_panel.focused.as_ref() == Some(&comment.id) {
+                painter.rect_filled(area, 0.0, comment_color.gamma_multiply(0.2));
+                painter.rect_stroke(area, 0.0, egui::Stroke::new(2.5, comment_color), egui::StrokeKind::Inside);
+            } else {
+                painter.rect_stroke(area, 0.0, egui::Stroke::new(1.0, comment_color), egui::StrokeKind::Inside);
+            }
+            painter.text(area.left_top(), egui::Align2::RIGHT_BOTTOM, "💬", egui::FontId::proportional(14.0), comment_color);
+        }
+
+        // Preview of the shape being dragged
+        if let (Some(shape), Some(start), Some(end)) = (
+            self.whiteboard.tool.dragged_shape(),
+            self.whiteboard.current_stroke.first(),
+            self.whiteboard.current_stroke.last(),
+        ) {
+            let preview = crate::backend_api::Stroke {
+                points: vec![start.clone(), end.clone()],
+                color: self.whiteboard.stroke_color.to_array(),
+                width: self.whiteboard.stroke_width,
+                id: String::new(),
+                shape: Some(shape),
+            };
+            let outline: Vec<egui::Pos2> = preview.path().iter().map(to_screen).collect();
+            painter.add(egui::Shape::line(outline, egui::Stroke::new(preview.width * 2.0 * scale, self.whiteboard.stroke_color)));
+        }
+
+        for peer in self.backend.peers() {
+            let color = peer_color(&peer);
+
+            if let Some(selection) = &peer.presence.selection {
+                let area = egui::Rect::from_two_pos(to_screen(&selection.start), to_screen(&selection.end));
+                painter.rect_filled(area, 0.0, color.gamma_multiply(0.15));
+                painter.rect_stroke(area, 0.0, egui::Stroke::new(1.5, color), egui::StrokeKind::Inside);
+            }
+
+            let Some(point) = &peer.presence.cursor else { continue };
+            let pos = to_screen(point);
+            painter.circle_filled(pos, 5.0, color);
+            // Pulsing ring after the peer's avatar was clicked
+            if let Some((_, pinged_at)) = self.pinged_peer.as_ref().filter(|(id, _)| *id == peer.peer_id) {
+                let elapsed = pinged_at.elapsed().as_secs_f32();
+                if elapsed < PING_SECONDS {
+                    let radius = 8.0 + 24.0 * (elapsed * 2.0).fract();
+                    painter.circle_stroke(pos, radius, egui::Stroke::new(2.0, color));
+                    ui.ctx().request_repaint();
+                }
+            }
+            painter.text(pos + egui::Vec2::new(8.0, 8.0), egui::Align2::LEFT_TOP, peer.display_name(), egui::FontId::proportional(12.0), color);
+        }
+    }
+
+    /// Directories and openable files under `dir`; clicking a file opens it in a tab.
+    fn workspace_tree(&mut self, ui: &mut egui::Ui, dir: &std::path::Path) {
+        for entry in self.workspace_listing(dir) {
+            if entry.is_dir {
+                egui::CollapsingHeader::new(format!("📁 {}", entry.name()))
+                    .id_salt(&entry.path)
+                    .show(ui, |ui| self.workspace_tree(ui, &entry.path));
+            } else {
+                let open = self.doc_file.as_ref().is_some_and(|file| file.path() == entry.path);
+                if ui.selectable_label(open, format!("🗋 {}", entry.name())).clicked() {
+                    self.open_in_tab(&entry.path);
+                }
+            }
+        }
+    }
+
+    /// Tabs of the open documents, with buttons to close them and open a new one.
+    pub fn tab_bar(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::top("tabs").show(ctx, |ui| {
+            ui.add_enabled_ui(!self.in_room(), |ui| {
+                ui.horizontal(|ui| {
+                    let mut switch = None;
+                    let mut close = None;
+                    for (index, (name, dirty)) in self.tab_labels().into_iter().enumerate() {
+                        let label = if dirty { format!("● {}", name) } else { name };
+                        if ui.selectable_label(index == self.active_tab, label).clicked() {
+                            switch = Some(index);
+                        }
+                        if ui.small_button("✖").on_hover_text("Close tab").clicked() {
+                            close = Some(index);
+                        }
+                        ui.separator();
+                    }
+                    if ui.button("➕").on_hover_text("New tab").clicked() {
+                        self.new_tab();
+                    }
+                    if let Some(index) = switch {
+                        self.switch_tab(index);
+                    }
+                    if let Some(index) = close {
+                        self.close_tab(index);
+                    }
+                })
+            })
+            .response
+            .on_disabled_hover_text("Disconnect first: peers sync with the document shown");
+        });
+    }
+
+    /// Timeline of the document's changes, shown while a past version is previewed.
+    pub fn history_panel(&mut self, ctx: &egui::Context) {
+        let Some(history) = &self.history else {
+            return;
+        };
+        let last = history.entries.len() - 1;
+        let mut index = history.index;
+        let entry = &history.entries[index];
+        let description = format!(
+            "{} by {}, {}",
+            entry.message.as_deref().unwrap_or("Change"),
+            entry.actor,
+            crate::ui::format_modified_at(entry.timestamp)
+        );
+        let mut restore = false;
+        let mut close = false;
+        egui::TopBottomPanel::top("history").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("History:");
+                ui.add(egui::Slider::new(&mut index, 0..=last).custom_formatter(|n, _| format!("{}/{}", n as usize + 1, last + 1)));
+                ui.label(description);
+                if ui
+                    .add_enabled(index != last, egui::Button::new("Restore this version"))
+                    .on_hover_text("Erases and redraws strokes on this page to match the version shown")
+                    .clicked()
+                {
+                    restore = true;
+                }
+                if ui.button("Close").clicked() {
+                    close = true;
+                }
+            });
+        });
+        if index != history.index {
+            self.preview_version(index);
+        }
+        if restore {
+            self.restore_version();
+        } else if close {
+            self.close_history();
+        }
+    }
+
+    /// Stacks the notifications of `notify` in the bottom right corner until
+    /// they expire after `TOAST_SECONDS` or are clicked away.
+    pub fn toasts(&mut self, ctx: &egui::Context) {
+        self.toasts.retain(|toast| toast.shown.elapsed().as_secs_f32() < TOAST_SECONDS);
+        let Some(oldest) = self.toasts.iter().map(|toast| toast.shown).min() else {
+            return;
+        };
+        ctx.request_repaint_after(std::time::Duration::from_secs_f32(TOAST_SECONDS).saturating_sub(oldest.elapsed()));
+
+        let mut dismissed = None;
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-12.0, -36.0))
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                for (i, toast) in self.toasts.iter().enumerate().rev() {
+                    let color = match toast.level {
+                        crate::ui::Level::Info => ui.visuals().text_color(),
+                        crate::ui::Level::Warning => ui.visuals().warn_fg_color,
+                        crate::ui::Level::Error => ui.visuals().error_fg_color,
+                    };
+                    let response = egui::Frame::popup(ui.style())
+                        .show(ui, |ui| {
+                            ui.set_max_width(320.0);
+                            ui.colored_label(color, &toast.text);
+                        })
+                        .response
+                        .interact(egui::Sense::click())
+                        .on_hover_text("Click to dismiss");
+                    if response.clicked() {
+                        dismissed = Some(i);
+                    }
+                }
+            });
+        if let Some(i) = dismissed {
+            self.toasts.remove(i);
+        }
+    }
+
+    /// Window with the full log of room events, newest first.
+    pub fn event_log(&mut self, ctx: &egui::Context) {
+        let mut open = self.event_log_open;
+        egui::Window::new("Event log").open(&mut open).default_height(300.0).show(ctx, |ui| {
+            let events = self.livekit_events.lock().unwrap().clone();
+            if events.is_empty() {
+                ui.weak("Nothing happened yet.");
+            }
+            egui::ScrollArea::vertical().auto_shrink([false, true]).show(ui, |ui| {
+                for event in events.iter().rev() {
+                    ui.label(event);
+                }
+            });
+        });
+        self.event_log_open = open;
+    }
+
+    /// Window listing the server's active rooms, to join one or create a new one.
+    pub fn room_browser(&mut self, ctx: &egui::Context) {
+        let mut open = self.room_browser_open;
+        let mut join = None;
+        let mut refresh = None;
+        egui::Window::new("Rooms").id(egui::Id::new("room_browser")).open(&mut open).show(ctx, |ui| {
+            let busy = self.rooms_receiver.is_some();
+            ui.horizontal(|ui| {
+                if ui.add_enabled(!busy, egui::Button::new("⟳ Refresh")).clicked() {
+                    refresh = Some(None);
+                }
+                if busy {
+                    ui.spinner();
+                }
+            });
+            ui.separator();
+            match &self.rooms {
+                None => {
+                    ui.weak("Listing rooms…");
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(egui::Color32::RED, e.to_string());
+                }
+                Some(Ok(rooms)) if rooms.is_empty() => {
+                    ui.weak("No active rooms.");
+                }
+                Some(Ok(rooms)) => {
+                    let document_room = self
+                        .backend
+                        .document_id()
+                        .map(|id| crate::protocol::room_for_document(&id));
+                    egui::Grid::new("rooms").num_columns(3).striped(true).show(ui, |ui| {
+                        for room in rooms {
+                            let label = ui.label(&room.name);
+                            if document_room.as_deref() == Some(room.name.as_str()) {
+                                label.on_hover_text("The room of this document");
+                            }
+                            ui.label(match room.participants {
+                                1 => "1 participant".to_string(),
+                                n => format!("{} participants", n),
+                            });
+                            let here = self.in_room() && self.livekit_room == room.name;
+                            if ui.add_enabled(!here, egui::Button::new("Join")).clicked() {
+                                join = Some(room.name.clone());
+                            }
+                            ui.end_row();
+                        }
+                    });
+                }
+            }
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.new_room_name).hint_text("this document's room"));
+                if ui.add_enabled(!busy, egui::Button::new("Create")).on_hover_text("Create the room and join it").clicked() {
+                    refresh = Some(Some(self.new_room_name.trim().to_string()));
+                }
+            });
+        });
+        self.room_browser_open = open;
+        if let Some(name) = join {
+            self.join_room(name);
+            self.show_livekit();
+        }
+        match refresh {
+            Some(Some(name)) => {
+                let name = if name.is_empty() {
+                    crate::protocol::room_for_document(&self.ensure_document_id())
+                } else {
+                    name
+                };
+                self.new_room_name.clear();
+                self.refresh_rooms(ctx, Some(name));
+            }
+            Some(None) => self.refresh_rooms(ctx, None),
+            None => {}
+        }
+    }
+
+    /// Window with the word count, reading time and contributions of the current page.
+    pub fn statistics_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.statistics_open;
+        let title = format!("Statistics of page {}", self.backend.current_page() + 1);
+        egui::Window::new("Statistics").id(egui::Id::new("statistics")).open(&mut open).show(ctx, |ui| {
+            let statistics = &self.statistics;
+            ui.strong(title);
+            egui::Grid::new("document_statistics").show(ui, |ui| {
+                ui.label("Words");
+                ui.label(statistics.words.to_string());
+                ui.end_row();
+                ui.label("Characters");
+                ui.label(statistics.characters.to_string());
+                ui.end_row();
+                ui.label("Lines");
+                ui.label(statistics.lines.to_string());
+                ui.end_row();
+                ui.label("Text labels");
+                ui.label(statistics.labels.to_string());
+                ui.end_row();
+                ui.label("Strokes and shapes");
+                ui.label(statistics.strokes.to_string());
+                ui.end_row();
+                ui.label("Reading time");
+                ui.label(format!("{} min", statistics.reading_minutes()))
+                    .on_hover_text(format!("At {} words per minute", crate::statistics::WORDS_PER_MINUTE));
+                ui.end_row();
+            });
+            ui.separator();
+            ui.strong("Contributions");
+            if statistics.contributions.is_empty() {
+                ui.weak("Nothing drawn yet.");
+            }
+            for (author, share) in &statistics.contributions {
+                ui.horizontal(|ui| {
+                    let name = if author.is_empty() { "Unknown" } else { author.as_str() };
+                    let color = crate::ui::get_user_color(author);
+                    let (rect, _) = ui.allocate_exact_size(egui::vec2(12.0, 12.0), egui::Sense::hover());
+                    ui.painter().circle_filled(rect.center(), 4.0, color);
+                    ui.add(egui::ProgressBar::new(share / 100.0).desired_width(120.0).text(format!("{:.0}%", share)));
+                    ui.label(name);
+                });
             }
         });
+        self.statistics_open = open;
+    }
+
+    /// Resizable window for each screen share that is open, showing its
+    /// latest frame scaled to the window's width.
+    pub fn screen_share_windows(&mut self, ctx: &egui::Context) {
+        for share in &mut self.screen_shares {
+            if let Some(image) = share.frames.lock().unwrap().take() {
+                match &mut share.texture {
+                    Some(texture) => texture.set(image, egui::TextureOptions::LINEAR),
+                    None => share.texture = Some(ctx.load_texture(format!("screen-{}", share.peer), image, egui::TextureOptions::LINEAR)),
+                }
+            }
+            let mut open = share.open;
+            egui::Window::new(format!("🖥 {}", share.peer))
+                .id(egui::Id::new(("screen_share", &share.peer)))
+                .open(&mut open)
+                .resizable(true)
+                .default_width(480.0)
+                .show(ctx, |ui| match &share.texture {
+                    Some(texture) => {
+                        let size = texture.size_vec2();
+                        let width = ui.available_width().max(1.0);
+                        ui.image((texture.id(), egui::vec2(width, width * size.y / size.x.max(1.0))));
+                    }
+                    None => {
+                        ui.spinner();
+                    }
+                });
+            share.open = open;
+        }
+    }
+
+    /// Chat and event stream of the LiveKit room, docked next to the canvas.
+    /// Collapsed, it shows how many events arrived since it was last open.
+    pub fn chat_dock(&mut self, ctx: &egui::Context) {
+        let events = self.livekit_events.lock().unwrap().clone();
+        if !self.chat_dock.open {
+            let unread = events.len().saturating_sub(self.chat_dock.seen);
+            egui::SidePanel::right("chat").resizable(false).exact_width(44.0).show(ctx, |ui| {
+                let label = if unread > 0 { format!("🗨\n{}", unread) } else { "🗨".to_string() };
+                let button = egui::Button::new(egui::RichText::new(label).color(if unread > 0 {
+                    egui::Color32::ORANGE
+                } else {
+                    ui.visuals().text_color()
+                }));
+                if ui.add(button).on_hover_text(format!("Chat ({} unread)", unread)).clicked() {
+                    self.chat_dock.open = true;
+                }
+            });
+            return;
+        }
+        self.chat_dock.seen = events.len();
+        egui::SidePanel::right("chat").resizable(true).default_width(260.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Chat");
+                if ui.small_button("▶").on_hover_text("Collapse").clicked() {
+                    self.chat_dock.open = false;
+                }
+            });
+            let input_height = 40.0;
+            egui::ScrollArea::vertical()
+                .id_salt("chat_dock")
+                .max_height(ui.available_height() - input_height)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for event in &events {
+                        ui.label(event);
+                    }
+                });
+            ui.separator();
+            ui.add_enabled_ui(self.in_room(), |ui| {
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.livekit_message)
+                        .hint_text("Message")
+                        .desired_width(f32::INFINITY),
+                );
+                if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) && !self.livekit_message.trim().is_empty() {
+                    let message = std::mem::take(&mut self.livekit_message);
+                    self.send_livekit_message(message);
+                    response.request_focus();
+                }
+            })
+            .response
+            .on_disabled_hover_text("Connect to a room to chat");
+        });
+    }
+
+    /// Lists the comment threads of the document, with fields to comment on
+    /// the selected area, reply and resolve.
+    pub fn comments_panel(&mut self, ctx: &egui::Context) {
+        if !self.comments_panel.visible {
+            return;
+        }
+        let comments = self.comments.clone();
+        let mut intents = Vec::new();
+        egui::SidePanel::right("comments").resizable(true).default_width(260.0).show(ctx, |ui| {
+            ui.heading("Comments");
+            ui.add(
+                egui::TextEdit::multiline(&mut self.comments_panel.draft)
+                    .hint_text("Comment on the selected area")
+                    .desired_rows(2),
+            );
+            let can_add = self.whiteboard.selection.is_some() && !self.comments_panel.draft.trim().is_empty();
+            if ui
+                .add_enabled(can_add, egui::Button::new("Add comment"))
+                .on_disabled_hover_text("Select an area with the select tool and write a comment")
+                .clicked()
+            {
+                if let Some(selection) = &self.whiteboard.selection {
+                    intents.push(Intent::AddComment {
+                        start: selection.start.clone(),
+                        end: selection.end.clone(),
+                        text: std::mem::take(&mut self.comments_panel.draft).trim().to_string(),
+                    });
+                }
+            }
+            ui.checkbox(&mut self.comments_panel.show_resolved, "Show resolved");
+            ui.separator();
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                let threads = comments
+                    .iter()
+                    .filter(|c| c.reply_to.is_none() && (self.comments_panel.show_resolved || !c.resolved));
+                for comment in threads {
+                    ui.group(|ui| {
+                        ui.set_width(ui.available_width());
+                        let focused = self.comments_panel.focused.as_ref() == Some(&comment.id);
+                        let header = ui.selectable_label(focused, egui::RichText::new(&comment.author).strong())
+                            .on_hover_text("Highlight the commented area");
+                        if header.clicked() {
+                            self.comments_panel.focused = (!focused).then(|| comment.id.clone());
+                        }
+                        ui.weak(crate::ui::format_modified_at(comment.created_at));
+                        ui.label(&comment.text);
+                        for reply in comments.iter().filter(|c| c.reply_to.as_ref() == Some(&comment.id)) {
+                            ui.indent(&reply.id, |ui| {
+                                ui.horizontal_wrapped(|ui| {
+                                    ui.strong(&reply.author);
+                                    ui.weak(crate::ui::format_modified_at(reply.created_at));
+                                });
+                                ui.label(&reply.text);
+                            });
+                        }
+                        if comment.resolved {
+                            ui.weak("✔ Resolved");
+                            return;
+                        }
+                        ui.horizontal(|ui| {
+                            let draft = self.comments_panel.reply_drafts.entry(comment.id.clone()).or_default();
+                            let response = ui.add(egui::TextEdit::singleline(draft).hint_text("Reply").desired_width(150.0));
+                            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) && !draft.trim().is_empty() {
+                                let text = std::mem::take(draft).trim().to_string();
+                                intents.push(Intent::ReplyComment { id: comment.id.clone(), text });
+                            }
+                            if ui.button("Resolve").clicked() {
+                                intents.push(Intent::ResolveComment { id: comment.id.clone() });
+                            }
+                        });
+                    });
+                }
+            });
+        });
+        for intent in intents {
+            self.handle_intent(intent);
+        }
+    }
+
+    /// Offers to restore the recovery snapshot of unsaved work left by a previous session.
+    pub fn recovery_banner(&mut self, ctx: &egui::Context) {
+        let Some(written) = self.recovery_offer.as_ref().map(|(_, written)| *written) else {
+            return;
+        };
+        let written_ms = written
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        egui::TopBottomPanel::top("recovery").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.colored_label(
+                    egui::Color32::ORANGE,
+                    format!("⚠ Unsaved work from a previous session ({}) can be restored.", crate::ui::format_modified_at(written_ms)),
+                );
+                if ui
+                    .button("Restore")
+                    .on_hover_text("If it was encrypted, enter its passphrase in the sidebar first")
+                    .clicked()
+                {
+                    self.restore_recovery();
+                }
+                if ui.button("Discard").clicked() {
+                    self.discard_recovery();
+                }
+            });
+        });
     }
 
     /// Renders the bottom status bar displaying connection status and participants.
     pub fn status_bar(&mut self, ctx: &egui::Context) {
+        self.refresh_stats();
         egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.label(&self.status);
+                ui.separator();
+                match self.backend.local_presence().cursor {
+                    Some(point) => ui.label(format!("{}, {}", point.x, point.y)),
+                    None => ui.weak("–, –"),
+                }
+                .on_hover_text("Pointer position on the canvas");
+                if let Some(selection) = &self.whiteboard.selection {
+                    ui.label(format!(
+                        "{} × {} selected",
+                        (selection.end.x - selection.start.x).abs(),
+                        (selection.end.y - selection.start.y).abs()
+                    ));
+                }
+                ui.separator();
+                ui.label(format!(
+                    "Page {}/{} · {} strokes · {} ops",
+                    self.backend.current_page() + 1,
+                    self.backend.page_count(),
+                    self.whiteboard.strokes.len(),
+                    self.stats.op_count
+                ));
+                let statistics = &self.statistics;
+                let words = ui.add(egui::Button::new(format!("{} words", statistics.words)).frame(false)).on_hover_text(format!(
+                    "{} characters · {} lines · {} min read\nClick for the document statistics",
+                    statistics.characters,
+                    statistics.lines,
+                    statistics.reading_minutes()
+                ));
+                if words.clicked() {
+                    self.statistics_open = !self.statistics_open;
+                }
+                ui.separator();
+                ui.label(format!("{} peers", self.stats.peer_count));
+                if self.stats.pending_sync_bytes > 0 {
+                    ui.label(format!("⟳ {:.1} KB pending", self.stats.pending_sync_bytes as f64 / 1024.0))
+                        .on_hover_text("Changes peers are not yet known to have");
+                } else if self.stats.peer_count > 0 {
+                    ui.label("✔ Synced");
+                }
+                ui.separator();
+                ui.label(&self.backend_name).on_hover_text("Document backend");
+                ui.separator();
+                if self.backend.can_undo() {
+                    ui.label(format!("↶ {} to undo", self.action_shortcut(ctx, crate::keymap::Action::Undo)));
+                } else {
+                    ui.weak("Nothing to undo");
+                }
                 
-                if self.livekit_connected {
+                if self.in_room() {
                     ui.separator();
                     ui.label("Participants:");
                     
-                    let participants = {
-                        let guard = self.livekit_participants.lock().unwrap();
-                        guard.clone()
-                    };
-
-                    for p in participants {
+                    for p in self.participant_names() {
                         let color = crate::ui::get_user_color(&p);
                         let (rect, _) = ui.allocate_exact_size(egui::vec2(12.0, 12.0), egui::Sense::hover());
                         ui.painter().circle_filled(rect.center(), 4.0, color);
-                        ui.label(&p);
+                        let label = ui.label(&p);
+                        if let Some(badge) = self.role_badge(&p) {
+                            label.on_hover_text(badge);
+                        }
                     }
                 }
             });
         });
     }
 }
+
+/// Explains the room passphrase next to its field.
+const ROOM_PASSPHRASE_HINT: &str = "Encrypts edits and chat end to end, so the server cannot read them; \
+everyone in the room needs the same passphrase. Cursors and names are not encrypted.";
+
+/// How long a peer's pointer is highlighted after clicking their avatar.
+const PING_SECONDS: f32 = 2.0;
+
+/// Width of the page drawn in the minimap, in points.
+const MINIMAP_WIDTH: f32 = 160.0;
+
+/// How long a notification toast stays on screen.
+const TOAST_SECONDS: f32 = 5.0;
+
+/// Color a peer is shown in: the one they chose, or one derived from their peer id.
+fn peer_color(peer: &crate::backend_api::PeerPresence) -> egui::Color32 {
+    match peer.presence.user.color {
+        Some([r, g, b, a]) => egui::Color32::from_rgba_unmultiplied(r, g, b, a),
+        None => crate::ui::get_user_color(&peer.peer_id),
+    }
+}
+
+/// Up to two initials of a name ("Ada Lovelace" → "AL"), or "?" for an empty one.
+fn initials(name: &str) -> String {
+    let initials: String = name
+        .split(|c: char| c.is_whitespace() || c == '_' || c == '-' || c == '.')
+        .filter_map(|word| word.chars().next())
+        .take(2)
+        .flat_map(char::to_uppercase)
+        .collect();
+    if initials.is_empty() {
+        "?".to_string()
+    } else {
+        initials
+    }
+}
+
+/// Draws a round avatar with the initials of `name`.
+fn avatar(ui: &mut egui::Ui, name: &str, color: egui::Color32) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(22.0, 22.0), egui::Sense::click());
+    ui.painter().circle_filled(rect.center(), 11.0, color);
+    ui.painter().text(rect.center(), egui::Align2::CENTER_CENTER, initials(name), egui::FontId::proportional(10.0), egui::Color32::WHITE);
+    response
+}
+
+/// Describes a keyboard shortcut the way the current platform writes it (e.g. "Ctrl+Z" or "⌘Z").
+fn shortcut_text(ctx: &egui::Context, modifiers: egui::Modifiers, key: egui::Key) -> String {
+    ctx.format_shortcut(&egui::KeyboardShortcut::new(modifiers, key))
+}
@@ -0,0 +1,312 @@
+//! Voice chat beside the shared document.
+//!
+//! The default input device is read with `cpal` on a thread of its own, since
+//! its streams cannot move between threads. Its first channel is converted to
+//! 16-bit samples and fed, in 10 ms frames, to the LiveKit audio source of the
+//! track published in the room.
+//!
+//! Remote audio tracks are decoded to mono at the output device's sample rate
+//! and mixed, each at its participant's volume, by a `Speaker` playing through
+//! the default output device.
+use livekit::options::TrackPublishOptions;
+use livekit::prelude::*;
+use livekit::webrtc::audio_source::native::NativeAudioSource;
+use livekit::webrtc::audio_stream::native::NativeAudioStream;
+use livekit::webrtc::prelude::{AudioFrame, AudioSourceOptions, RtcAudioSource};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Milliseconds of audio LiveKit's audio source buffers before dropping samples.
+const QUEUE_MS: u32 = 100;
+
+/// Milliseconds of a participant's audio kept waiting for playback; older
+/// samples are dropped so a stalled output does not build up delay.
+const PLAYBACK_BUFFER_MS: u32 = 200;
+
+/// State of the local microphone in a room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MicrophoneState {
+    /// Not captured nor published.
+    #[default]
+    Off,
+    /// Published and heard by the room.
+    Live,
+    /// Published, but silenced.
+    Muted,
+}
+
+/// Capture of the default input device, running until dropped.
+pub struct Microphone {
+    /// Audio source the captured samples are fed to.
+    source: NativeAudioSource,
+    /// Closed on drop, which stops the capture thread.
+    _stop: std::sync::mpsc::Sender<()>,
+}
+
+impl Microphone {
+    /// Starts capturing the default input device. Must be called from within a
+    /// Tokio runtime, which runs the task feeding the audio source.
+    ///
+    /// # Errors
+    /// Returns why no input device could be opened.
+    pub fn start() -> Result<Self, String> {
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let (opened_tx, opened) = std::sync::mpsc::channel();
+        let (samples_tx, mut samples) = tokio::sync::mpsc::unbounded_channel::<Vec<i16>>();
+        std::thread::spawn(move || match open_input(samples_tx) {
+            Ok((stream, sample_rate)) => {
+                let _ = opened_tx.send(Ok(sample_rate));
+                // Returns once the `Microphone` is dropped; the stream goes with it.
+                let _ = stopped.recv();
+                drop(stream);
+            }
+            Err(e) => {
+                let _ = opened_tx.send(Err(e));
+            }
+        });
+        let sample_rate = opened.recv().map_err(|e| e.to_string())??;
+
+        let options = AudioSourceOptions { echo_cancellation: true, noise_suppression: true, auto_gain_control: true };
+        let source = NativeAudioSource::new(options, sample_rate, 1, QUEUE_MS);
+        let feed = source.clone();
+        tokio::spawn(async move {
+            let frame_len = (sample_rate / 100) as usize;
+            let mut pending = Vec::new();
+            while let Some(chunk) = samples.recv().await {
+                pending.extend(chunk);
+                while pending.len() >= frame_len {
+                    let data: Vec<i16> = pending.drain(..frame_len).collect();
+                    let frame = AudioFrame { data: data.into(), sample_rate, num_channels: 1, samples_per_channel: frame_len as u32 };
+                    if let Err(e) = feed.capture_frame(&frame).await {
+                        eprintln!("Dropped a microphone frame: {}", e);
+                    }
+                }
+            }
+        });
+        Ok(Self { source, _stop: stop })
+    }
+}
+
+/// Opens the default input device, sending its samples to `samples`; returns
+/// the running stream and its sample rate.
+fn open_input(samples: tokio::sync::mpsc::UnboundedSender<Vec<i16>>) -> Result<(cpal::Stream, u32), String> {
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    let device = cpal::default_host().default_input_device().ok_or("no microphone found")?;
+    let supported = device.default_input_config().map_err(|e| e.to_string())?;
+    let config: cpal::StreamConfig = supported.config();
+    let stream = match supported.sample_format() {
+        cpal::SampleFormat::F32 => input_stream::<f32>(&device, &config, samples),
+        cpal::SampleFormat::I16 => input_stream::<i16>(&device, &config, samples),
+        cpal::SampleFormat::U16 => input_stream::<u16>(&device, &config, samples),
+        format => return Err(format!("unsupported sample format {:?}", format)),
+    }
+    .map_err(|e| e.to_string())?;
+    stream.play().map_err(|e| e.to_string())?;
+    Ok((stream, config.sample_rate.0))
+}
+
+/// Input stream of `device` in samples of type `T`, keeping the first channel.
+fn input_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: tokio::sync::mpsc::UnboundedSender<Vec<i16>>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: cpal::SizedSample,
+    i16: cpal::FromSample<T>,
+{
+    use cpal::traits::DeviceTrait;
+    use cpal::Sample;
+    let channels = config.channels.max(1) as usize;
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            let mono = data.chunks(channels).map(|frame| frame[0].to_sample::<i16>()).collect();
+            let _ = samples.send(mono);
+        },
+        |e| eprintln!("Microphone error: {}", e),
+        None,
+    )
+}
+
+/// The microphone published in a room.
+pub struct PublishedMicrophone {
+    _microphone: Microphone,
+    publication: LocalTrackPublication,
+}
+
+/// Brings the microphone `published` in `room` to `state`: starts capturing
+/// and publishes it, mutes or unmutes it, or unpublishes it and stops the
+/// capture. Returns the state reached.
+///
+/// # Errors
+/// Returns why the microphone could not be opened or published; it is off then.
+pub async fn set_microphone(
+    room: &Room,
+    published: &mut Option<PublishedMicrophone>,
+    state: MicrophoneState,
+) -> Result<MicrophoneState, String> {
+    if state == MicrophoneState::Off {
+        if let Some(microphone) = published.take() {
+            room.local_participant().unpublish_track(&microphone.publication.sid()).await.map_err(|e| e.to_string())?;
+        }
+        return Ok(MicrophoneState::Off);
+    }
+    if published.is_none() {
+        let microphone = Microphone::start()?;
+        let track = LocalAudioTrack::create_audio_track("microphone", RtcAudioSource::Native(microphone.source.clone()));
+        let options = TrackPublishOptions { source: TrackSource::Microphone, ..Default::default() };
+        let publication = room
+            .local_participant()
+            .publish_track(LocalTrack::Audio(track), options)
+            .await
+            .map_err(|e| e.to_string())?;
+        *published = Some(PublishedMicrophone { _microphone: microphone, publication });
+    }
+    let publication = &published.as_ref().expect("the microphone was just published").publication;
+    if state == MicrophoneState::Muted {
+        publication.mute();
+    } else {
+        publication.unmute();
+    }
+    Ok(state)
+}
+
+/// Audio of each participant waiting to be played, and their volumes.
+#[derive(Default)]
+struct Mixer {
+    samples: HashMap<String, VecDeque<i16>>,
+    /// Volume of each participant, 1.0 when not set.
+    volumes: HashMap<String, f32>,
+}
+
+/// Playback of the room's remote audio tracks through the default output
+/// device, running until dropped.
+pub struct Speaker {
+    mixer: Arc<Mutex<Mixer>>,
+    sample_rate: u32,
+    /// Task decoding the track of each participant heard.
+    tracks: HashMap<String, tokio::task::JoinHandle<()>>,
+    /// Closed on drop, which stops the output thread.
+    _stop: std::sync::mpsc::Sender<()>,
+}
+
+impl Speaker {
+    /// Opens the default output device.
+    ///
+    /// # Errors
+    /// Returns why no output device could be opened.
+    pub fn start() -> Result<Self, String> {
+        let mixer = Arc::new(Mutex::new(Mixer::default()));
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let (opened_tx, opened) = std::sync::mpsc::channel();
+        let output_mixer = mixer.clone();
+        std::thread::spawn(move || match open_output(output_mixer) {
+            Ok((stream, sample_rate)) => {
+                let _ = opened_tx.send(Ok(sample_rate));
+                // Returns once the `Speaker` is dropped; the stream goes with it.
+                let _ = stopped.recv();
+                drop(stream);
+            }
+            Err(e) => {
+                let _ = opened_tx.send(Err(e));
+            }
+        });
+        let sample_rate = opened.recv().map_err(|e| e.to_string())??;
+        Ok(Self { mixer, sample_rate, tracks: HashMap::new(), _stop: stop })
+    }
+
+    /// Plays `track`, the audio `peer` publishes, in place of the one it played before.
+    /// Must be called from within a Tokio runtime, which runs the decoding task.
+    pub fn play(&mut self, peer: &str, track: RemoteAudioTrack) {
+        use futures_util::StreamExt;
+        self.stop(peer);
+        let mut stream = NativeAudioStream::new(track.rtc_track(), self.sample_rate as i32, 1);
+        let mixer = self.mixer.clone();
+        let id = peer.to_string();
+        let limit = (self.sample_rate * PLAYBACK_BUFFER_MS / 1000) as usize;
+        let task = tokio::spawn(async move {
+            while let Some(frame) = stream.next().await {
+                let mut guard = mixer.lock().unwrap();
+                let queue = guard.samples.entry(id.clone()).or_default();
+                queue.extend(frame.data.iter());
+                let excess = queue.len().saturating_sub(limit);
+                queue.drain(..excess);
+            }
+        });
+        self.tracks.insert(peer.to_string(), task);
+    }
+
+    /// Stops playing the audio of `peer`.
+    pub fn stop(&mut self, peer: &str) {
+        if let Some(task) = self.tracks.remove(peer) {
+            task.abort();
+        }
+        self.mixer.lock().unwrap().samples.remove(peer);
+    }
+
+    /// Sets the volume `peer` is played at, from 0.0 (silent) up; 1.0 is unchanged.
+    pub fn set_volume(&self, peer: &str, volume: f32) {
+        self.mixer.lock().unwrap().volumes.insert(peer.to_string(), volume);
+    }
+}
+
+impl Drop for Speaker {
+    fn drop(&mut self) {
+        for task in self.tracks.values() {
+            task.abort();
+        }
+    }
+}
+
+/// Opens the default output device, playing what `mixer` holds; returns the
+/// running stream and its sample rate.
+fn open_output(mixer: Arc<Mutex<Mixer>>) -> Result<(cpal::Stream, u32), String> {
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    let device = cpal::default_host().default_output_device().ok_or("no audio output found")?;
+    let supported = device.default_output_config().map_err(|e| e.to_string())?;
+    let config: cpal::StreamConfig = supported.config();
+    let stream = match supported.sample_format() {
+        cpal::SampleFormat::F32 => output_stream::<f32>(&device, &config, mixer),
+        cpal::SampleFormat::I16 => output_stream::<i16>(&device, &config, mixer),
+        cpal::SampleFormat::U16 => output_stream::<u16>(&device, &config, mixer),
+        format => return Err(format!("unsupported sample format {:?}", format)),
+    }
+    .map_err(|e| e.to_string())?;
+    stream.play().map_err(|e| e.to_string())?;
+    Ok((stream, config.sample_rate.0))
+}
+
+/// Output stream of `device` in samples of type `T`, playing the mix of the
+/// participants' audio on every channel.
+fn output_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mixer: Arc<Mutex<Mixer>>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    use cpal::traits::DeviceTrait;
+    use cpal::Sample;
+    let channels = config.channels.max(1) as usize;
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut guard = mixer.lock().unwrap();
+            let Mixer { samples, volumes } = &mut *guard;
+            for frame in data.chunks_mut(channels) {
+                let mut mixed = 0.0;
+                for (peer, queue) in samples.iter_mut() {
+                    if let Some(sample) = queue.pop_front() {
+                        mixed += sample as f32 / i16::MAX as f32 * volumes.get(peer).copied().unwrap_or(1.0);
+                    }
+                }
+                let value = T::from_sample(mixed.clamp(-1.0, 1.0));
+                frame.fill(value);
+            }
+        },
+        |e| eprintln!("Audio output error: {}", e),
+        None,
+    )
+}
@@ -0,0 +1,72 @@
+//! Workspace folder listing for the sidebar's file tree.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// File extensions the editor can open: document files and background images.
+pub const OPENABLE_EXTENSIONS: &[&str] = &["crdt", "png"];
+
+/// A directory or openable file inside a workspace folder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+impl DirEntry {
+    /// File or directory name, for display.
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// Lists the subdirectories and openable files of `dir`, directories first,
+/// each group sorted by name. Hidden entries (starting with `.`) are skipped.
+pub fn list_dir(dir: &Path) -> io::Result<Vec<DirEntry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let is_dir = entry.file_type()?.is_dir();
+        let openable = path
+            .extension()
+            .is_some_and(|ext| OPENABLE_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)));
+        if is_dir || openable {
+            entries.push(DirEntry { path, is_dir });
+        }
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.path.cmp(&b.path)));
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_dir_shows_directories_then_openable_files() {
+        let dir = std::env::temp_dir().join(format!("workspace-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sketches")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        for name in ["b.crdt", "a.PNG", "notes.txt", ".hidden.crdt"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let names: Vec<(String, bool)> = list_dir(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.name(), entry.is_dir))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("sketches".to_string(), true), ("a.PNG".to_string(), false), ("b.crdt".to_string(), false)]
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
+}